@@ -0,0 +1,66 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use my_crate::batch::BatchProjector;
+use my_crate::engine::ProjectionEngine;
+use my_crate::spending::{GrowthType, ProjectSpend, UserModel};
+
+fn sample_models(count: usize) -> Vec<UserModel> {
+    (0..count)
+        .map(|i| {
+            let mut user = UserModel::new(format!("user{}", i), 30).unwrap();
+            user.add_project(ProjectSpend::new("Housing".into(), 80.0, 0.03, GrowthType::Compound).unwrap());
+            user.add_project(ProjectSpend::new("Food".into(), 25.0, 0.02, GrowthType::Flat).unwrap());
+            user.add_project(ProjectSpend::new(
+                "Care".into(),
+                10.0 + (i % 7) as f64,
+                0.04,
+                GrowthType::Custom("base * (1.0 + rate * year) * 1.1".into()),
+            ).unwrap());
+            user
+        })
+        .collect()
+}
+
+fn bench_projections(c: &mut Criterion) {
+    let mut group = c.benchmark_group("projection");
+    group.sample_size(10);
+
+    for count in [100, 1000] {
+        let models = sample_models(count);
+        group.throughput(Throughput::Elements(count as u64));
+
+        group.bench_with_input(BenchmarkId::new("serial", count), &models, |b, models| {
+            b.iter(|| {
+                for model in models {
+                    model.calculate_total_spend().unwrap();
+                }
+            })
+        });
+
+        // Baseline: a fresh engine for every yearly spend, compiling each custom formula every time
+        group.bench_with_input(BenchmarkId::new("engine_per_call", count), &models, |b, models| {
+            b.iter(|| {
+                for model in models {
+                    for year in 0..model.projection_years {
+                        for project in &model.projects {
+                            ProjectionEngine::new().yearly_spend(project, year).unwrap();
+                        }
+                    }
+                }
+            })
+        });
+
+        let projector = BatchProjector::new(0).unwrap();
+        group.bench_with_input(BenchmarkId::new("batch", count), &models, |b, models| {
+            b.iter(|| projector.project_all(models))
+        });
+
+        group.bench_with_input(BenchmarkId::new("batch_stream", count), &models, |b, models| {
+            b.iter(|| projector.stream(models.clone()).into_iter().count())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_projections);
+criterion_main!(benches);
@@ -0,0 +1,122 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use crate::budget::{BreachKind, BreachScope, BudgetBreach};
+
+// A webhook that has not answered by then is treated as failed
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error)]
+pub enum AlertError {
+    #[error("Webhook request failed: {0}")]
+    Request(String),
+}
+
+#[derive(Debug, Serialize)]
+pub struct BudgetAlert<'a> {
+    pub user_id: &'a str,
+    pub breaches: &'a [BudgetBreach],
+}
+
+// Which breaches a user has; the spend behind them may move without changing it
+type BreachSet = Vec<(u32, BreachScope, BreachKind, u64)>;
+
+fn breach_set(breaches: &[BudgetBreach]) -> BreachSet {
+    breaches.iter()
+        .map(|breach| (breach.year, breach.scope.clone(), breach.kind, breach.threshold.to_bits()))
+        .collect()
+}
+
+// Posts budget breaches as JSON to a configured URL. Clones share the record of what each user
+// was last alerted about, so a user hears about a set of breaches once, not on every projection.
+#[derive(Debug, Clone)]
+pub struct WebhookNotifier {
+    url: String,
+    agent: ureq::Agent,
+    alerted: Arc<Mutex<HashMap<String, BreachSet>>>,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            agent: ureq::AgentBuilder::new().timeout(WEBHOOK_TIMEOUT).build(),
+            alerted: Arc::default(),
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    // Records the user's current breaches; true when they are new since the last alert and worth sending
+    pub fn should_notify(&self, user_id: &str, breaches: &[BudgetBreach]) -> bool {
+        let current = breach_set(breaches);
+        let previous = self.alerted.lock().unwrap().insert(user_id.to_string(), current.clone());
+        !current.is_empty() && previous.as_ref() != Some(&current)
+    }
+
+    pub fn notify(&self, user_id: &str, breaches: &[BudgetBreach]) -> Result<(), AlertError> {
+        let sent = self.agent.post(&self.url).send_json(BudgetAlert { user_id, breaches });
+        if let Err(e) = sent {
+            // Forget the alert so the next projection tries again
+            self.alerted.lock().unwrap().remove(user_id);
+            return Err(AlertError::Request(e.to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::spawn_http;
+
+    fn rent_breach(spend: f64) -> BudgetBreach {
+        BudgetBreach {
+            year: 2,
+            scope: BreachScope::Project("Rent".into()),
+            kind: BreachKind::CapExceeded,
+            spend,
+            cap: 1000.0,
+            threshold: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_webhook_posts_breaches() {
+        let (url, sink) = spawn_http("/alerts", "200 OK", "");
+        let breaches = vec![rent_breach(1200.0)];
+
+        WebhookNotifier::new(url).notify("user1", &breaches).unwrap();
+
+        let body: serde_json::Value = serde_json::from_str(&sink.join().unwrap().1).unwrap();
+        assert_eq!(body["user_id"], "user1");
+        assert_eq!(body["breaches"][0]["year"], 2);
+        assert_eq!(body["breaches"][0]["kind"], "cap_exceeded");
+        assert_eq!(body["breaches"][0]["scope"]["name"], "Rent");
+    }
+
+    #[test]
+    fn test_alerts_only_when_breaches_change() {
+        let notifier = WebhookNotifier::new("http://127.0.0.1:9/alerts".into());
+        let total = BudgetBreach { scope: BreachScope::Total, ..rent_breach(1500.0) };
+
+        assert!(!notifier.should_notify("user1", &[]));
+        assert!(notifier.should_notify("user1", &[rent_breach(1200.0)]));
+        // The same breach with a different spend is not news
+        assert!(!notifier.clone().should_notify("user1", &[rent_breach(1300.0)]));
+        assert!(notifier.should_notify("user1", &[rent_breach(1300.0), total.clone()]));
+        assert!(notifier.should_notify("user2", &[total]));
+
+        // Once back under budget, the next breach alerts again
+        assert!(!notifier.should_notify("user1", &[]));
+        assert!(notifier.should_notify("user1", &[rent_breach(1200.0)]));
+
+        // A failed delivery is retried on the next projection
+        assert!(notifier.notify("user1", &[rent_breach(1200.0)]).is_err());
+        assert!(notifier.should_notify("user1", &[rent_breach(1200.0)]));
+    }
+}
@@ -0,0 +1,287 @@
+use bitcoin::hashes::{sha256, Hash};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::RwLock;
+use utoipa::ToSchema;
+use crate::nostr::NostrPublicKey;
+
+// The prev_hash of the first entry
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ResourceKind {
+    User,
+    Project,
+    Budget,
+    Scenario,
+    Escrow,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct FieldChange {
+    // Dotted path of the field within the resource
+    pub path: String,
+    // Absent when the field did not exist before the change
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub before: Option<Value>,
+    // Absent when the change removed the field
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub after: Option<Value>,
+}
+
+// One link of the hash chain
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    // Hex pubkey of the NIP-98 signer; None when authentication is disabled
+    pub actor: Option<String>,
+    pub action: AuditAction,
+    pub resource: ResourceKind,
+    pub resource_id: String,
+    // Pubkeys whose records changed: the model's owner, or an escrow's parties
+    pub subjects: Vec<String>,
+    pub changes: Vec<FieldChange>,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    // sha256 over the JSON of every field but `hash`; object keys serialize sorted, so this is stable
+    pub fn compute_hash(&self) -> String {
+        let mut value = serde_json::to_value(self).expect("audit entries serialize to JSON");
+        if let Value::Object(fields) = &mut value {
+            fields.remove("hash");
+        }
+        sha256::Hash::hash(value.to_string().as_bytes()).to_string()
+    }
+}
+
+// A change to record, before it is stamped and chained
+pub struct Change {
+    action: AuditAction,
+    resource: ResourceKind,
+    resource_id: String,
+    subjects: Vec<String>,
+    changes: Vec<FieldChange>,
+}
+
+impl Change {
+    // The action follows from which sides exist: only `after` is a create, only `before` a delete
+    pub fn new<T: Serialize>(resource: ResourceKind, resource_id: &str, before: Option<&T>, after: Option<&T>) -> Self {
+        let action = match (before, after) {
+            (None, Some(_)) => AuditAction::Create,
+            (Some(_), None) => AuditAction::Delete,
+            _ => AuditAction::Update,
+        };
+        let to_value = |side: Option<&T>| side
+            .map(|value| serde_json::to_value(value).expect("audited resources serialize to JSON"))
+            .unwrap_or(Value::Null);
+
+        Self {
+            action,
+            resource,
+            resource_id: resource_id.to_string(),
+            subjects: Vec::new(),
+            changes: diff(&to_value(before), &to_value(after)),
+        }
+    }
+
+    pub fn subject(mut self, pubkey: impl Into<String>) -> Self {
+        self.subjects.push(pubkey.into());
+        self
+    }
+}
+
+// Field-level differences between two JSON values. Objects are compared key by key;
+// anything else, arrays included, is reported whole when it differs.
+pub fn diff(before: &Value, after: &Value) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    diff_into("", before, after, &mut changes);
+    changes
+}
+
+fn diff_into(path: &str, before: &Value, after: &Value, changes: &mut Vec<FieldChange>) {
+    let empty = serde_json::Map::new();
+    let (before_fields, after_fields) = match (before, after) {
+        (Value::Object(b), Value::Object(a)) => (b, a),
+        (Value::Object(b), Value::Null) => (b, &empty),
+        (Value::Null, Value::Object(a)) => (&empty, a),
+        _ => {
+            if before != after {
+                changes.push(FieldChange {
+                    path: path.to_string(),
+                    before: Some(before.clone()).filter(|v| !v.is_null()),
+                    after: Some(after.clone()).filter(|v| !v.is_null()),
+                });
+            }
+            return;
+        }
+    };
+
+    let mut keys: Vec<&String> = before_fields.keys().chain(after_fields.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    for key in keys {
+        let field_path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+        match (before_fields.get(key), after_fields.get(key)) {
+            (Some(b), Some(a)) => diff_into(&field_path, b, a, changes),
+            (b, a) => changes.push(FieldChange { path: field_path, before: b.cloned(), after: a.cloned() }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct AuditVerification {
+    pub valid: bool,
+    pub entries: usize,
+    // Hash of the last entry, or the genesis hash for an empty log
+    pub head: String,
+    // Sequence number of the first entry that fails to verify
+    pub first_invalid: Option<u64>,
+    pub reason: Option<String>,
+}
+
+// Checks that every entry hashes to its recorded hash and links to the one before it
+pub fn verify_chain(entries: &[AuditEntry]) -> AuditVerification {
+    let mut prev_hash = GENESIS_HASH;
+
+    for (index, entry) in entries.iter().enumerate() {
+        let problem = if entry.seq != index as u64 + 1 {
+            Some(format!("expected sequence number {}", index + 1))
+        } else if entry.prev_hash != prev_hash {
+            Some("prev_hash does not match the previous entry".to_string())
+        } else if entry.compute_hash() != entry.hash {
+            Some("contents do not match the recorded hash".to_string())
+        } else {
+            None
+        };
+
+        if let Some(reason) = problem {
+            return AuditVerification {
+                valid: false,
+                entries: entries.len(),
+                head: entries.last().map_or(GENESIS_HASH, |last| last.hash.as_str()).to_string(),
+                first_invalid: Some(entry.seq),
+                reason: Some(reason),
+            };
+        }
+        prev_hash = &entry.hash;
+    }
+
+    AuditVerification {
+        valid: true,
+        entries: entries.len(),
+        head: prev_hash.to_string(),
+        first_invalid: None,
+        reason: None,
+    }
+}
+
+// Append-only, hash-chained record of every change to stored models and escrows
+#[derive(Default)]
+pub struct AuditLog {
+    entries: RwLock<Vec<AuditEntry>>,
+}
+
+impl AuditLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, actor: Option<NostrPublicKey>, change: Change) -> AuditEntry {
+        let mut entries = self.entries.write().unwrap();
+        let prev_hash = entries.last().map_or(GENESIS_HASH, |last| last.hash.as_str()).to_string();
+
+        let mut entry = AuditEntry {
+            seq: entries.len() as u64 + 1,
+            timestamp: Utc::now(),
+            actor: actor.map(|pubkey| pubkey.to_hex()),
+            action: change.action,
+            resource: change.resource,
+            resource_id: change.resource_id,
+            subjects: change.subjects,
+            changes: change.changes,
+            prev_hash,
+            hash: String::new(),
+        };
+        entry.hash = entry.compute_hash();
+
+        entries.push(entry.clone());
+        entry
+    }
+
+    // Entries matching `filter`, oldest first
+    pub fn query(&self, filter: impl Fn(&AuditEntry) -> bool) -> Vec<AuditEntry> {
+        self.entries.read().unwrap().iter().filter(|entry| filter(entry)).cloned().collect()
+    }
+
+    pub fn verify(&self) -> AuditVerification {
+        verify_chain(&self.entries.read().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff_reports_changed_added_and_removed_fields() {
+        let before = json!({ "name": "Rent", "budget": { "yearly_cap": 100, "thresholds": [0.8] }, "old": 1 });
+        let after = json!({ "name": "Rent", "budget": { "yearly_cap": 200, "thresholds": [0.8, 0.9] }, "new": 2 });
+
+        let changes = diff(&before, &after);
+        let paths: Vec<(&str, bool, bool)> = changes.iter()
+            .map(|change| (change.path.as_str(), change.before.is_some(), change.after.is_some()))
+            .collect();
+        assert_eq!(paths, [
+            ("budget.thresholds", true, true),
+            ("budget.yearly_cap", true, true),
+            ("new", false, true),
+            ("old", true, false),
+        ]);
+    }
+
+    #[test]
+    fn test_chain_verifies_and_detects_tampering() {
+        let log = AuditLog::new();
+        let project = json!({ "project_name": "Rent", "daily_spend": 10.0 });
+        let created = log.record(None, Change::new(ResourceKind::Project, "Rent", None, Some(&project)).subject("alice"));
+        let deleted = log.record(None, Change::new(ResourceKind::Project, "Rent", Some(&project), None));
+
+        assert_eq!(created.action, AuditAction::Create);
+        assert_eq!(deleted.action, AuditAction::Delete);
+        assert_eq!(deleted.prev_hash, created.hash);
+        assert_eq!(log.verify(), AuditVerification {
+            valid: true,
+            entries: 2,
+            head: deleted.hash.clone(),
+            first_invalid: None,
+            reason: None,
+        });
+
+        let mut entries = log.query(|_| true);
+        entries[0].changes[0].after = Some(json!(5.0));
+        let verification = verify_chain(&entries);
+        assert!(!verification.valid);
+        assert_eq!(verification.first_invalid, Some(1));
+
+        // Re-hashing the edited entry still breaks the link from the next one
+        entries[0].hash = entries[0].compute_hash();
+        assert_eq!(verify_chain(&entries).first_invalid, Some(2));
+    }
+}
@@ -0,0 +1,308 @@
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::PayloadError;
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest, ResponseError};
+use base64::Engine;
+use bitcoin::hashes::{sha256, Hash};
+use serde::Deserialize;
+use std::future::{ready, Ready};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::error::ApiError;
+use crate::nostr::{NostrEvent, NostrPublicKey};
+
+// NIP-98 HTTP auth events are kind 27235
+pub const HTTP_AUTH_KIND: u32 = 27235;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub enabled: bool,
+    // How far, in seconds, an event's created_at may drift from the server clock
+    pub max_skew: i64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self { enabled: true, max_skew: 60 }
+    }
+}
+
+impl AuthConfig {
+    pub fn disabled() -> Self {
+        Self { enabled: false, ..Self::default() }
+    }
+}
+
+fn unauthorized(message: impl Into<String>) -> ApiError {
+    ApiError::new(StatusCode::UNAUTHORIZED, "unauthorized", message)
+}
+
+// Verifies the NIP-98 `Authorization: Nostr <base64 event>` header and records the signer.
+// The body is buffered so its hash can be checked, then handed back to the handler.
+pub async fn verify_nip98<B: MessageBody>(
+    mut req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    let config = req.app_data::<web::Data<AuthConfig>>()
+        .map(|config| config.get_ref().clone())
+        .unwrap_or_default();
+
+    if config.enabled {
+        let body = match req.extract::<web::Bytes>().await {
+            Ok(body) => body,
+            Err(e) => {
+                let e = match e.as_error::<PayloadError>() {
+                    Some(PayloadError::Overflow) => ApiError::from(PayloadError::Overflow),
+                    _ => ApiError::bad_request("invalid_payload", e.to_string()),
+                };
+                return Ok(req.into_response(e.error_response()).map_into_right_body());
+            }
+        };
+        match authenticate(req.request(), &body, &config) {
+            Ok(pubkey) => {
+                req.set_payload(Payload::from(body));
+                req.extensions_mut().insert(pubkey);
+            }
+            Err(e) => return Ok(req.into_response(e.error_response()).map_into_right_body()),
+        }
+    }
+
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+fn authenticate(req: &HttpRequest, body: &[u8], config: &AuthConfig) -> Result<NostrPublicKey, ApiError> {
+    let header = req.headers().get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| unauthorized("Missing Authorization header"))?;
+
+    let encoded = header.strip_prefix("Nostr ")
+        .ok_or_else(|| unauthorized("Authorization scheme must be Nostr"))?;
+
+    let decoded = base64::engine::general_purpose::STANDARD.decode(encoded.trim())
+        .map_err(|e| unauthorized(format!("Authorization event is not base64: {}", e)))?;
+
+    let event: NostrEvent = serde_json::from_slice(&decoded)
+        .map_err(|e| unauthorized(format!("Authorization event is not valid JSON: {}", e)))?;
+
+    if event.kind != HTTP_AUTH_KIND {
+        return Err(unauthorized(format!("Authorization event must be kind {}", HTTP_AUTH_KIND)));
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64);
+    if (now - event.created_at).abs() > config.max_skew {
+        return Err(unauthorized("Authorization event is outside the allowed time window"));
+    }
+
+    let info = req.connection_info();
+    let url = format!("{}://{}{}", info.scheme(), info.host(), req.uri());
+    if event.tag("u") != Some(url.as_str()) {
+        return Err(unauthorized(format!("Authorization event is not for {}", url)));
+    }
+
+    if !event.tag("method").is_some_and(|method| method.eq_ignore_ascii_case(req.method().as_str())) {
+        return Err(unauthorized(format!("Authorization event is not for method {}", req.method())));
+    }
+
+    if !body.is_empty() {
+        let payload_hash = sha256::Hash::hash(body).to_string();
+        if event.tag("payload") != Some(payload_hash.as_str()) {
+            return Err(unauthorized("Authorization event payload hash does not match the body"));
+        }
+    }
+
+    event.verify().map_err(|e| unauthorized(e.to_string()))
+}
+
+// The signer of the request, or None when authentication is disabled
+pub struct Nip98Auth(Option<NostrPublicKey>);
+
+impl Nip98Auth {
+    pub fn pubkey(&self) -> Option<NostrPublicKey> {
+        self.0
+    }
+
+    // Only the owner of a model may read or change it. Returns the id models are stored under:
+    // the hex pubkey when user_id is an npub or hex key, so both spellings reach the same model.
+    pub fn authorize(&self, user_id: &str) -> Result<String, ApiError> {
+        let owner = NostrPublicKey::from_str(user_id);
+        let Some(pubkey) = self.0 else {
+            return Ok(owner.map_or_else(|_| user_id.to_string(), |owner| owner.to_hex()));
+        };
+
+        match owner {
+            Ok(owner) if owner == pubkey => Ok(owner.to_hex()),
+            _ => Err(ApiError::new(StatusCode::FORBIDDEN, "forbidden", format!("{} may not access user {}", pubkey, user_id))
+                .with_field("user_id")),
+        }
+    }
+
+    // Only one of an escrow's parties may create it
+    pub fn authorize_party(&self, parties: &[NostrPublicKey]) -> Result<(), ApiError> {
+        match self.0 {
+            Some(pubkey) if !parties.contains(&pubkey) => Err(ApiError::new(StatusCode::FORBIDDEN, "forbidden",
+                format!("{} is not a party to this escrow", pubkey)).with_field("escrow_input")),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FromRequest for Nip98Auth {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(Self(req.extensions().get::<NostrPublicKey>().copied())))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use bitcoin::secp256k1::{KeyPair, Secp256k1, SecretKey};
+
+    pub(crate) fn keypair(seed: u8) -> KeyPair {
+        KeyPair::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[seed; 32]).unwrap())
+    }
+
+    // Builds the Authorization header a client would send
+    pub(crate) fn auth_header(keypair: &KeyPair, method: &str, url: &str, body: Option<&[u8]>) -> String {
+        let mut tags = tags(url, method);
+        if let Some(body) = body {
+            tags.push(vec!["payload".to_string(), sha256::Hash::hash(body).to_string()]);
+        }
+
+        encode(&NostrEvent::sign(keypair, now(), HTTP_AUTH_KIND, tags, String::new()))
+    }
+
+    fn pubkey(seed: u8) -> NostrPublicKey {
+        NostrPublicKey::from_hex(&keypair(seed).x_only_public_key().0.to_string()).unwrap()
+    }
+
+    fn encode(event: &NostrEvent) -> String {
+        format!("Nostr {}", base64::engine::general_purpose::STANDARD.encode(serde_json::to_vec(event).unwrap()))
+    }
+
+    fn now() -> i64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+    }
+
+    fn tags(url: &str, method: &str) -> Vec<Vec<String>> {
+        vec![vec!["u".to_string(), url.to_string()], vec!["method".to_string(), method.to_string()]]
+    }
+
+    // Authenticates a request as the server sees it: http://localhost:8080 plus `uri`
+    fn check(method: &str, uri: &str, header: &str, body: &[u8]) -> Result<NostrPublicKey, ApiError> {
+        let req = actix_web::test::TestRequest::default()
+            .method(method.parse().unwrap())
+            .uri(uri)
+            .insert_header(("Authorization", header))
+            .to_http_request();
+        authenticate(&req, body, &AuthConfig::default())
+    }
+
+    fn message(result: Result<NostrPublicKey, ApiError>) -> String {
+        let e = result.unwrap_err();
+        assert_eq!((e.status, e.body.code), (StatusCode::UNAUTHORIZED, "unauthorized"));
+        e.body.message
+    }
+
+    #[test]
+    fn test_valid_event_authenticates_its_signer() {
+        let alice = keypair(1);
+        let body = br#"{"amount":1}"#;
+        let header = auth_header(&alice, "post", "http://localhost:8080/escrow?dry_run=1", Some(body));
+        let pubkey = check("POST", "/escrow?dry_run=1", &header, body).unwrap();
+        assert_eq!(pubkey.x_only(), alice.x_only_public_key().0);
+    }
+
+    #[test]
+    fn test_event_must_be_http_auth_within_the_time_window() {
+        let alice = keypair(1);
+        let url = "http://localhost:8080/users";
+        let header = encode(&NostrEvent::sign(&alice, now(), 1, tags(url, "GET"), String::new()));
+        assert!(message(check("GET", "/users", &header, b"")).contains("kind 27235"));
+
+        for created_at in [now() - 120, now() + 120] {
+            let header = encode(&NostrEvent::sign(&alice, created_at, HTTP_AUTH_KIND, tags(url, "GET"), String::new()));
+            assert!(message(check("GET", "/users", &header, b"")).contains("time window"));
+        }
+    }
+
+    #[test]
+    fn test_event_must_name_the_exact_url_and_method() {
+        let alice = keypair(1);
+        for url in ["https://localhost:8080/users", "http://example.com/users", "http://localhost:8080/users?page=2"] {
+            let header = auth_header(&alice, "GET", url, None);
+            assert!(message(check("GET", "/users", &header, b"")).contains("not for http://localhost:8080/users"), "{}", url);
+        }
+        let header = auth_header(&alice, "GET", "http://localhost:8080/users?page=1", None);
+        assert!(message(check("GET", "/users?page=2", &header, b"")).contains("not for http://localhost:8080/users?page=2"));
+
+        let header = auth_header(&alice, "GET", "http://localhost:8080/users", None);
+        assert!(message(check("DELETE", "/users", &header, b"")).contains("method DELETE"));
+    }
+
+    #[test]
+    fn test_event_must_hash_the_body() {
+        let alice = keypair(1);
+        let url = "http://localhost:8080/users";
+        let header = auth_header(&alice, "POST", url, Some(b"{\"a\":1}"));
+        assert!(message(check("POST", "/users", &header, b"{\"a\":2}")).contains("payload hash"));
+
+        let header = auth_header(&alice, "POST", url, None);
+        assert!(message(check("POST", "/users", &header, b"{\"a\":1}")).contains("payload hash"));
+        // Without a body there is nothing to hash
+        check("POST", "/users", &header, b"").unwrap();
+    }
+
+    #[test]
+    fn test_header_must_be_a_signed_nostr_event() {
+        let alice = keypair(1);
+        let url = "http://localhost:8080/users";
+        assert!(message(check("GET", "/users", "Bearer abc", b"")).contains("scheme"));
+        assert!(message(check("GET", "/users", "Nostr not*base64", b"")).contains("not base64"));
+        let not_json = format!("Nostr {}", base64::engine::general_purpose::STANDARD.encode("{"));
+        assert!(message(check("GET", "/users", &not_json, b"")).contains("not valid JSON"));
+
+        // Another event's signature, and content changed after signing
+        let mut event = NostrEvent::sign(&alice, now(), HTTP_AUTH_KIND, tags(url, "GET"), String::new());
+        event.sig = NostrEvent::sign(&alice, now(), HTTP_AUTH_KIND, tags(url, "POST"), String::new()).sig;
+        assert!(message(check("GET", "/users", &encode(&event), b"")).contains("signature"));
+        let mut event = NostrEvent::sign(&alice, now(), HTTP_AUTH_KIND, tags(url, "GET"), String::new());
+        event.pubkey = keypair(2).x_only_public_key().0.to_string();
+        message(check("GET", "/users", &encode(&event), b""));
+    }
+
+    #[test]
+    fn test_authorize_accepts_either_spelling_of_the_signer() {
+        let alice = pubkey(1);
+        let bob = pubkey(2);
+        let auth = Nip98Auth(Some(alice));
+        assert_eq!(auth.authorize(&alice.to_npub()).unwrap(), alice.to_hex());
+        assert_eq!(auth.authorize(&alice.to_hex()).unwrap(), alice.to_hex());
+
+        for user_id in [bob.to_npub(), bob.to_hex(), "alice".to_string()] {
+            let e = auth.authorize(&user_id).unwrap_err();
+            assert_eq!((e.status, e.body.code, e.body.field.as_deref()), (StatusCode::FORBIDDEN, "forbidden", Some("user_id")));
+        }
+        // Without authentication ids are still stored under the hex key
+        assert_eq!(Nip98Auth(None).authorize(&alice.to_npub()).unwrap(), alice.to_hex());
+        assert_eq!(Nip98Auth(None).authorize("alice").unwrap(), "alice");
+    }
+
+    #[test]
+    fn test_authorize_party_needs_the_signer_among_the_parties() {
+        let alice = pubkey(1);
+        let bob = pubkey(2);
+        let carol = pubkey(3);
+
+        Nip98Auth(Some(alice)).authorize_party(&[alice, bob]).unwrap();
+        Nip98Auth(Some(bob)).authorize_party(&[alice, bob]).unwrap();
+        let e = Nip98Auth(Some(carol)).authorize_party(&[alice, bob]).unwrap_err();
+        assert_eq!((e.status, e.body.field.as_deref()), (StatusCode::FORBIDDEN, Some("escrow_input")));
+        Nip98Auth(None).authorize_party(&[alice, bob]).unwrap();
+    }
+}
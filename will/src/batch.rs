@@ -0,0 +1,132 @@
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc};
+use crate::engine::ProjectionEngine;
+use crate::spending::{SpendingError, UserModel};
+
+#[derive(Debug)]
+pub struct BatchResult {
+    pub user_id: String,
+    pub totals: Result<HashMap<u32, f64>, SpendingError>,
+}
+
+// Projects many user models across a dedicated thread pool.
+// All workers share one engine, so a formula used by many models is compiled once.
+pub struct BatchProjector {
+    pool: rayon::ThreadPool,
+    engine: Arc<ProjectionEngine>,
+}
+
+impl BatchProjector {
+    // `threads` of 0 lets rayon pick one worker per CPU
+    pub fn new(threads: usize) -> Result<Self, rayon::ThreadPoolBuildError> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("projection-{}", i))
+            .build()?;
+
+        Ok(Self {
+            pool,
+            engine: Arc::new(ProjectionEngine::new()),
+        })
+    }
+
+    pub fn engine(&self) -> &ProjectionEngine {
+        &self.engine
+    }
+
+    pub fn threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    // Projects every model and returns the results in input order
+    pub fn project_all(&self, models: &[UserModel]) -> Vec<BatchResult> {
+        let engine = &self.engine;
+        self.pool.install(|| {
+            models.par_iter()
+                .map(|model| project_one(engine, model))
+                .collect()
+        })
+    }
+
+    // Streams results as soon as each model finishes; completion order is not input order.
+    // The receiver yields until every model has been projected.
+    pub fn stream(&self, models: Vec<UserModel>) -> mpsc::Receiver<BatchResult> {
+        let (sender, receiver) = mpsc::channel();
+        let engine = self.engine.clone();
+
+        self.pool.spawn(move || {
+            models.par_iter().for_each_with(sender, |sender, model| {
+                // The consumer may stop listening early; remaining results are dropped
+                let _ = sender.send(project_one(&engine, model));
+            });
+        });
+
+        receiver
+    }
+}
+
+fn project_one(engine: &ProjectionEngine, model: &UserModel) -> BatchResult {
+    BatchResult {
+        user_id: model.user_id.clone(),
+        totals: engine.total_spend(model),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spending::{GrowthType, ProjectSpend};
+
+    fn models(count: usize) -> Vec<UserModel> {
+        (0..count)
+            .map(|i| {
+                let mut user = UserModel::new(format!("user{}", i), 10).unwrap();
+                user.add_project(ProjectSpend::new("A".into(), 10.0 + i as f64, 0.05, GrowthType::Compound).unwrap());
+                user.add_project(ProjectSpend::new(
+                    "B".into(),
+                    5.0,
+                    0.02,
+                    GrowthType::Custom("base * (1.0 + rate) ** year".into()),
+                ).unwrap());
+                user
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_batch_matches_serial() {
+        let models = models(50);
+        let projector = BatchProjector::new(4).unwrap();
+
+        let results = projector.project_all(&models);
+        assert_eq!(results.len(), models.len());
+        assert_eq!(projector.engine().compiled_formulas(), 1);
+
+        for (model, result) in models.iter().zip(&results) {
+            assert_eq!(model.user_id, result.user_id);
+            let serial = model.calculate_total_spend().unwrap();
+            let batch = result.totals.as_ref().unwrap();
+            for year in 0..model.projection_years {
+                assert!((serial[&year] - batch[&year]).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn test_stream_yields_every_model() {
+        let projector = BatchProjector::new(2).unwrap();
+        let mut user_ids: Vec<String> = projector.stream(models(20))
+            .into_iter()
+            .map(|result| {
+                assert!(result.totals.is_ok());
+                result.user_id
+            })
+            .collect();
+
+        user_ids.sort();
+        let mut expected: Vec<String> = (0..20).map(|i| format!("user{}", i)).collect();
+        expected.sort();
+        assert_eq!(user_ids, expected);
+    }
+}
@@ -1,51 +1,7 @@
 use image::{ImageBuffer, Rgb};
+use my_crate::draw::{draw_circle, draw_line};
 use std::f64::consts::PI;
 
-fn draw_circle(img: &mut ImageBuffer<Rgb<u8>, Vec<u8>>, x: i32, y: i32, radius: i32, color: Rgb<u8>) {
-    for i in -radius..=radius {
-        for j in -radius..=radius {
-            if i*i + j*j <= radius*radius {
-                let px = (x + i) as u32;
-                let py = (y + j) as u32;
-                if px < img.width() && py < img.height() {
-                    img.put_pixel(px, py, color);
-                }
-            }
-        }
-    }
-}
-
-fn draw_line(img: &mut ImageBuffer<Rgb<u8>, Vec<u8>>, x1: i32, y1: i32, x2: i32, y2: i32, color: Rgb<u8>) {
-    let dx = (x2 - x1).abs();
-    let dy = (y2 - y1).abs();
-    let sx = if x1 < x2 { 1 } else { -1 };
-    let sy = if y1 < y2 { 1 } else { -1 };
-    let mut err = dx - dy;
-
-    let mut x = x1;
-    let mut y = y1;
-
-    loop {
-        if x >= 0 && x < img.width() as i32 && y >= 0 && y < img.height() as i32 {
-            img.put_pixel(x as u32, y as u32, color);
-        }
-
-        if x == x2 && y == y2 {
-            break;
-        }
-
-        let e2 = 2 * err;
-        if e2 > -dy {
-            err -= dy;
-            x += sx;
-        }
-        if e2 < dx {
-            err += dx;
-            y += sy;
-        }
-    }
-}
-
 fn main() {
     // Create a new image with a white background
     let width = 800;
@@ -1,6 +1,16 @@
+use clap::Parser;
+use my_crate::config::{CliArgs, ServerConfig};
 use my_crate::web;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    web::run_server().await
-} 
\ No newline at end of file
+    let config = match ServerConfig::load(CliArgs::parse()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    web::run_server(config).await
+}
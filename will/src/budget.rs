@@ -0,0 +1,176 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use std::collections::HashMap;
+use crate::spending::{SpendingError, UserModel};
+use crate::engine::ProjectionEngine;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct BudgetCaps {
+    #[serde(default)]
+    pub yearly_cap: Option<f64>,
+    #[serde(default)]
+    pub project_caps: HashMap<String, f64>,
+    // Fractions of a cap that should raise an alert, e.g. 0.8 for 80%
+    #[serde(default)]
+    pub thresholds: Vec<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", content = "name", rename_all = "snake_case")]
+pub enum BreachScope {
+    Total,
+    Project(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BreachKind {
+    Threshold,
+    CapExceeded,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BudgetBreach {
+    pub year: u32,
+    pub scope: BreachScope,
+    pub kind: BreachKind,
+    pub spend: f64,
+    pub cap: f64,
+    // The highest threshold crossed; 1.0 when the cap itself is exceeded
+    pub threshold: f64,
+}
+
+impl BudgetCaps {
+    pub fn validate(&self) -> Result<(), SpendingError> {
+        if let Some(cap) = self.yearly_cap {
+            if cap <= 0.0 {
+                return Err(SpendingError::InvalidBudget("Yearly cap must be greater than 0".into()));
+            }
+        }
+
+        for (project, cap) in &self.project_caps {
+            if *cap <= 0.0 {
+                return Err(SpendingError::InvalidBudget(format!("Cap for project '{}' must be greater than 0", project)));
+            }
+        }
+
+        for threshold in &self.thresholds {
+            if *threshold <= 0.0 || *threshold > 1.0 {
+                return Err(SpendingError::InvalidBudget(format!("Threshold {} must be within (0, 1]", threshold)));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.yearly_cap.is_none() && self.project_caps.is_empty()
+    }
+
+    fn check(&self, year: u32, scope: BreachScope, spend: f64, cap: f64) -> Option<BudgetBreach> {
+        if spend > cap {
+            return Some(BudgetBreach { year, scope, kind: BreachKind::CapExceeded, spend, cap, threshold: 1.0 });
+        }
+
+        let threshold = self.thresholds
+            .iter()
+            .copied()
+            .filter(|threshold| spend >= cap * threshold)
+            .max_by(f64::total_cmp)?;
+
+        Some(BudgetBreach { year, scope, kind: BreachKind::Threshold, spend, cap, threshold })
+    }
+}
+
+impl UserModel {
+    // Flags every projected year, in total and per project, that crosses a cap or threshold
+    pub fn check_budget(&self) -> Result<Vec<BudgetBreach>, SpendingError> {
+        let mut breaches = Vec::new();
+
+        if self.budget.is_empty() {
+            return Ok(breaches);
+        }
+
+        let engine = ProjectionEngine::shared();
+
+        for year in 0..self.projection_years {
+            let mut year_total = 0.0;
+
+            for project in &self.projects {
+                let spend = engine.yearly_spend(project, year)?;
+                year_total += spend;
+
+                if let Some(cap) = self.budget.project_caps.get(&project.project_name) {
+                    let scope = BreachScope::Project(project.project_name.clone());
+                    breaches.extend(self.budget.check(year, scope, spend, *cap));
+                }
+            }
+
+            if let Some(cap) = self.budget.yearly_cap {
+                breaches.extend(self.budget.check(year, BreachScope::Total, year_total, cap));
+            }
+        }
+
+        Ok(breaches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spending::{GrowthType, ProjectSpend};
+
+    fn model_with_caps(budget: BudgetCaps) -> UserModel {
+        let mut user = UserModel::new("user1".into(), 3).unwrap();
+        user.add_project(ProjectSpend::new("Rent".into(), 100.0, 0.10, GrowthType::Compound).unwrap());
+        user.add_project(ProjectSpend::new("Food".into(), 20.0, 0.0, GrowthType::Flat).unwrap());
+        user.set_budget(budget).unwrap();
+        user
+    }
+
+    #[test]
+    fn test_yearly_cap_breach() {
+        // Totals: 43800, 47450, 51465
+        let user = model_with_caps(BudgetCaps {
+            yearly_cap: Some(45000.0),
+            thresholds: vec![0.9],
+            ..Default::default()
+        });
+
+        let breaches = user.check_budget().unwrap();
+        assert_eq!(breaches.len(), 3);
+        assert_eq!(breaches[0].kind, BreachKind::Threshold);
+        assert_eq!(breaches[1].kind, BreachKind::CapExceeded);
+        assert_eq!(breaches[2].kind, BreachKind::CapExceeded);
+        assert!(breaches.iter().all(|b| b.scope == BreachScope::Total));
+    }
+
+    #[test]
+    fn test_project_cap_reports_highest_threshold() {
+        let mut project_caps = HashMap::new();
+        project_caps.insert("Food".to_string(), 8000.0);
+
+        let user = model_with_caps(BudgetCaps {
+            project_caps,
+            thresholds: vec![0.5, 0.9, 0.75],
+            ..Default::default()
+        });
+
+        // Food is 7300 every year, i.e. 91% of its cap
+        let breaches = user.check_budget().unwrap();
+        assert_eq!(breaches.len(), 3);
+        assert!(breaches.iter().all(|b| b.scope == BreachScope::Project("Food".into())));
+        assert!(breaches.iter().all(|b| (b.threshold - 0.9).abs() < f64::EPSILON));
+    }
+
+    #[test]
+    fn test_invalid_threshold_rejected() {
+        let mut user = UserModel::new("user1".into(), 3).unwrap();
+        let result = user.set_budget(BudgetCaps {
+            yearly_cap: Some(1000.0),
+            thresholds: vec![1.5],
+            ..Default::default()
+        });
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,338 @@
+use image::{DynamicImage, ImageOutputFormat, Rgb};
+use serde::Deserialize;
+use std::fmt::Write;
+use std::io::Cursor;
+use utoipa::ToSchema;
+use crate::draw::{self, Canvas, GLYPH_HEIGHT};
+use crate::projection::Projection;
+
+pub const MIN_SIZE: u32 = 200;
+pub const MAX_SIZE: u32 = 2000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChartKind {
+    #[default]
+    Line,
+    StackedBar,
+}
+
+pub struct Series {
+    pub name: String,
+    pub values: Vec<f64>,
+}
+
+// What a chart shows, independent of how it is rendered
+pub struct ChartData {
+    pub title: String,
+    // One per point; empty labels are not drawn
+    pub x_labels: Vec<String>,
+    pub series: Vec<Series>,
+}
+
+impl ChartData {
+    // One series per project; sub-yearly periods are labelled at the start of each year
+    pub fn from_projection(title: String, projection: &Projection) -> Self {
+        let x_labels = projection.periods.iter()
+            .map(|p| if p.period == 0 { p.year.to_string() } else { String::new() })
+            .collect();
+
+        let names: Vec<String> = projection.periods.first()
+            .map(|p| p.projects.iter().map(|t| t.project.clone()).collect())
+            .unwrap_or_default();
+        let series = names.into_iter()
+            .enumerate()
+            .map(|(i, name)| Series {
+                name,
+                values: projection.periods.iter().map(|p| p.projects[i].total).collect(),
+            })
+            .collect();
+
+        Self { title, x_labels, series }
+    }
+
+    fn points(&self) -> usize {
+        self.x_labels.len()
+    }
+}
+
+const PALETTE: [(u8, u8, u8); 8] = [
+    (31, 119, 180), (255, 127, 14), (44, 160, 44), (214, 39, 40),
+    (148, 103, 189), (140, 86, 75), (227, 119, 194), (127, 127, 127),
+];
+
+fn color(index: usize) -> (u8, u8, u8) {
+    PALETTE[index % PALETTE.len()]
+}
+
+// Axis labels such as 950, 12.5K or 3M
+fn short_amount(value: f64) -> String {
+    let (scaled, suffix) = match value.abs() {
+        v if v >= 1e9 => (value / 1e9, "B"),
+        v if v >= 1e6 => (value / 1e6, "M"),
+        v if v >= 1e3 => (value / 1e3, "K"),
+        _ => (value, ""),
+    };
+    let text = format!("{:.1}", scaled);
+    format!("{}{}", text.trim_end_matches(".0"), suffix)
+}
+
+const LEGEND_WIDTH: f64 = 150.0;
+const LEGEND_NAME_CHARS: usize = 18;
+
+// Plot geometry shared by the PNG and SVG renderers
+struct Layout {
+    width: f64,
+    height: f64,
+    left: f64,
+    top: f64,
+    right: f64,
+    bottom: f64,
+    y_max: f64,
+    y_step: f64,
+}
+
+impl Layout {
+    fn new(data: &ChartData, kind: ChartKind, width: u32, height: u32) -> Self {
+        let max = match kind {
+            ChartKind::Line => data.series.iter()
+                .flat_map(|s| s.values.iter().copied())
+                .fold(0.0, f64::max),
+            ChartKind::StackedBar => (0..data.points())
+                .map(|i| data.series.iter().map(|s| s.values[i].max(0.0)).sum::<f64>())
+                .fold(0.0, f64::max),
+        };
+
+        // Five or so gridlines at 1, 2 or 5 times a power of ten
+        let rough = if max > 0.0 { max / 5.0 } else { 1.0 };
+        let magnitude = 10f64.powf(rough.log10().floor());
+        let y_step = [1.0, 2.0, 5.0, 10.0].iter()
+            .map(|m| m * magnitude)
+            .find(|step| *step >= rough)
+            .unwrap_or(10.0 * magnitude);
+        let y_max = (max / y_step).ceil().max(1.0) * y_step;
+
+        Self {
+            width: width as f64,
+            height: height as f64,
+            left: 60.0,
+            top: 36.0,
+            right: width as f64 - LEGEND_WIDTH,
+            bottom: height as f64 - 40.0,
+            y_max,
+            y_step,
+        }
+    }
+
+    fn slot(&self, points: usize) -> f64 {
+        (self.right - self.left) / points.max(1) as f64
+    }
+
+    fn x(&self, index: usize, points: usize) -> f64 {
+        self.left + (index as f64 + 0.5) * self.slot(points)
+    }
+
+    // Negative amounts are drawn on the axis
+    fn y(&self, value: f64) -> f64 {
+        self.bottom - value.max(0.0) / self.y_max * (self.bottom - self.top)
+    }
+
+    fn gridlines(&self) -> impl Iterator<Item = f64> + '_ {
+        let steps = (self.y_max / self.y_step).round() as usize;
+        (0..=steps).map(move |i| i as f64 * self.y_step)
+    }
+
+    // Draws every n-th non-empty x label so neighbouring labels never overlap
+    fn label_stride(&self, data: &ChartData) -> usize {
+        let labelled = data.x_labels.iter().filter(|l| !l.is_empty()).count().max(1);
+        let widest = data.x_labels.iter().map(|l| draw::text_width(l, 1)).max().unwrap_or(0) as f64 + 8.0;
+        let available = self.right - self.left;
+        ((widest * labelled as f64) / available).ceil().max(1.0) as usize
+    }
+
+    // Each bar segment as (series, point, top, bottom)
+    fn bar_segments(&self, data: &ChartData) -> Vec<(usize, usize, f64, f64)> {
+        let mut segments = Vec::new();
+        for point in 0..data.points() {
+            let mut base = 0.0;
+            for (series, s) in data.series.iter().enumerate() {
+                let value = s.values[point].max(0.0);
+                segments.push((series, point, self.y(base + value), self.y(base)));
+                base += value;
+            }
+        }
+        segments
+    }
+}
+
+fn legend_name(name: &str) -> String {
+    name.chars().take(LEGEND_NAME_CHARS).collect()
+}
+
+pub fn render_png(data: &ChartData, kind: ChartKind, width: u32, height: u32) -> Result<Vec<u8>, image::ImageError> {
+    let layout = Layout::new(data, kind, width, height);
+    let points = data.points();
+    let mut img: Canvas = Canvas::from_pixel(width, height, Rgb([255, 255, 255]));
+    let black = Rgb([0, 0, 0]);
+    let grey = Rgb([220, 220, 220]);
+    let rgb = |(r, g, b): (u8, u8, u8)| Rgb([r, g, b]);
+
+    draw::draw_text(&mut img, layout.left as i32, 12, &data.title, 2, black);
+
+    for value in layout.gridlines() {
+        let y = layout.y(value).round() as i32;
+        draw::draw_line(&mut img, layout.left as i32, y, layout.right as i32, y, grey);
+        let label = short_amount(value);
+        let x = layout.left as i32 - 6 - draw::text_width(&label, 1);
+        draw::draw_text(&mut img, x, y - GLYPH_HEIGHT / 2, &label, 1, black);
+    }
+
+    match kind {
+        ChartKind::Line => {
+            for (series, s) in data.series.iter().enumerate() {
+                let c = rgb(color(series));
+                let coords: Vec<(i32, i32)> = s.values.iter().enumerate()
+                    .map(|(i, v)| (layout.x(i, points).round() as i32, layout.y(*v).round() as i32))
+                    .collect();
+                for pair in coords.windows(2) {
+                    let ((x1, y1), (x2, y2)) = (pair[0], pair[1]);
+                    draw::draw_line(&mut img, x1, y1, x2, y2, c);
+                    draw::draw_line(&mut img, x1, y1 + 1, x2, y2 + 1, c);
+                }
+                if points <= 60 {
+                    for (x, y) in coords {
+                        draw::draw_circle(&mut img, x, y, 3, c);
+                    }
+                }
+            }
+        }
+        ChartKind::StackedBar => {
+            let bar = (layout.slot(points) * 0.7).max(1.0);
+            for (series, point, top, bottom) in layout.bar_segments(data) {
+                let x = (layout.x(point, points) - bar / 2.0).round() as i32;
+                let height = (bottom - top).round() as i32;
+                draw::fill_rect(&mut img, x, top.round() as i32, bar.round() as i32, height, rgb(color(series)));
+            }
+        }
+    }
+
+    let (left, right, bottom) = (layout.left as i32, layout.right as i32, layout.bottom as i32);
+    draw::draw_line(&mut img, left, layout.top as i32, left, bottom, black);
+    draw::draw_line(&mut img, left, bottom, right, bottom, black);
+
+    let stride = layout.label_stride(data);
+    for (i, label) in data.x_labels.iter().enumerate().filter(|(_, l)| !l.is_empty()).step_by(stride) {
+        let x = layout.x(i, points).round() as i32;
+        draw::draw_line(&mut img, x, bottom, x, bottom + 4, black);
+        draw::draw_text(&mut img, x - draw::text_width(label, 1) / 2, bottom + 8, label, 1, black);
+    }
+    let axis_title = "YEAR";
+    draw::draw_text(&mut img, (left + right - draw::text_width(axis_title, 1)) / 2, bottom + 22, axis_title, 1, black);
+
+    for (series, s) in data.series.iter().enumerate() {
+        let y = layout.top as i32 + series as i32 * 16;
+        draw::fill_rect(&mut img, right + 16, y, 10, 10, rgb(color(series)));
+        draw::draw_text(&mut img, right + 32, y + 2, &legend_name(&s.name), 1, black);
+    }
+
+    let mut png = Vec::new();
+    DynamicImage::ImageRgb8(img).write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)?;
+    Ok(png)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+pub fn render_svg(data: &ChartData, kind: ChartKind, width: u32, height: u32) -> String {
+    let layout = Layout::new(data, kind, width, height);
+    let points = data.points();
+    let hex = |(r, g, b): (u8, u8, u8)| format!("#{:02x}{:02x}{:02x}", r, g, b);
+    let mut svg = String::new();
+
+    // Writing to a String cannot fail
+    let _ = writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="sans-serif" font-size="12">"#, w = layout.width, h = layout.height);
+    let _ = writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#);
+    let _ = writeln!(svg, r#"<text x="{}" y="24" font-size="16">{}</text>"#, layout.left, escape(&data.title));
+
+    for value in layout.gridlines() {
+        let y = layout.y(value);
+        let _ = writeln!(svg, r##"<line x1="{}" y1="{y:.1}" x2="{}" y2="{y:.1}" stroke="#dcdcdc"/>"##, layout.left, layout.right);
+        let _ = writeln!(svg, r#"<text x="{}" y="{:.1}" text-anchor="end">{}</text>"#, layout.left - 6.0, y + 4.0, short_amount(value));
+    }
+
+    match kind {
+        ChartKind::Line => {
+            for (series, s) in data.series.iter().enumerate() {
+                let coords: Vec<String> = s.values.iter().enumerate()
+                    .map(|(i, v)| format!("{:.1},{:.1}", layout.x(i, points), layout.y(*v)))
+                    .collect();
+                let _ = writeln!(svg, r#"<polyline fill="none" stroke="{}" stroke-width="2" points="{}"/>"#, hex(color(series)), coords.join(" "));
+            }
+        }
+        ChartKind::StackedBar => {
+            let bar = (layout.slot(points) * 0.7).max(1.0);
+            for (series, point, top, bottom) in layout.bar_segments(data) {
+                let _ = writeln!(
+                    svg,
+                    r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{}"/>"#,
+                    layout.x(point, points) - bar / 2.0, top, bar, bottom - top, hex(color(series)),
+                );
+            }
+        }
+    }
+
+    let _ = writeln!(svg, r#"<path d="M{l},{t} V{b} H{r}" fill="none" stroke="black"/>"#, l = layout.left, t = layout.top, b = layout.bottom, r = layout.right);
+
+    let stride = layout.label_stride(data);
+    for (i, label) in data.x_labels.iter().enumerate().filter(|(_, l)| !l.is_empty()).step_by(stride) {
+        let _ = writeln!(svg, r#"<text x="{:.1}" y="{}" text-anchor="middle">{}</text>"#, layout.x(i, points), layout.bottom + 16.0, escape(label));
+    }
+    let _ = writeln!(svg, r#"<text x="{}" y="{}" text-anchor="middle">Year</text>"#, (layout.left + layout.right) / 2.0, layout.bottom + 32.0);
+
+    for (series, s) in data.series.iter().enumerate() {
+        let y = layout.top + series as f64 * 16.0;
+        let _ = writeln!(svg, r#"<rect x="{}" y="{}" width="10" height="10" fill="{}"/>"#, layout.right + 16.0, y, hex(color(series)));
+        let _ = writeln!(svg, r#"<text x="{}" y="{}">{}</text>"#, layout.right + 32.0, y + 9.0, escape(&legend_name(&s.name)));
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data() -> ChartData {
+        ChartData {
+            title: "Alice".into(),
+            x_labels: vec!["0".into(), "1".into(), "2".into()],
+            series: vec![
+                Series { name: "Rent".into(), values: vec![100.0, 200.0, 300.0] },
+                Series { name: "Food <& drink>".into(), values: vec![50.0, 50.0, 50.0] },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_axis_scale_and_labels() {
+        let layout = Layout::new(&data(), ChartKind::StackedBar, 800, 480);
+        assert_eq!(layout.y_max, 400.0);
+        assert_eq!(layout.y_step, 100.0);
+        assert_eq!(short_amount(12_500.0), "12.5K");
+        assert_eq!(short_amount(3_000_000.0), "3M");
+    }
+
+    #[test]
+    fn test_renders_png_and_svg() {
+        let png = render_png(&data(), ChartKind::Line, 400, 300).unwrap();
+        let decoded = image::load_from_memory(&png).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (400, 300));
+
+        let svg = render_svg(&data(), ChartKind::StackedBar, 400, 300);
+        assert!(svg.starts_with("<svg"));
+        assert_eq!(svg.matches("<rect").count(), 1 + 6 + 2);
+        assert!(svg.contains("Food &lt;&amp; drink&gt;"));
+    }
+}
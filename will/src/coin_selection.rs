@@ -0,0 +1,392 @@
+use bitcoin::hashes::Hash;
+use bitcoin::{OutPoint, Script, TxOut, VarInt, WPubkeyHash};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+// The minimum relay fee rate, in sat/vB
+pub const MIN_FEE_RATE: f64 = 1.0;
+// Rates above this are almost certainly a unit mix-up, such as sat/kvB for sat/vB
+pub const MAX_FEE_RATE: f64 = 1_000.0;
+// Like bitcoind's -maxtxfee: no funding transaction should pay more than 0.1 BTC
+pub const MAX_FEE: u64 = 10_000_000;
+const BNB_TRIES: usize = 100_000;
+// The 41 non-witness bytes, then the witness item count, signature and compressed key
+const P2WPKH_INPUT_WEIGHT: u64 = 164 + 1 + (1 + 72) + (1 + 33);
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SelectionError {
+    #[error("A fee rate of {0} sat/vB is below the {MIN_FEE_RATE} sat/vB relay minimum")]
+    FeeRateTooLow(f64),
+    #[error("A fee rate of {0} sat/vB is absurd; the limit is {MAX_FEE_RATE} sat/vB")]
+    AbsurdFeeRate(f64),
+    #[error("A fee of {0} sats is absurd; the limit is {MAX_FEE} sats")]
+    AbsurdFee(u64),
+    #[error("{0} sats is below the {1} sat dust limit for its output")]
+    Dust(u64, u64),
+    #[error("Cannot estimate the size of spending {0}: only P2WPKH and taproot outputs are supported")]
+    UnsupportedInput(OutPoint),
+    #[error("Insufficient funds: {available} sats available after input fees, {needed} needed")]
+    InsufficientFunds { available: u64, needed: u64 },
+    #[error("{0} sats would be left over and burned as fee; give a change address")]
+    ChangeRequired(u64),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    // Looks for a set of inputs that needs no change, falling back to largest-first
+    #[default]
+    BranchAndBound,
+    // The largest inputs until the target is met; fewest inputs, usually with change
+    LargestFirst,
+}
+
+// A spendable output the funding transaction may use
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub outpoint: OutPoint,
+    pub txout: TxOut,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selection {
+    pub inputs: Vec<Candidate>,
+    pub change: Option<TxOut>,
+    pub fee: u64,
+    // Estimated, assuming 72-byte low-R ECDSA and 64-byte schnorr signatures
+    pub vsize: u64,
+}
+
+// Weight of spending `script_pubkey`: the 41 non-witness bytes (outpoint, empty scriptSig,
+// nSequence) at 4 WU each, plus the witness
+fn input_weight(script_pubkey: &Script) -> Option<u64> {
+    if script_pubkey.is_v0_p2wpkh() {
+        Some(P2WPKH_INPUT_WEIGHT)
+    } else if script_pubkey.is_v1_p2tr() {
+        // Item count and a key-path signature
+        Some(164 + 1 + (1 + 64))
+    } else {
+        None
+    }
+}
+
+fn output_weight(output: &TxOut) -> u64 {
+    let len = output.script_pubkey.len();
+    (8 + VarInt(len as u64).len() + len) as u64 * 4
+}
+
+// Version, lock time, the input and output counts, and the segwit marker and flag
+fn overhead_weight(inputs: usize, outputs: usize) -> u64 {
+    (8 + VarInt(inputs as u64).len() + VarInt(outputs as u64).len()) as u64 * 4 + 2
+}
+
+fn vsize(weight: u64) -> u64 {
+    weight.div_ceil(4)
+}
+
+fn fee(weight: u64, fee_rate: f64) -> u64 {
+    (vsize(weight) as f64 * fee_rate).ceil() as u64
+}
+
+// Bitcoin Core's dust rule at its 3 sat/vB dust relay fee: an output is dust when it is worth less
+// than the fee to create and later spend it
+pub fn dust_threshold(script_pubkey: &Script) -> u64 {
+    let output = TxOut { value: 0, script_pubkey: script_pubkey.clone() };
+    let spend = if script_pubkey.is_witness_program() { 67 } else { 148 };
+    (output_weight(&output) / 4 + spend) * 3
+}
+
+// Picks inputs from `candidates` to pay `outputs` at `fee_rate` sat/vB, adding change to
+// `change_script` when the excess is worth more than the change costs
+pub fn select(
+    candidates: &[Candidate],
+    outputs: &[TxOut],
+    change_script: Option<&Script>,
+    fee_rate: f64,
+    strategy: Strategy,
+) -> Result<Selection, SelectionError> {
+    if fee_rate.is_nan() || fee_rate < MIN_FEE_RATE {
+        return Err(SelectionError::FeeRateTooLow(fee_rate));
+    }
+    if fee_rate > MAX_FEE_RATE {
+        return Err(SelectionError::AbsurdFeeRate(fee_rate));
+    }
+    for output in outputs {
+        let threshold = dust_threshold(&output.script_pubkey);
+        if output.value < threshold {
+            return Err(SelectionError::Dust(output.value, threshold));
+        }
+    }
+
+    // What each candidate adds once the fee for spending it is paid; uneconomic ones are left out
+    let mut pool = Vec::new();
+    for candidate in candidates {
+        let weight = input_weight(&candidate.txout.script_pubkey).ok_or(SelectionError::UnsupportedInput(candidate.outpoint))?;
+        let effective = candidate.txout.value as i64 - fee(weight, fee_rate) as i64;
+        if effective > 0 {
+            pool.push((candidate, effective));
+        }
+    }
+    pool.sort_by_key(|(_, effective)| std::cmp::Reverse(*effective));
+
+    let paid: u64 = outputs.iter().map(|output| output.value).sum();
+    let outputs_weight: u64 = outputs.iter().map(output_weight).sum();
+    // Priced for as many inputs as could be picked, so the input count's VarInt is not undercounted
+    let target = (paid + fee(overhead_weight(pool.len().max(1), outputs.len() + 1) + outputs_weight, fee_rate)) as i64;
+    let available: i64 = pool.iter().map(|(_, effective)| effective).sum();
+    if available < target {
+        return Err(SelectionError::InsufficientFunds { available: available.max(0) as u64, needed: target as u64 });
+    }
+
+    // Creating change and later spending it; below this, leaving the excess as fee is cheaper.
+    // Without a change address, priced as P2WPKH change.
+    let change_output = TxOut {
+        value: 0,
+        script_pubkey: change_script.cloned().unwrap_or_else(|| Script::new_v0_p2wpkh(&WPubkeyHash::all_zeros())),
+    };
+    let change_fee = fee(output_weight(&change_output), fee_rate);
+    let spend_weight = input_weight(&change_output.script_pubkey).unwrap_or(P2WPKH_INPUT_WEIGHT);
+    let cost_of_change = (change_fee + fee(spend_weight, fee_rate)) as i64;
+
+    let changeless = match strategy {
+        Strategy::BranchAndBound => branch_and_bound(&pool.iter().map(|(_, effective)| *effective).collect::<Vec<_>>(), target, cost_of_change),
+        Strategy::LargestFirst => None,
+    };
+    let selected: Vec<usize> = match changeless {
+        Some(selected) => selected,
+        None => {
+            let mut total = 0;
+            (0..pool.len()).take_while(|i| {
+                let more = total < target;
+                total += pool[*i].1;
+                more
+            })
+            .collect()
+        }
+    };
+
+    let inputs: Vec<Candidate> = selected.iter().map(|i| pool[*i].0.clone()).collect();
+    let input_value: u64 = inputs.iter().map(|input| input.txout.value).sum();
+    let inputs_weight: u64 = inputs.iter().filter_map(|input| input_weight(&input.txout.script_pubkey)).sum();
+    let weight = overhead_weight(inputs.len(), outputs.len()) + inputs_weight + outputs_weight;
+    let needed = paid + fee(weight, fee_rate);
+    let excess = input_value.checked_sub(needed)
+        .ok_or(SelectionError::InsufficientFunds { available: input_value, needed })?;
+
+    let change = if excess as i64 <= cost_of_change {
+        None
+    } else if change_script.is_none() {
+        return Err(SelectionError::ChangeRequired(excess));
+    } else {
+        let output = TxOut { value: excess - change_fee, ..change_output };
+        (output.value >= dust_threshold(&output.script_pubkey)).then_some(output)
+    };
+
+    let weight = overhead_weight(inputs.len(), outputs.len() + change.iter().count())
+        + inputs_weight
+        + outputs_weight
+        + change.iter().map(output_weight).sum::<u64>();
+    let fee = input_value - paid - change.as_ref().map_or(0, |output| output.value);
+    if fee > MAX_FEE {
+        return Err(SelectionError::AbsurdFee(fee));
+    }
+    Ok(Selection { inputs, change, fee, vsize: vsize(weight) })
+}
+
+// Depth-first search for inputs whose effective values land between `target` and
+// `target + cost_of_change`, so no change is needed and little is overpaid. `pool` is sorted
+// largest first, which finds solutions early and prunes most branches. Each candidate is first
+// included, then excluded; the selected indices double as the stack to backtrack through, so a
+// large wallet cannot exhaust the call stack.
+fn branch_and_bound(pool: &[i64], target: i64, cost_of_change: i64) -> Option<Vec<usize>> {
+    // What the candidates from each index on could still add
+    let mut remaining = vec![0; pool.len() + 1];
+    for i in (0..pool.len()).rev() {
+        remaining[i] = remaining[i + 1] + pool[i];
+    }
+    let upper = target + cost_of_change;
+
+    let mut best: Option<(i64, Vec<usize>)> = None;
+    let mut selected = Vec::new();
+    let (mut index, mut value) = (0, 0);
+    for _ in 0..BNB_TRIES {
+        let backtrack = if value > upper || value + remaining[index] < target {
+            true
+        } else if value >= target {
+            let waste = value - target;
+            if best.as_ref().is_none_or(|(best, _)| waste < *best) {
+                best = Some((waste, selected.clone()));
+            }
+            true
+        } else {
+            false
+        };
+
+        if !backtrack {
+            selected.push(index);
+            value += pool[index];
+            index += 1;
+            continue;
+        }
+        if best.as_ref().is_some_and(|(waste, _)| *waste == 0) {
+            break;
+        }
+        // Undo the latest inclusion and carry on without it
+        match selected.pop() {
+            Some(last) => {
+                value -= pool[last];
+                index = last + 1;
+            }
+            None => break,
+        }
+    }
+    best.map(|(_, selected)| selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::blockdata::script::Builder;
+    use bitcoin::{Txid, WScriptHash};
+
+    fn candidate(vout: u32, value: u64) -> Candidate {
+        Candidate {
+            outpoint: OutPoint { txid: Txid::all_zeros(), vout },
+            txout: TxOut { value, script_pubkey: Script::new_v0_p2wpkh(&WPubkeyHash::hash(&[vout as u8])) },
+        }
+    }
+
+    fn taproot_candidate(vout: u32, value: u64) -> Candidate {
+        let script_pubkey = Builder::new().push_int(1).push_slice(&[vout as u8; 32]).into_script();
+        Candidate { outpoint: OutPoint { txid: Txid::all_zeros(), vout }, txout: TxOut { value, script_pubkey } }
+    }
+
+    fn escrow(value: u64) -> TxOut {
+        TxOut { value, script_pubkey: Script::new_v0_p2wsh(&WScriptHash::all_zeros()) }
+    }
+
+    fn change() -> Script {
+        Script::new_v0_p2wpkh(&WPubkeyHash::all_zeros())
+    }
+
+    #[test]
+    fn test_branch_and_bound_avoids_change() {
+        let candidates: Vec<Candidate> = [100_000, 60_000, 45_000, 30_000].iter().enumerate()
+            .map(|(vout, value)| candidate(vout as u32, *value))
+            .collect();
+
+        // 60k and 30k cover 89.8k plus fees with little to spare; largest-first takes 100k and makes change
+        let selection = select(&candidates, &[escrow(89_800)], Some(&change()), 1.0, Strategy::BranchAndBound).unwrap();
+        let values: Vec<u64> = selection.inputs.iter().map(|input| input.txout.value).collect();
+        assert_eq!(values, [60_000, 30_000]);
+        assert_eq!(selection.change, None);
+        // Two P2WPKH inputs and a P2WSH output
+        assert_eq!(selection.vsize, 190);
+        assert_eq!(selection.fee, 200);
+
+        let selection = select(&candidates, &[escrow(89_800)], Some(&change()), 1.0, Strategy::LargestFirst).unwrap();
+        assert_eq!(selection.inputs.len(), 1);
+        let change = selection.change.unwrap();
+        assert_eq!(selection.fee, selection.vsize);
+        assert_eq!(100_000, 89_800 + change.value + selection.fee);
+    }
+
+    #[test]
+    fn test_fee_and_dust_checks() {
+        let candidates = [candidate(0, 50_000)];
+        let select = |amount, change: Option<&Script>, fee_rate| select(&candidates, &[escrow(amount)], change, fee_rate, Strategy::BranchAndBound);
+
+        assert_eq!(select(10_000, Some(&change()), 0.5).unwrap_err(), SelectionError::FeeRateTooLow(0.5));
+        assert_eq!(select(10_000, Some(&change()), 5_000.0).unwrap_err(), SelectionError::AbsurdFeeRate(5_000.0));
+        assert_eq!(select(300, Some(&change()), 2.0).unwrap_err(), SelectionError::Dust(300, 330));
+        assert!(matches!(select(49_990, Some(&change()), 2.0), Err(SelectionError::InsufficientFunds { .. })));
+
+        // Without a change address the excess would be burned
+        assert_eq!(select(10_000, None, 2.0).unwrap_err(), SelectionError::ChangeRequired(39_756));
+        // Change below the dust limit goes to the fee instead
+        let selection = select(49_700, Some(&change()), 1.0).unwrap();
+        assert_eq!((selection.change, selection.fee), (None, 300));
+
+        // More than 252 inputs widen the input count's VarInt
+        let dust: Vec<Candidate> = (0..300).map(|vout| candidate(vout, 1_000)).collect();
+        let selection = super::select(&dust, &[escrow(240_000)], Some(&change()), 1.0, Strategy::LargestFirst).unwrap();
+        assert!(selection.inputs.len() > 252);
+        let change = selection.change.unwrap().value;
+        assert_eq!(selection.inputs.len() as u64 * 1_000, 240_000 + change + selection.fee);
+        assert!(selection.fee >= selection.vsize);
+
+        let unsupported = Candidate { outpoint: OutPoint::null(), txout: escrow(50_000) };
+        assert!(matches!(
+            super::select(&[unsupported], &[escrow(10_000)], None, 1.0, Strategy::LargestFirst),
+            Err(SelectionError::UnsupportedInput(_)),
+        ));
+    }
+
+    #[test]
+    fn test_branch_and_bound_finds_exact_match() {
+        // 50 + 20 hits the target with nothing to spare, so the search stops there
+        assert_eq!(branch_and_bound(&[50, 40, 30, 20], 70, 5), Some(vec![0, 3]));
+        // Nothing lands within the window
+        assert_eq!(branch_and_bound(&[50, 40], 60, 5), None);
+
+        // Far more candidates than a recursive search could descend through
+        let pool = vec![1; 200_000];
+        let selected = branch_and_bound(&pool, 50_000, 0).unwrap();
+        assert_eq!(selected.len(), 50_000);
+    }
+
+    #[test]
+    fn test_falls_back_to_largest_first() {
+        let candidates = [candidate(0, 60_000), candidate(1, 100_000)];
+
+        // No set of inputs avoids change, so the largest input is spent and change made
+        let selection = select(&candidates, &[escrow(50_000)], Some(&change()), 1.0, Strategy::BranchAndBound).unwrap();
+        let largest_first = select(&candidates, &[escrow(50_000)], Some(&change()), 1.0, Strategy::LargestFirst).unwrap();
+        assert_eq!(selection, largest_first);
+        assert_eq!(selection.inputs, [candidate(1, 100_000)]);
+        assert_eq!(100_000, 50_000 + selection.change.unwrap().value + selection.fee);
+    }
+
+    #[test]
+    fn test_change_below_dust_is_dropped() {
+        let candidates = [candidate(0, 50_000)];
+
+        // 178 sats are left over, more than the 99 change costs, but the 147 sat change would be dust
+        let selection = select(&candidates, &[escrow(49_700)], Some(&change()), 1.0, Strategy::LargestFirst).unwrap();
+        assert_eq!(selection.change, None);
+        assert_eq!(selection.fee, 300);
+        assert_eq!(selection.vsize, 122);
+    }
+
+    #[test]
+    fn test_fee_rate_limits() {
+        let candidates = [candidate(0, 1_000_000)];
+        let select = |fee_rate| select(&candidates, &[escrow(500_000)], Some(&change()), fee_rate, Strategy::BranchAndBound);
+
+        assert!(select(MIN_FEE_RATE).is_ok());
+        assert!(select(MAX_FEE_RATE).is_ok());
+        assert!(matches!(select(f64::NAN), Err(SelectionError::FeeRateTooLow(_))));
+        assert_eq!(select(0.99).unwrap_err(), SelectionError::FeeRateTooLow(0.99));
+        assert_eq!(select(1_000.5).unwrap_err(), SelectionError::AbsurdFeeRate(1_000.5));
+
+        // An allowed rate can still add up to an absurd fee over enough inputs
+        let many: Vec<Candidate> = (0..200).map(|vout| candidate(vout, 100_000)).collect();
+        assert!(matches!(
+            super::select(&many, &[escrow(5_000_000)], Some(&change()), MAX_FEE_RATE, Strategy::LargestFirst),
+            Err(SelectionError::AbsurdFee(_)),
+        ));
+    }
+
+    #[test]
+    fn test_mixed_input_weights() {
+        let candidates = [taproot_candidate(0, 30_000), candidate(1, 30_000)];
+
+        let selection = select(&candidates, &[escrow(50_000)], Some(&change()), 1.0, Strategy::LargestFirst).unwrap();
+        assert_eq!(selection.inputs.len(), 2);
+        // 42 WU of overhead, 230 for the key-path spend, 272 for the P2WPKH spend and 172 + 124 for the outputs
+        assert_eq!(selection.vsize, 210);
+        assert_eq!(selection.fee, 210);
+        assert_eq!(selection.change.unwrap().value, 9_790);
+    }
+}
@@ -0,0 +1,419 @@
+use bitcoin::Network;
+use clap::Parser;
+use serde::{Deserialize, Deserializer};
+use std::fs::File;
+use std::io::BufReader;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use thiserror::Error;
+use crate::auth::AuthConfig;
+use crate::ratelimit::LimitsConfig;
+use crate::rpc::RpcConfig;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Cannot read config file {0}: {1}")]
+    ReadFile(PathBuf, std::io::Error),
+    #[error("Cannot parse config file {0}: {1}")]
+    ParseFile(PathBuf, toml::de::Error),
+    #[error("Invalid value for {0}: {1}")]
+    InvalidValue(&'static str, String),
+    #[error("Invalid bind address '{0}': {1}")]
+    InvalidBind(String, String),
+    #[error("Invalid allowed origin '{0}': {1}")]
+    InvalidOrigin(String, String),
+    #[error("Invalid TLS settings: {0}")]
+    InvalidTls(String),
+}
+
+// Command-line overrides; anything left unset falls back to the environment, then the config file
+#[derive(Debug, Default, Parser)]
+#[command(name = "web_server", about = "Projection and escrow API server")]
+pub struct CliArgs {
+    /// TOML config file (also WILL_CONFIG)
+    #[arg(long, short)]
+    pub config: Option<PathBuf>,
+    /// Address to listen on, e.g. 127.0.0.1:8080; repeatable
+    #[arg(long)]
+    pub bind: Vec<String>,
+    /// Origin allowed to make cross-origin requests; repeatable
+    #[arg(long = "allowed-origin")]
+    pub allowed_origins: Vec<String>,
+    /// PEM certificate chain; serves HTTPS when set together with --tls-key
+    #[arg(long)]
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key
+    #[arg(long)]
+    pub tls_key: Option<PathBuf>,
+    /// Largest accepted request body in bytes
+    #[arg(long)]
+    pub payload_limit: Option<usize>,
+    /// Number of worker threads
+    #[arg(long)]
+    pub workers: Option<usize>,
+    /// One of off, error, warn, info, debug, trace
+    #[arg(long)]
+    pub log_level: Option<String>,
+    /// Directory served at / for the front end
+    #[arg(long)]
+    pub static_dir: Option<PathBuf>,
+    /// Bitcoin network: mainnet, testnet, signet or regtest
+    #[arg(long)]
+    pub network: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: Vec<String>,
+    pub allowed_origins: Vec<String>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub payload_limit: usize,
+    pub workers: Option<usize>,
+    pub log_level: String,
+    pub budget_webhook_url: Option<String>,
+    pub static_dir: PathBuf,
+    // Every address, PSBT and node the escrow routes deal with must be on this network
+    #[serde(deserialize_with = "deserialize_network")]
+    pub network: Network,
+    pub bitcoin_rpc: Option<RpcConfig>,
+    pub auth: AuthConfig,
+    pub limits: LimitsConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: vec!["0.0.0.0:8080".to_string()],
+            allowed_origins: Vec::new(),
+            tls_cert: None,
+            tls_key: None,
+            payload_limit: 256 * 1024,
+            workers: None,
+            log_level: "info".to_string(),
+            budget_webhook_url: None,
+            static_dir: PathBuf::from("static"),
+            network: Network::Bitcoin,
+            bitcoin_rpc: None,
+            auth: AuthConfig::default(),
+            limits: LimitsConfig::default(),
+        }
+    }
+}
+
+const MAX_PAYLOAD_LIMIT: usize = 16 * 1024 * 1024;
+const MAX_WORKERS: usize = 1024;
+
+fn list(value: &str) -> Vec<String> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// bitcoin's own names, plus mainnet for the main chain
+pub fn parse_network(value: &str) -> Result<Network, String> {
+    match value.to_lowercase().as_str() {
+        "mainnet" | "main" => Ok(Network::Bitcoin),
+        other => Network::from_str(other).map_err(|_| format!("'{}' is not one of mainnet, testnet, signet or regtest", value)),
+    }
+}
+
+fn deserialize_network<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Network, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    parse_network(&value).map_err(serde::de::Error::custom)
+}
+
+fn parse<T: FromStr>(name: &'static str, value: &str) -> Result<T, ConfigError>
+where
+    T::Err: std::fmt::Display,
+{
+    value.parse().map_err(|e: T::Err| ConfigError::InvalidValue(name, format!("'{}': {}", value, e)))
+}
+
+impl ServerConfig {
+    pub fn load(args: CliArgs) -> Result<Self, ConfigError> {
+        Self::load_from(args, |name| std::env::var(name).ok())
+    }
+
+    // Layers defaults, config file, environment and command line, then validates the result
+    pub fn load_from(args: CliArgs, env: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let path = args.config.clone().or_else(|| env("WILL_CONFIG").map(PathBuf::from));
+        let mut config = match path {
+            Some(path) => Self::from_file(&path)?,
+            None => Self::default(),
+        };
+
+        config.apply_env(env)?;
+        config.apply_args(args)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::ReadFile(path.to_path_buf(), e))?;
+        toml::from_str(&contents).map_err(|e| ConfigError::ParseFile(path.to_path_buf(), e))
+    }
+
+    fn apply_env(&mut self, env: impl Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+        // HOST and PORT predate BIND and are still honoured
+        if env("HOST").is_some() || env("PORT").is_some() {
+            let host = env("HOST").unwrap_or_else(|| "0.0.0.0".to_string());
+            let port = env("PORT").unwrap_or_else(|| "8080".to_string());
+            self.bind = vec![format!("{}:{}", host, port)];
+        }
+        if let Some(bind) = env("BIND") {
+            self.bind = list(&bind);
+        }
+        if let Some(origins) = env("ALLOWED_ORIGINS") {
+            self.allowed_origins = list(&origins);
+        }
+        if let Some(cert) = env("TLS_CERT") {
+            self.tls_cert = Some(cert.into());
+        }
+        if let Some(key) = env("TLS_KEY") {
+            self.tls_key = Some(key.into());
+        }
+        if let Some(limit) = env("PAYLOAD_LIMIT") {
+            self.payload_limit = parse("PAYLOAD_LIMIT", &limit)?;
+        }
+        if let Some(workers) = env("WORKERS") {
+            self.workers = Some(parse("WORKERS", &workers)?);
+        }
+        if let Some(level) = env("LOG_LEVEL") {
+            self.log_level = level;
+        }
+        if let Some(url) = env("BUDGET_WEBHOOK_URL") {
+            self.budget_webhook_url = Some(url);
+        }
+        if let Some(dir) = env("STATIC_DIR") {
+            self.static_dir = dir.into();
+        }
+        if let Some(network) = env("BITCOIN_NETWORK") {
+            self.network = parse_network(&network).map_err(|e| ConfigError::InvalidValue("BITCOIN_NETWORK", e))?;
+        }
+        // RPC_BTC is the node URL; credentials may come separately
+        if let Some(url) = env("RPC_BTC") {
+            let rpc = self.bitcoin_rpc.get_or_insert(RpcConfig { url: String::new(), user: None, password: None });
+            rpc.url = url;
+        }
+        if let Some(rpc) = &mut self.bitcoin_rpc {
+            if let Some(user) = env("RPC_USER") {
+                rpc.user = Some(user);
+            }
+            if let Some(password) = env("RPC_PASSWORD") {
+                rpc.password = Some(password);
+            }
+        }
+        if let Some(rate) = env("RATE_LIMIT_PER_IP") {
+            self.limits.requests_per_minute_per_ip = parse("RATE_LIMIT_PER_IP", &rate)?;
+        }
+        if let Some(rate) = env("RATE_LIMIT_PER_PUBKEY") {
+            self.limits.requests_per_minute_per_pubkey = parse("RATE_LIMIT_PER_PUBKEY", &rate)?;
+        }
+        if env("NIP98_AUTH").as_deref() == Some("off") {
+            self.auth.enabled = false;
+        }
+        Ok(())
+    }
+
+    fn apply_args(&mut self, args: CliArgs) -> Result<(), ConfigError> {
+        if !args.bind.is_empty() {
+            self.bind = args.bind;
+        }
+        if !args.allowed_origins.is_empty() {
+            self.allowed_origins = args.allowed_origins;
+        }
+        if args.tls_cert.is_some() {
+            self.tls_cert = args.tls_cert;
+        }
+        if args.tls_key.is_some() {
+            self.tls_key = args.tls_key;
+        }
+        if let Some(limit) = args.payload_limit {
+            self.payload_limit = limit;
+        }
+        if args.workers.is_some() {
+            self.workers = args.workers;
+        }
+        if let Some(level) = args.log_level {
+            self.log_level = level;
+        }
+        if let Some(dir) = args.static_dir {
+            self.static_dir = dir;
+        }
+        if let Some(network) = args.network {
+            self.network = parse_network(&network).map_err(|e| ConfigError::InvalidValue("network", e))?;
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.bind.is_empty() {
+            return Err(ConfigError::InvalidValue("bind", "at least one address is required".into()));
+        }
+        for addr in &self.bind {
+            addr.to_socket_addrs()
+                .map_err(|e| ConfigError::InvalidBind(addr.clone(), e.to_string()))?;
+        }
+
+        for origin in &self.allowed_origins {
+            validate_origin(origin)?;
+        }
+
+        if self.payload_limit == 0 || self.payload_limit > MAX_PAYLOAD_LIMIT {
+            return Err(ConfigError::InvalidValue(
+                "payload_limit",
+                format!("{} is outside 1..={} bytes", self.payload_limit, MAX_PAYLOAD_LIMIT),
+            ));
+        }
+
+        if let Some(workers) = self.workers {
+            if workers == 0 || workers > MAX_WORKERS {
+                return Err(ConfigError::InvalidValue("workers", format!("{} is outside 1..={}", workers, MAX_WORKERS)));
+            }
+        }
+
+        self.log_filter()?;
+
+        if self.auth.max_skew <= 0 {
+            return Err(ConfigError::InvalidValue("auth.max_skew", "must be a positive number of seconds".into()));
+        }
+
+        if self.limits.burst == 0 {
+            return Err(ConfigError::InvalidValue("limits.burst", "must be at least 1".into()));
+        }
+        if self.limits.max_projects == 0 || self.limits.max_projection_years == 0 || self.limits.max_running_jobs == 0 {
+            return Err(ConfigError::InvalidValue("limits", "max_projects, max_projection_years and max_running_jobs must be at least 1".into()));
+        }
+
+        if let Some(url) = &self.budget_webhook_url {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(ConfigError::InvalidValue("budget_webhook_url", format!("'{}' is not an http(s) URL", url)));
+            }
+        }
+
+        if let Some(rpc) = &self.bitcoin_rpc {
+            if !rpc.url.starts_with("http://") && !rpc.url.starts_with("https://") {
+                return Err(ConfigError::InvalidValue("bitcoin_rpc.url", format!("'{}' is not an http(s) URL", rpc.url)));
+            }
+        }
+
+        if self.tls_cert.is_some() || self.tls_key.is_some() {
+            self.rustls_config()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn log_filter(&self) -> Result<log::LevelFilter, ConfigError> {
+        parse("log_level", &self.log_level)
+    }
+
+    // Loads the certificate chain and key; None when TLS is not configured
+    pub fn rustls_config(&self) -> Result<Option<rustls::ServerConfig>, ConfigError> {
+        let (cert_path, key_path) = match (&self.tls_cert, &self.tls_key) {
+            (None, None) => return Ok(None),
+            (Some(cert), Some(key)) => (cert, key),
+            _ => return Err(ConfigError::InvalidTls("tls_cert and tls_key must be set together".into())),
+        };
+
+        let open = |path: &PathBuf| {
+            File::open(path)
+                .map(BufReader::new)
+                .map_err(|e| ConfigError::InvalidTls(format!("cannot open {}: {}", path.display(), e)))
+        };
+
+        let certs = rustls_pemfile::certs(&mut open(cert_path)?)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| ConfigError::InvalidTls(format!("cannot parse {}: {}", cert_path.display(), e)))?;
+        if certs.is_empty() {
+            return Err(ConfigError::InvalidTls(format!("no certificates found in {}", cert_path.display())));
+        }
+
+        let key = rustls_pemfile::private_key(&mut open(key_path)?)
+            .map_err(|e| ConfigError::InvalidTls(format!("cannot parse {}: {}", key_path.display(), e)))?
+            .ok_or_else(|| ConfigError::InvalidTls(format!("no private key found in {}", key_path.display())))?;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| ConfigError::InvalidTls(e.to_string()))?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| ConfigError::InvalidTls(e.to_string()))?;
+
+        Ok(Some(config))
+    }
+}
+
+// Origins are scheme://host[:port] with nothing after; wildcards are refused
+fn validate_origin(origin: &str) -> Result<(), ConfigError> {
+    let invalid = |reason: &str| ConfigError::InvalidOrigin(origin.to_string(), reason.to_string());
+
+    if origin == "*" {
+        return Err(invalid("wildcards are not allowed, list each origin explicitly"));
+    }
+
+    let host = origin.strip_prefix("https://")
+        .or_else(|| origin.strip_prefix("http://"))
+        .ok_or_else(|| invalid("must start with http:// or https://"))?;
+
+    if host.is_empty() || host.contains('/') || host.contains('*') {
+        return Err(invalid("must be scheme://host[:port] without a path"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn load(args: CliArgs, vars: &[(&str, &str)]) -> Result<ServerConfig, ConfigError> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        ServerConfig::load_from(args, |name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn test_cli_overrides_env_overrides_file() {
+        let path = std::env::temp_dir().join(format!("will-config-{}.toml", std::process::id()));
+        std::fs::write(&path, "bind = [\"127.0.0.1:9000\"]\nworkers = 2\nlog_level = \"warn\"\nnetwork = \"mainnet\"\n\n[auth]\nmax_skew = 30\n").unwrap();
+
+        let args = CliArgs {
+            config: Some(path.clone()),
+            log_level: Some("debug".into()),
+            network: Some("signet".into()),
+            ..Default::default()
+        };
+        let config = load(args, &[("WORKERS", "4"), ("BITCOIN_NETWORK", "testnet"), ("ALLOWED_ORIGINS", "https://app.example.com, http://localhost:3000")]).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(config.bind, vec!["127.0.0.1:9000"]);
+        assert_eq!(config.workers, Some(4));
+        assert_eq!(config.log_level, "debug");
+        assert_eq!(config.network, Network::Signet);
+        assert_eq!(config.allowed_origins.len(), 2);
+        assert_eq!(config.auth.max_skew, 30);
+        assert!(config.auth.enabled);
+    }
+
+    #[test]
+    fn test_invalid_settings_rejected() {
+        assert!(matches!(load(CliArgs::default(), &[("ALLOWED_ORIGINS", "*")]), Err(ConfigError::InvalidOrigin(..))));
+        assert!(matches!(load(CliArgs::default(), &[("ALLOWED_ORIGINS", "example.com")]), Err(ConfigError::InvalidOrigin(..))));
+        assert!(matches!(load(CliArgs::default(), &[("WORKERS", "0")]), Err(ConfigError::InvalidValue("workers", _))));
+        assert!(matches!(load(CliArgs::default(), &[("WORKERS", "many")]), Err(ConfigError::InvalidValue("WORKERS", _))));
+        assert!(matches!(load(CliArgs::default(), &[("LOG_LEVEL", "loud")]), Err(ConfigError::InvalidValue("log_level", _))));
+        assert!(matches!(load(CliArgs::default(), &[("BIND", "nowhere")]), Err(ConfigError::InvalidBind(..))));
+        assert!(matches!(load(CliArgs::default(), &[("TLS_CERT", "cert.pem")]), Err(ConfigError::InvalidTls(_))));
+        assert!(matches!(load(CliArgs::default(), &[("RPC_BTC", "localhost:8332")]), Err(ConfigError::InvalidValue("bitcoin_rpc.url", _))));
+        assert!(matches!(load(CliArgs::default(), &[("BITCOIN_NETWORK", "liquid")]), Err(ConfigError::InvalidValue("BITCOIN_NETWORK", _))));
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Instant;
+use crate::metrics::metrics;
+use crate::spending::{GrowthType, ProjectSpend, SpendingError, UserModel};
+
+// Upper bound on the work a single formula evaluation may do, so user formulas cannot pin a worker
+pub const MAX_FORMULA_OPERATIONS: u64 = 50_000;
+
+// Compiled formulas an engine keeps; past this the least recently used one is dropped
+pub const MAX_CACHED_FORMULAS: usize = 1024;
+
+fn formula_engine() -> rhai::Engine {
+    let mut engine = rhai::Engine::new();
+    engine.set_max_operations(MAX_FORMULA_OPERATIONS);
+    engine.set_max_expr_depths(64, 32);
+    engine.set_max_string_size(1024);
+    engine.set_max_array_size(1024);
+    engine.set_max_map_size(1024);
+    engine
+}
+
+struct CachedFormula {
+    ast: Arc<rhai::AST>,
+    // Tick of the last lookup
+    used: AtomicU64,
+}
+
+// A Rhai engine shared across projections, compiling each custom formula only once
+pub struct ProjectionEngine {
+    engine: rhai::Engine,
+    formulas: RwLock<HashMap<String, CachedFormula>>,
+    capacity: usize,
+    clock: AtomicU64,
+}
+
+static SHARED: OnceLock<ProjectionEngine> = OnceLock::new();
+
+impl Default for ProjectionEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProjectionEngine {
+    pub fn new() -> Self {
+        Self::with_cache_capacity(MAX_CACHED_FORMULAS)
+    }
+
+    pub fn with_cache_capacity(capacity: usize) -> Self {
+        Self {
+            engine: formula_engine(),
+            formulas: RwLock::new(HashMap::new()),
+            capacity: capacity.max(1),
+            clock: AtomicU64::new(0),
+        }
+    }
+
+    // The process-wide engine, so formulas compiled for one request are reused by the next
+    pub fn shared() -> &'static Self {
+        SHARED.get_or_init(Self::new)
+    }
+
+    pub fn compiled_formulas(&self) -> usize {
+        self.formulas.read().unwrap().len()
+    }
+
+    fn compile(&self, formula: &str) -> Result<Arc<rhai::AST>, SpendingError> {
+        let tick = self.clock.fetch_add(1, Ordering::Relaxed);
+        if let Some(cached) = self.formulas.read().unwrap().get(formula) {
+            cached.used.store(tick, Ordering::Relaxed);
+            return Ok(cached.ast.clone());
+        }
+
+        let ast = self.engine.compile(formula)
+            .map_err(|e| SpendingError::FormulaError(e.to_string()))?;
+        let ast = Arc::new(ast);
+
+        let mut formulas = self.formulas.write().unwrap();
+        if formulas.len() >= self.capacity && !formulas.contains_key(formula) {
+            let oldest = formulas.iter()
+                .min_by_key(|(_, cached)| cached.used.load(Ordering::Relaxed))
+                .map(|(formula, _)| formula.clone());
+            if let Some(oldest) = oldest {
+                formulas.remove(&oldest);
+            }
+        }
+        formulas.insert(formula.to_string(), CachedFormula { ast: ast.clone(), used: AtomicU64::new(tick) });
+        Ok(ast)
+    }
+
+    pub fn yearly_spend(&self, project: &ProjectSpend, year: u32) -> Result<f64, SpendingError> {
+        match &project.growth_type {
+            GrowthType::Custom(formula) => {
+                let ast = self.compile(formula)?;
+                let mut scope = project.formula_scope(year);
+
+                let started = Instant::now();
+                let result = self.engine.eval_ast_with_scope::<f64>(&mut scope, &ast);
+                metrics().observe_formula(started.elapsed());
+
+                result.map_err(|e| SpendingError::FormulaError(e.to_string()))
+            }
+            _ => project.calculate_yearly_spend(year),
+        }
+    }
+
+    pub fn total_spend(&self, model: &UserModel) -> Result<HashMap<u32, f64>, SpendingError> {
+        let mut yearly_totals = HashMap::new();
+
+        for year in 0..model.projection_years {
+            let mut year_total = 0.0;
+
+            for project in &model.projects {
+                year_total += self.yearly_spend(project, year)?;
+            }
+
+            yearly_totals.insert(year, year_total);
+        }
+
+        Ok(yearly_totals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runaway_formula_is_stopped() {
+        let project = ProjectSpend::new(
+            "Loop".into(),
+            1.0,
+            0.0,
+            GrowthType::Custom("let x = 0; loop { x += 1; } base".into()),
+        ).unwrap();
+
+        let result = ProjectionEngine::new().yearly_spend(&project, 1);
+        assert!(matches!(result, Err(SpendingError::FormulaError(_))));
+    }
+
+    #[test]
+    fn test_formula_cache_drops_least_recently_used() {
+        let engine = ProjectionEngine::with_cache_capacity(2);
+        let formula = |body: &str| ProjectSpend::new(body.into(), 1.0, 0.0, GrowthType::Custom(body.into())).unwrap();
+        let (a, b, c) = (formula("base"), formula("base * 2.0"), formula("base * 3.0"));
+
+        engine.yearly_spend(&a, 0).unwrap();
+        engine.yearly_spend(&b, 0).unwrap();
+        engine.yearly_spend(&a, 0).unwrap();
+        engine.yearly_spend(&c, 0).unwrap();
+
+        assert_eq!(engine.compiled_formulas(), 2);
+        let formulas = engine.formulas.read().unwrap();
+        assert!(formulas.contains_key("base") && formulas.contains_key("base * 3.0"));
+    }
+}
@@ -0,0 +1,364 @@
+use actix_web::error::{JsonPayloadError, PathError, PayloadError, QueryPayloadError};
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use serde::Serialize;
+use utoipa::ToSchema;
+use std::fmt;
+use crate::coin_selection::SelectionError;
+use crate::escrow::EscrowError;
+use crate::escrow_script::{Role, ScriptError};
+use crate::import::ImportError;
+use crate::miniscript::MiniscriptError;
+use crate::psbt::PsbtError;
+use crate::release::ReleaseError;
+use crate::rpc::RpcError;
+use crate::spending::SpendingError;
+
+// The JSON body every failed API request returns
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ErrorBody {
+    #[schema(value_type = String, example = "not_found")]
+    pub code: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub details: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub body: ErrorBody,
+    // Seconds, sent as a Retry-After header
+    pub retry_after: Option<u64>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: ErrorBody {
+                code,
+                message: message.into(),
+                field: None,
+                details: None,
+            },
+            retry_after: None,
+        }
+    }
+
+    pub fn with_field(mut self, field: impl Into<String>) -> Self {
+        self.body.field = Some(field.into());
+        self
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.body.details = Some(details);
+        self
+    }
+
+    pub fn with_retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+
+    pub fn bad_request(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, code, message)
+    }
+
+    pub fn not_found(resource: &str, id: &str) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not_found", format!("{} {} not found", resource, id))
+            .with_details(serde_json::json!({ "resource": resource, "id": id }))
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", message)
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.body.code, self.body.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status);
+        if let Some(seconds) = self.retry_after {
+            response.insert_header((header::RETRY_AFTER, seconds));
+        }
+        response.json(&self.body)
+    }
+}
+
+impl From<SpendingError> for ApiError {
+    fn from(e: SpendingError) -> Self {
+        let message = e.to_string();
+        match e {
+            SpendingError::InvalidGrowthRate(_) => {
+                Self::bad_request("invalid_growth_rate", message).with_field("growth_rate")
+            }
+            SpendingError::FormulaError(_) => {
+                Self::new(StatusCode::UNPROCESSABLE_ENTITY, "formula_error", message).with_field("growth_type")
+            }
+            SpendingError::InvalidYears(_) => {
+                Self::bad_request("invalid_projection_years", message).with_field("projection_years")
+            }
+            SpendingError::InvalidBudget(_) => Self::bad_request("invalid_budget", message),
+            SpendingError::InvalidScenario(_) => Self::bad_request("invalid_scenario", message).with_field("scenario"),
+        }
+    }
+}
+
+impl From<ImportError> for ApiError {
+    fn from(e: ImportError) -> Self {
+        let message = e.to_string();
+        match e {
+            ImportError::Csv(_) => Self::bad_request("invalid_csv", message).with_field("csv"),
+            ImportError::MissingColumn(column) => {
+                Self::bad_request("missing_column", message).with_field("csv")
+                    .with_details(serde_json::json!({ "column": column }))
+            }
+            ImportError::InvalidDelimiter(_) => Self::bad_request("invalid_delimiter", message).with_field("csv_format.delimiter"),
+            ImportError::InvalidDate(_, line) => {
+                Self::bad_request("invalid_date", message).with_field("csv")
+                    .with_details(serde_json::json!({ "line": line }))
+            }
+            ImportError::InvalidAmount(_, line) => {
+                Self::bad_request("invalid_amount", message).with_field("csv")
+                    .with_details(serde_json::json!({ "line": line }))
+            }
+            ImportError::InvalidPattern(_) => Self::bad_request("invalid_rule", message).with_field("rules"),
+            ImportError::Spending(e) => e.into(),
+        }
+    }
+}
+
+impl From<EscrowError> for ApiError {
+    fn from(e: EscrowError) -> Self {
+        let message = e.to_string();
+        match e {
+            EscrowError::InvalidKey(field, _) => {
+                Self::bad_request("invalid_key", message).with_field(format!("escrow_input.{}", field))
+            }
+            EscrowError::Script(
+                ScriptError::MissingArbitrator(_)
+                | ScriptError::TimelockRecipientWithoutKey
+                | ScriptError::HashlockRecipientWithoutKey,
+            ) => {
+                Self::bad_request("missing_arbitrator", message).with_field("escrow_input.npub_arbitrator")
+            }
+            EscrowError::Script(ScriptError::DuplicateKey(_, role)) => {
+                let field = match role {
+                    Role::Party1 => "npub_1",
+                    Role::Party2 => "npub_2",
+                    Role::Arbitrator => "npub_arbitrator",
+                };
+                Self::bad_request("duplicate_key", message).with_field(format!("escrow_input.{}", field))
+            }
+            EscrowError::Script(ScriptError::InvalidTimelock(_)) => {
+                Self::bad_request("invalid_timelock", message).with_field("escrow_input.timelock_duration")
+            }
+            EscrowError::MissingTimelockRecipient => {
+                Self::bad_request("missing_timelock_recipient", message).with_field("escrow_input.timelock_recipient")
+            }
+            EscrowError::Script(ScriptError::InvalidHashlock(_)) => {
+                Self::bad_request("invalid_hashlock", message).with_field("escrow_input.hashlock")
+            }
+            EscrowError::MissingHashlockRecipient => {
+                Self::bad_request("missing_hashlock_recipient", message).with_field("escrow_input.hashlock_recipient")
+            }
+            EscrowError::NeedsTaproot(field) => {
+                Self::bad_request("needs_taproot", message).with_field(format!("escrow_input.{}", field))
+            }
+            EscrowError::InvalidTxid(_) => Self::bad_request("invalid_txid", message).with_field("utxos"),
+            EscrowError::InvalidAmount => Self::bad_request("invalid_amount", message).with_field("amount"),
+            EscrowError::InvalidFundingUtxo(_) => Self::bad_request("invalid_funding_utxo", message).with_field("utxos"),
+            EscrowError::InvalidChangeAddress(_) => Self::bad_request("invalid_change_address", message).with_field("change_address"),
+            EscrowError::Selection(SelectionError::FeeRateTooLow(_) | SelectionError::AbsurdFeeRate(_)) => {
+                Self::bad_request("invalid_fee_rate", message).with_field("fee_rate")
+            }
+            EscrowError::Selection(SelectionError::AbsurdFee(fee)) => {
+                Self::bad_request("absurd_fee", message).with_field("fee_rate").with_details(serde_json::json!({ "fee": fee }))
+            }
+            EscrowError::Selection(SelectionError::Dust(_, threshold)) => {
+                Self::bad_request("dust", message).with_field("amount").with_details(serde_json::json!({ "dust_limit": threshold }))
+            }
+            EscrowError::Selection(SelectionError::UnsupportedInput(_)) => {
+                Self::bad_request("unsupported_input", message).with_field("utxos")
+            }
+            EscrowError::Selection(SelectionError::InsufficientFunds { available, needed }) => {
+                Self::new(StatusCode::UNPROCESSABLE_ENTITY, "insufficient_funds", message)
+                    .with_details(serde_json::json!({ "available": available, "needed": needed }))
+            }
+            EscrowError::Selection(SelectionError::ChangeRequired(_)) => {
+                Self::bad_request("change_required", message).with_field("change_address")
+            }
+            EscrowError::InvalidPsbt(i, _) => Self::bad_request("invalid_psbt", message).with_field(format!("psbts[{}]", i)),
+            EscrowError::Psbt(PsbtError::Invalid(_) | PsbtError::Empty | PsbtError::WrongNetwork(..)) => {
+                Self::bad_request("invalid_psbt", message).with_field("psbts")
+            }
+            EscrowError::Psbt(PsbtError::DifferentTransaction(i)) => {
+                Self::bad_request("psbt_mismatch", message).with_field(format!("psbts[{}]", i))
+            }
+            EscrowError::Psbt(PsbtError::Incomplete(input, _)) => {
+                Self::new(StatusCode::UNPROCESSABLE_ENTITY, "incomplete_psbt", message)
+                    .with_details(serde_json::json!({ "input": input }))
+            }
+            EscrowError::InvalidPreimage(_) => Self::bad_request("invalid_preimage", message).with_field("release.preimage"),
+            EscrowError::Release(ReleaseError::PathUnavailable(_)) => Self::bad_request("path_unavailable", message).with_field("path"),
+            EscrowError::Release(ReleaseError::InvalidPayouts(_) | ReleaseError::WrongNetwork(..)) => {
+                Self::bad_request("invalid_payouts", message).with_field("payouts")
+            }
+            EscrowError::Release(ReleaseError::Dust(_, _, threshold)) => {
+                Self::bad_request("dust", message).with_field("payouts").with_details(serde_json::json!({ "dust_limit": threshold }))
+            }
+            EscrowError::Release(ReleaseError::AbsurdFee(_)) => Self::bad_request("absurd_fee", message).with_field("fee"),
+            EscrowError::Release(ReleaseError::WrongInput) => Self::bad_request("psbt_mismatch", message).with_field("psbts"),
+            EscrowError::Release(ReleaseError::MissingSignature(role)) => {
+                Self::new(StatusCode::UNPROCESSABLE_ENTITY, "incomplete_psbt", message)
+                    .with_details(serde_json::json!({ "role": role }))
+            }
+            EscrowError::Release(ReleaseError::MissingMuSig2Signature) => {
+                Self::new(StatusCode::UNPROCESSABLE_ENTITY, "missing_musig2_signature", message)
+            }
+            EscrowError::Release(ReleaseError::MissingPreimage) => {
+                Self::new(StatusCode::UNPROCESSABLE_ENTITY, "incomplete_psbt", message).with_field("release.preimage")
+            }
+            EscrowError::RpcUnavailable => Self::new(StatusCode::SERVICE_UNAVAILABLE, "rpc_unavailable", message),
+            EscrowError::Rpc(e) => e.into(),
+        }
+    }
+}
+
+impl From<MiniscriptError> for ApiError {
+    fn from(e: MiniscriptError) -> Self {
+        use miniscript::policy::concrete::PolicyError;
+
+        let message = e.to_string();
+        match e {
+            MiniscriptError::Syntax(_) => Self::bad_request("syntax_error", message),
+            MiniscriptError::InvalidKey(_) => Self::bad_request("invalid_key", message),
+            MiniscriptError::InvalidHash(..) => Self::bad_request("invalid_hash", message),
+            MiniscriptError::BadChecksum(_) => Self::bad_request("bad_checksum", message),
+            MiniscriptError::UnsupportedDescriptor(_) => Self::bad_request("unsupported_descriptor", message),
+            MiniscriptError::MissingInternalKey => Self::bad_request("missing_internal_key", message),
+            MiniscriptError::InvalidTapTree(_) => Self::bad_request("invalid_tap_tree", message),
+            MiniscriptError::TooManyPaths(limit) => {
+                Self::new(StatusCode::UNPROCESSABLE_ENTITY, "too_many_paths", message)
+                    .with_details(serde_json::json!({ "limit": limit }))
+            }
+            MiniscriptError::Miniscript(e) => match e {
+                miniscript::Error::TypeCheck(_) | miniscript::Error::NonTopLevel(_) | miniscript::Error::AnalysisError(_) => {
+                    Self::bad_request("type_error", message)
+                }
+                miniscript::Error::ContextError(_) | miniscript::Error::PubKeyCtxError(..) => {
+                    Self::bad_request("wrong_context", message)
+                }
+                miniscript::Error::PolicyError(PolicyError::IncorrectThresh) => Self::bad_request("invalid_threshold", message),
+                miniscript::Error::PolicyError(PolicyError::ZeroTime | PolicyError::TimeTooFar) => {
+                    Self::bad_request("invalid_timelock", message)
+                }
+                // The policy is well formed, but no safe, non-malleable script within the limits implements it
+                miniscript::Error::CompilerError(_) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "uncompilable_policy", message),
+                _ => Self::bad_request("syntax_error", message),
+            },
+        }
+    }
+}
+
+impl From<RpcError> for ApiError {
+    fn from(e: RpcError) -> Self {
+        let message = e.to_string();
+        match e {
+            RpcError::Rejected { code, .. } => {
+                Self::new(StatusCode::BAD_GATEWAY, "broadcast_rejected", message)
+                    .with_details(serde_json::json!({ "rpc_code": code }))
+            }
+            RpcError::WrongNetwork { .. } => Self::new(StatusCode::BAD_GATEWAY, "wrong_network", message),
+            _ => Self::new(StatusCode::BAD_GATEWAY, "rpc_error", message),
+        }
+    }
+}
+
+impl From<JsonPayloadError> for ApiError {
+    fn from(e: JsonPayloadError) -> Self {
+        match &e {
+            JsonPayloadError::OverflowKnownLength { length, limit } => {
+                Self::new(StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", e.to_string())
+                    .with_details(serde_json::json!({ "length": length, "limit": limit }))
+            }
+            JsonPayloadError::Overflow { limit } => {
+                Self::new(StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", e.to_string())
+                    .with_details(serde_json::json!({ "limit": limit }))
+            }
+            JsonPayloadError::ContentType => {
+                Self::new(StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported_media_type", "Expected a JSON request body")
+            }
+            JsonPayloadError::Deserialize(inner) => {
+                let mut error = Self::bad_request("invalid_json", inner.to_string())
+                    .with_details(serde_json::json!({ "line": inner.line(), "column": inner.column() }));
+                if let Some(field) = serde_field(&inner.to_string()) {
+                    error = error.with_field(field);
+                }
+                error
+            }
+            _ => Self::bad_request("invalid_json", e.to_string()),
+        }
+    }
+}
+
+impl From<PayloadError> for ApiError {
+    fn from(e: PayloadError) -> Self {
+        match e {
+            PayloadError::Overflow => Self::new(StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", e.to_string()),
+            _ => Self::bad_request("invalid_payload", e.to_string()),
+        }
+    }
+}
+
+impl From<PathError> for ApiError {
+    fn from(e: PathError) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "invalid_path", e.to_string())
+    }
+}
+
+impl From<QueryPayloadError> for ApiError {
+    fn from(e: QueryPayloadError) -> Self {
+        let mut error = Self::bad_request("invalid_query", e.to_string());
+        if let Some(field) = serde_field(&e.to_string()) {
+            error = error.with_field(field);
+        }
+        error
+    }
+}
+
+// serde names the offending field in backticks, e.g. "missing field `user_id`"
+fn serde_field(message: &str) -> Option<String> {
+    ["missing field `", "unknown field `", "duplicate field `"]
+        .iter()
+        .find_map(|prefix| {
+            let start = message.find(prefix)? + prefix.len();
+            let end = start + message[start..].find('`')?;
+            Some(message[start..end].to_string())
+        })
+}
+
+pub fn json_error_handler(e: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::from(e).into()
+}
+
+pub fn path_error_handler(e: PathError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::from(e).into()
+}
+
+pub fn query_error_handler(e: QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::from(e).into()
+}
@@ -0,0 +1,761 @@
+use actix_web::{web, HttpResponse};
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::{Address, Network, OutPoint, PackedLockTime, Script, Sequence, Transaction, TxIn, TxOut, Txid, Witness};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use thiserror::Error;
+use utoipa::ToSchema;
+use crate::audit::{AuditAction, AuditLog, Change, ResourceKind};
+use crate::auth::Nip98Auth;
+use crate::coin_selection::{self, Candidate, Selection, SelectionError, Strategy};
+use crate::error::{ApiError, ErrorBody};
+use crate::escrow_script::{
+    self, EscrowOutput, EscrowParties, EscrowScript, EscrowTemplate, Hashlock, OutputType, Role, ScriptError, SpendingPath, Timelock,
+    TimelockPath,
+};
+use crate::metrics::metrics;
+use crate::nostr::{NostrError, NostrPublicKey};
+use crate::psbt::{self, Psbt, PsbtError};
+use crate::release::{self, Payout, ReleaseError, ReleasePath};
+use crate::rpc::{BitcoinRpc, RpcError};
+use crate::taproot::TaprootSummary;
+
+#[derive(Debug, Error)]
+pub enum EscrowError {
+    #[error("Invalid {0}: {1}")]
+    InvalidKey(&'static str, NostrError),
+    #[error("Invalid funding txid: {0}")]
+    InvalidTxid(String),
+    #[error("Amount must be greater than 0")]
+    InvalidAmount,
+    #[error(transparent)]
+    Script(#[from] ScriptError),
+    #[error("timelock_recipient is required with a timelock")]
+    MissingTimelockRecipient,
+    #[error("hashlock_recipient is required with a hashlock")]
+    MissingHashlockRecipient,
+    #[error("{0} needs a taproot output")]
+    NeedsTaproot(&'static str),
+    #[error("Invalid funding output: {0}")]
+    InvalidFundingUtxo(String),
+    #[error("Invalid change address: {0}")]
+    InvalidChangeAddress(String),
+    #[error(transparent)]
+    Selection(#[from] SelectionError),
+    #[error("PSBT {0}: {1}")]
+    InvalidPsbt(usize, PsbtError),
+    #[error(transparent)]
+    Psbt(#[from] PsbtError),
+    #[error(transparent)]
+    Release(#[from] ReleaseError),
+    #[error("Invalid preimage: {0}")]
+    InvalidPreimage(String),
+    #[error("No Bitcoin node is configured")]
+    RpcUnavailable,
+    #[error(transparent)]
+    Rpc(#[from] RpcError),
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct EscrowInput {
+    pub npub_1: String,
+    pub npub_2: String,
+    // Required by templates B and C
+    #[serde(default)]
+    pub npub_arbitrator: Option<String>,
+    pub escrow_script: EscrowTemplate,
+    // Blocks after funding for a relative timelock, otherwise the height or unix time it opens at
+    #[serde(default)]
+    pub timelock_duration: Option<u32>,
+    #[serde(default)]
+    pub timelock_type: TimelockType,
+    // Who may spend alone once the timelock opens
+    #[serde(default)]
+    pub timelock_recipient: Option<Role>,
+    #[serde(default)]
+    pub output_type: OutputType,
+    // Taproot only: the parties cooperate through the key path, signing with MuSig2 under the
+    // BIP-327 aggregate of their two keys (sorted), which the server derives. The server runs no
+    // MuSig2 sessions: releasing that way needs an external MuSig2 signer to put the aggregate
+    // signature in the PSBT's tap_key_sig, otherwise the release fails with missing_musig2_signature
+    #[serde(default)]
+    pub musig_key_path: bool,
+    // Taproot only: SHA-256 digest whose preimage lets hashlock_recipient spend alone
+    #[serde(default)]
+    pub hashlock: Option<String>,
+    #[serde(default)]
+    pub hashlock_recipient: Option<Role>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TimelockType {
+    // CSV, counted from the funding transaction
+    #[default]
+    Relative,
+    // CLTV block height
+    Height,
+    // CLTV unix time
+    Time,
+}
+
+impl EscrowInput {
+    fn timelock(&self) -> Result<Option<TimelockPath>, EscrowError> {
+        let Some(value) = self.timelock_duration else {
+            return Ok(None);
+        };
+        let timelock = match self.timelock_type {
+            TimelockType::Relative => Timelock::Blocks(u16::try_from(value)
+                .map_err(|_| ScriptError::InvalidTimelock("a relative timelock is at most 65535 blocks".into()))?),
+            TimelockType::Height => Timelock::Height(value),
+            TimelockType::Time => Timelock::Time(value),
+        };
+        let recipient = self.timelock_recipient.ok_or(EscrowError::MissingTimelockRecipient)?;
+        Ok(Some(TimelockPath { timelock, recipient }))
+    }
+
+    // The keys of everyone named in the escrow: both parties and any arbitrator
+    pub fn parties(&self) -> Result<Vec<NostrPublicKey>, EscrowError> {
+        let mut parties = vec![parse_key("npub_1", &self.npub_1)?, parse_key("npub_2", &self.npub_2)?];
+        if let Some(arbitrator) = &self.npub_arbitrator {
+            parties.push(parse_key("npub_arbitrator", arbitrator)?);
+        }
+        Ok(parties)
+    }
+
+    // The escrow's script, address on `network` and spending paths
+    pub fn escrow(&self, network: Network) -> Result<EscrowScript, EscrowError> {
+        let parties = EscrowParties {
+            party_1: parse_key("npub_1", &self.npub_1)?,
+            party_2: parse_key("npub_2", &self.npub_2)?,
+            arbitrator: self.npub_arbitrator.as_deref()
+                .map(|key| parse_key("npub_arbitrator", key))
+                .transpose()?,
+        };
+
+        let timelock = self.timelock()?;
+        let hashlock = self.hashlock()?;
+        Ok(match self.output_type {
+            OutputType::P2wsh => {
+                if self.musig_key_path {
+                    return Err(EscrowError::NeedsTaproot("musig_key_path"));
+                }
+                if hashlock.is_some() {
+                    return Err(EscrowError::NeedsTaproot("hashlock"));
+                }
+                escrow_script::escrow_scripts(self.escrow_script, &parties, timelock, network)?
+            }
+            OutputType::Taproot => {
+                escrow_script::taproot_escrow(self.escrow_script, &parties, timelock, hashlock, self.musig_key_path, network)?
+            }
+        })
+    }
+
+    fn hashlock(&self) -> Result<Option<Hashlock>, EscrowError> {
+        let Some(hash) = &self.hashlock else {
+            return Ok(None);
+        };
+        let recipient = self.hashlock_recipient.ok_or(EscrowError::MissingHashlockRecipient)?;
+        Ok(Some(Hashlock { hash: hash.to_lowercase(), recipient }))
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateEscrowTxInput {
+    pub escrow_input: EscrowInput,
+    pub amount: u64,  // Amount in satoshis
+    // Outputs the escrow may be funded from; coin selection picks among them
+    pub utxos: Vec<FundingUtxo>,
+    // Target fee rate in sat/vB
+    pub fee_rate: f64,
+    // Where any change goes; without one, only selections that need no change are accepted
+    #[serde(default)]
+    pub change_address: Option<String>,
+    #[serde(default)]
+    pub coin_selection: Strategy,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct FundingUtxo {
+    pub txid: String,
+    pub vout: u32,
+    // Hex; P2WPKH or taproot, so the spend's size can be estimated
+    pub script_pubkey: String,
+    pub value: u64,
+}
+
+// What the audit log keeps about a created escrow
+#[derive(Serialize)]
+struct EscrowRecord<'a> {
+    template: EscrowTemplate,
+    timelock: Option<TimelockPath>,
+    npub_1: &'a str,
+    npub_2: &'a str,
+    npub_arbitrator: Option<&'a str>,
+    network: &'a str,
+    funding_inputs: Vec<String>,
+    amount: u64,
+    fee: u64,
+    address: &'a str,
+    output_type: OutputType,
+    witness_script: Option<&'a str>,
+    hashlock: Option<&'a Hashlock>,
+    taproot: Option<&'a TaprootSummary>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CreateEscrowTxOutput {
+    // Unsigned BIP-174 PSBT, base64; each party signs it and sends it back to /broadcast_escrow_tx
+    pub psbt: String,
+    // The funding txid, which signing leaves unchanged as long as the funding input is segwit
+    pub txid: String,
+    // The outpoints coin selection spent
+    pub inputs: Vec<String>,
+    // Change back to change_address, if any was worth creating
+    pub change: Option<u64>,
+    pub fee: u64,
+    // Estimated size once signed, which the fee is based on
+    pub vsize: u64,
+    // The network the address and PSBT are for
+    pub network: String,
+    pub address: String,
+    pub output_type: OutputType,
+    // P2WSH only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub witness_script: Option<String>,
+    // Taproot only: the keys, leaf scripts and control blocks needed to spend each path
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub taproot: Option<TaprootSummary>,
+    pub template: EscrowTemplate,
+    pub timelock: Option<TimelockPath>,
+    pub hashlock: Option<Hashlock>,
+    pub spending_paths: Vec<SpendingPath>,
+}
+
+fn parse_key(field: &'static str, value: &str) -> Result<NostrPublicKey, EscrowError> {
+    NostrPublicKey::from_str(value).map_err(|e| EscrowError::InvalidKey(field, e))
+}
+
+fn candidate(utxo: &FundingUtxo) -> Result<Candidate, EscrowError> {
+    let txid = Txid::from_str(&utxo.txid).map_err(|e| EscrowError::InvalidTxid(e.to_string()))?;
+    let script_pubkey = Script::from_str(&utxo.script_pubkey)
+        .map_err(|e| EscrowError::InvalidFundingUtxo(e.to_string()))?;
+    Ok(Candidate {
+        outpoint: OutPoint { txid, vout: utxo.vout },
+        txout: TxOut { value: utxo.value, script_pubkey },
+    })
+}
+
+// The escrow's script and address, plus the unsigned PSBT paying `amount` into it from the
+// selected inputs, with any change
+pub fn build_funding_tx(input: &CreateEscrowTxInput, network: Network) -> Result<(EscrowScript, Psbt, Selection), EscrowError> {
+    let escrow = input.escrow_input.escrow(network)?;
+    if input.amount == 0 {
+        return Err(EscrowError::InvalidAmount);
+    }
+    let candidates = input.utxos.iter().map(candidate).collect::<Result<Vec<_>, _>>()?;
+    let change_script = input.change_address.as_deref()
+        .map(|address| {
+            let address = Address::from_str(address).map_err(|e| EscrowError::InvalidChangeAddress(e.to_string()))?;
+            if !address.is_valid_for_network(network) {
+                return Err(EscrowError::InvalidChangeAddress(format!("{} is not a {} address", address, network)));
+            }
+            Ok(address.script_pubkey())
+        })
+        .transpose()?;
+
+    let escrow_output = TxOut { value: input.amount, script_pubkey: escrow.address.script_pubkey() };
+    let selection = coin_selection::select(
+        &candidates, std::slice::from_ref(&escrow_output), change_script.as_ref(), input.fee_rate, input.coin_selection,
+    )?;
+
+    let tx = Transaction {
+        version: 2,
+        lock_time: PackedLockTime::ZERO,
+        input: selection.inputs.iter()
+            .map(|input| TxIn {
+                previous_output: input.outpoint,
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: [escrow_output].into_iter().chain(selection.change.clone()).collect(),
+    };
+
+    let mut psbt = Psbt::from_unsigned_tx(tx).expect("the funding transaction is built unsigned");
+    psbt::set_network(&mut psbt, network);
+    // Lets signers check the amounts and sign segwit inputs without a lookup
+    for (psbt_input, selected) in psbt.inputs.iter_mut().zip(&selection.inputs) {
+        psbt_input.witness_utxo = Some(selected.txout.clone());
+    }
+    // Lets signers recognise the escrow output
+    match escrow.output {
+        EscrowOutput::P2wsh { ref witness_script } => psbt.outputs[0].witness_script = Some(witness_script.clone()),
+        EscrowOutput::Taproot(ref tree) => {
+            psbt.outputs[0].tap_internal_key = Some(tree.internal_key());
+            psbt.outputs[0].tap_tree = tree.psbt_tap_tree();
+        }
+    }
+
+    Ok((escrow, psbt, selection))
+}
+
+#[utoipa::path(
+    post,
+    path = "/create_escrow_tx",
+    request_body = CreateEscrowTxInput,
+    responses(
+        (status = 200, description = "Escrow address and the unsigned funding PSBT", body = CreateEscrowTxOutput),
+        (status = 400, description = "Invalid keys, funding outputs, amount, fee rate or change address", body = ErrorBody),
+        (status = 422, description = "The funding outputs cannot cover the amount and fee", body = ErrorBody),
+        (status = 401, description = "Missing or invalid NIP-98 authorization", body = ErrorBody),
+        (status = 403, description = "The signer is not one of the escrow's parties", body = ErrorBody),
+    )
+)]
+// Builds the escrow and its funding PSBT; signing happens in the parties' wallets
+pub async fn create_escrow_tx(
+    audit: web::Data<AuditLog>,
+    network: web::Data<Network>,
+    auth: Nip98Auth,
+    input: web::Json<CreateEscrowTxInput>,
+) -> Result<HttpResponse, ApiError> {
+    // The entry is filed under each party's key, so only a party may create it; checked before
+    // any work is done for the request
+    let parties = input.escrow_input.parties()?;
+    auth.authorize_party(&parties)?;
+    let (escrow, psbt, selection) = build_funding_tx(&input, **network)?;
+    let escrow_input = &input.escrow_input;
+
+    let output = CreateEscrowTxOutput {
+        psbt: psbt::encode(&psbt),
+        txid: psbt.unsigned_tx.txid().to_string(),
+        inputs: selection.inputs.iter().map(|input| input.outpoint.to_string()).collect(),
+        change: selection.change.map(|change| change.value),
+        fee: selection.fee,
+        vsize: selection.vsize,
+        network: network.to_string(),
+        address: escrow.address.to_string(),
+        output_type: escrow.output_type(),
+        witness_script: escrow.witness_script().map(|script| script.to_hex()),
+        taproot: escrow.taproot().map(|tree| tree.summary()),
+        template: escrow.template,
+        timelock: escrow.timelock,
+        hashlock: escrow.hashlock,
+        spending_paths: escrow.spending_paths,
+    };
+    let record = EscrowRecord {
+        template: output.template,
+        timelock: output.timelock,
+        npub_1: &escrow_input.npub_1,
+        npub_2: &escrow_input.npub_2,
+        npub_arbitrator: escrow_input.npub_arbitrator.as_deref(),
+        network: &output.network,
+        funding_inputs: output.inputs.clone(),
+        amount: input.amount,
+        fee: output.fee,
+        address: &output.address,
+        output_type: output.output_type,
+        witness_script: output.witness_script.as_deref(),
+        hashlock: output.hashlock.as_ref(),
+        taproot: output.taproot.as_ref(),
+    };
+    // Each party can find the entry under their own key
+    let change = parties.iter()
+        .fold(Change::new(ResourceKind::Escrow, &output.txid, None, Some(&record)), |change, key| change.subject(key.to_hex()));
+    audit.record(auth.pubkey(), change);
+
+    Ok(HttpResponse::Ok().json(output))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BroadcastEscrowTxInput {
+    // Base64 PSBTs of the same transaction, as signed by each party
+    pub psbts: Vec<String>,
+    // For transactions spending an escrow: which escrow and path, so its witness can be assembled
+    #[serde(default)]
+    pub release: Option<ReleaseContext>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ReleaseContext {
+    pub escrow_input: EscrowInput,
+    pub path: ReleasePath,
+    // Hex; the hashlock preimage, unless a PSBT already carries it
+    #[serde(default)]
+    pub preimage: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BroadcastEscrowTxOutput {
+    pub txid: String,
+}
+
+// Decodes the parties' PSBTs of one transaction and combines them
+pub fn combine_psbts(psbts: &[String], network: Network) -> Result<Psbt, EscrowError> {
+    let psbts = psbts.iter().enumerate()
+        .map(|(i, encoded)| {
+            let psbt = psbt::decode(encoded).and_then(|psbt| psbt::check_network(&psbt, network).map(|()| psbt));
+            psbt.map_err(|e| EscrowError::InvalidPsbt(i, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(psbt::combine(psbts)?)
+}
+
+// Combines the parties' PSBTs and finalizes every input, yielding the transaction to broadcast
+pub fn finalize_psbts(psbts: &[String], release: Option<&ReleaseContext>, network: Network) -> Result<Transaction, EscrowError> {
+    finalize_combined(combine_psbts(psbts, network)?, release, network)
+}
+
+fn finalize_combined(mut combined: Psbt, release: Option<&ReleaseContext>, network: Network) -> Result<Transaction, EscrowError> {
+    if let Some(release) = release {
+        let escrow = release.escrow_input.escrow(network)?;
+        if let Some(preimage) = &release.preimage {
+            let preimage = hex::decode(preimage).map_err(|e| EscrowError::InvalidPreimage(e.to_string()))?;
+            for input in &mut combined.inputs {
+                input.sha256_preimages.insert(sha256::Hash::hash(&preimage), preimage.clone());
+            }
+        }
+        release::finalize_release(&escrow, release.path, &mut combined)?;
+    }
+    Ok(psbt::extract(combined)?)
+}
+
+#[utoipa::path(
+    post,
+    path = "/broadcast_escrow_tx",
+    request_body = BroadcastEscrowTxInput,
+    responses(
+        (status = 200, description = "Transaction broadcast", body = BroadcastEscrowTxOutput),
+        (status = 400, description = "Invalid PSBTs, PSBTs of different transactions or for another network", body = ErrorBody),
+        (status = 422, description = "An input is still missing signatures", body = ErrorBody),
+        (status = 502, description = "The Bitcoin node rejected or failed the broadcast, or is on another network", body = ErrorBody),
+        (status = 503, description = "No Bitcoin node is configured", body = ErrorBody),
+        (status = 401, description = "Missing or invalid NIP-98 authorization", body = ErrorBody),
+        (status = 403, description = "The signer is not one of the escrow's parties", body = ErrorBody),
+    )
+)]
+// Combines and finalizes the signed PSBTs, then broadcasts the transaction through the configured node
+pub async fn broadcast_escrow_tx(
+    rpc: web::Data<Option<BitcoinRpc>>,
+    network: web::Data<Network>,
+    audit: web::Data<AuditLog>,
+    auth: Nip98Auth,
+    input: web::Json<BroadcastEscrowTxInput>,
+) -> Result<HttpResponse, ApiError> {
+    let combined = combine_psbts(&input.psbts, **network)?;
+    // A release names the escrow it spends, which finalizing checks against the PSBT. A funding
+    // transaction is matched by txid to the escrow created for it, whose entry lists the parties.
+    let parties = match &input.release {
+        Some(release) => release.escrow_input.parties()?,
+        None => funding_parties(&audit, &combined.unsigned_tx.txid()),
+    };
+    auth.authorize_party(&parties)?;
+    let tx = finalize_combined(combined, input.release.as_ref(), **network)?;
+    let rpc = rpc.get_ref().clone().ok_or(EscrowError::RpcUnavailable)?;
+
+    // Checked on every broadcast, so a node switched to another chain cannot receive it
+    let result = web::block(move || rpc.check_network().and_then(|_| rpc.send_raw_transaction(&tx)))
+        .await
+        .map_err(|e| ApiError::internal(e.to_string()))?;
+    metrics().record_broadcast(result.is_ok());
+    let txid = result.map_err(EscrowError::from)?;
+
+    Ok(HttpResponse::Ok().json(BroadcastEscrowTxOutput { txid: txid.to_string() }))
+}
+
+// The parties of the escrow `txid` funds, as recorded when it was created; none for other transactions
+fn funding_parties(audit: &AuditLog, txid: &Txid) -> Vec<NostrPublicKey> {
+    let txid = txid.to_string();
+    audit.query(|entry| entry.resource == ResourceKind::Escrow && entry.action == AuditAction::Create && entry.resource_id == txid)
+        .iter()
+        .flat_map(|entry| &entry.subjects)
+        .filter_map(|subject| NostrPublicKey::from_hex(subject).ok())
+        .collect()
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PayoutInput {
+    pub address: String,
+    // Satoshis; leave out on one payout to give it what remains after the others and the fee
+    #[serde(default)]
+    pub amount: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ReleaseEscrowTxInput {
+    pub escrow_input: EscrowInput,
+    // The escrow output being spent
+    pub escrow_txid: String,
+    pub escrow_vout: u32,
+    pub amount: u64,
+    pub path: ReleasePath,
+    pub payouts: Vec<PayoutInput>,
+    pub fee: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReleaseEscrowTxOutput {
+    // Unsigned BIP-174 PSBT, base64; the path's signers sign it and send it to /broadcast_escrow_tx
+    pub psbt: String,
+    pub spending_path: SpendingPath,
+    pub sequence: u32,
+    pub lock_time: u32,
+}
+
+// The unsigned PSBT spending an escrow through one of its paths
+pub fn build_release_tx(input: &ReleaseEscrowTxInput, network: Network) -> Result<(SpendingPath, Psbt), EscrowError> {
+    let escrow = input.escrow_input.escrow(network)?;
+    let escrow_txid = Txid::from_str(&input.escrow_txid)
+        .map_err(|e| EscrowError::InvalidTxid(e.to_string()))?;
+    let payouts = input.payouts.iter()
+        .map(|payout| {
+            let address = Address::from_str(&payout.address)
+                .map_err(|e| ReleaseError::InvalidPayouts(format!("{}: {}", payout.address, e)))?;
+            Ok(Payout { address, amount: payout.amount })
+        })
+        .collect::<Result<Vec<_>, ReleaseError>>()?;
+
+    let outpoint = OutPoint { txid: escrow_txid, vout: input.escrow_vout };
+    let mut psbt = release::build_release(&escrow, input.path, outpoint, input.amount, &payouts, input.fee, network)?;
+    psbt::set_network(&mut psbt, network);
+    Ok((escrow.path(input.path)?.clone(), psbt))
+}
+
+#[utoipa::path(
+    post,
+    path = "/release_escrow_tx",
+    request_body = ReleaseEscrowTxInput,
+    responses(
+        (status = 200, description = "The unsigned release PSBT", body = ReleaseEscrowTxOutput),
+        (status = 400, description = "Invalid escrow, txid or payouts, or a path the escrow does not have", body = ErrorBody),
+        (status = 401, description = "Missing or invalid NIP-98 authorization", body = ErrorBody),
+        (status = 403, description = "The signer is not one of the escrow's parties", body = ErrorBody),
+    )
+)]
+// Builds the transaction releasing an escrow through the chosen path, to be signed by its signers
+pub async fn release_escrow_tx(
+    network: web::Data<Network>,
+    auth: Nip98Auth,
+    input: web::Json<ReleaseEscrowTxInput>,
+) -> Result<HttpResponse, ApiError> {
+    auth.authorize_party(&input.escrow_input.parties()?)?;
+    let (spending_path, psbt) = build_release_tx(&input, **network)?;
+    Ok(HttpResponse::Ok().json(ReleaseEscrowTxOutput {
+        sequence: psbt.unsigned_tx.input[0].sequence.0,
+        lock_time: psbt.unsigned_tx.lock_time.to_u32(),
+        psbt: psbt::encode(&psbt),
+        spending_path,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_1: &str = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const KEY_2: &str = "c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+    const KEY_3: &str = "f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9";
+    // P2WPKH of secret key 1
+    const FUNDING_SCRIPT: &str = "0014751e76e8199196d454941c45d1b3a323f1433bd6";
+    const CHANGE_ADDRESS: &str = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+
+    fn utxo(vout: u32, value: u64) -> FundingUtxo {
+        FundingUtxo {
+            txid: "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b".into(),
+            vout,
+            script_pubkey: FUNDING_SCRIPT.into(),
+            value,
+        }
+    }
+
+    fn input() -> CreateEscrowTxInput {
+        CreateEscrowTxInput {
+            escrow_input: EscrowInput {
+                npub_1: KEY_1.into(),
+                npub_2: KEY_2.into(),
+                npub_arbitrator: Some(KEY_3.into()),
+                escrow_script: EscrowTemplate::B,
+                timelock_duration: None,
+                timelock_type: TimelockType::Relative,
+                timelock_recipient: None,
+                output_type: OutputType::P2wsh,
+                musig_key_path: false,
+                hashlock: None,
+                hashlock_recipient: None,
+            },
+            amount: 50_000,
+            utxos: vec![utxo(0, 30_000), utxo(1, 60_000)],
+            fee_rate: 2.0,
+            change_address: Some(CHANGE_ADDRESS.into()),
+            coin_selection: Strategy::BranchAndBound,
+        }
+    }
+
+    #[test]
+    fn test_funding_tx_pays_the_escrow() {
+        use crate::nostr::NostrSecretKey;
+        use crate::signer::LocalSigner;
+
+        let (escrow, mut psbt, selection) = build_funding_tx(&input(), Network::Bitcoin).unwrap();
+        let tx = psbt.unsigned_tx.clone();
+
+        let expected = format!("2102{}ad512102{}2102{}52ae", KEY_1, KEY_2, KEY_3);
+        assert_eq!(escrow.witness_script().unwrap().to_hex(), expected);
+        assert_eq!(tx.output[0].script_pubkey, escrow.address.script_pubkey());
+        assert_eq!(tx.output[0].value, 50_000);
+        assert_eq!(tx.input[0].previous_output.vout, 1);
+        assert_eq!(psbt.inputs[0].witness_utxo.as_ref().unwrap().script_pubkey.to_hex(), FUNDING_SCRIPT);
+        assert_eq!(psbt.outputs[0].witness_script.as_ref(), escrow.witness_script());
+
+        // Nothing is burned: the inputs pay the escrow, the change and the fee
+        let change = tx.output[1].value;
+        assert_eq!(Some(change), selection.change.map(|change| change.value));
+        assert_eq!(60_000, 50_000 + change + selection.fee);
+        assert_eq!(selection.fee, selection.vsize * 2);
+
+        // The estimate holds once the input is signed
+        let secret = NostrSecretKey::from_str(&format!("{:064x}", 1)).unwrap();
+        LocalSigner::from_nostr(&secret).sign_psbt(&mut psbt).unwrap();
+        let signed = psbt::extract(psbt).unwrap();
+        assert_eq!(signed.txid(), tx.txid());
+        assert!(signed.vsize() as u64 <= selection.vsize && selection.vsize - (signed.vsize() as u64) <= 1);
+    }
+
+    #[test]
+    fn test_funding_needs_enough_at_a_sane_fee_rate() {
+        let mut input = input();
+        input.amount = 95_000;
+        assert!(matches!(build_funding_tx(&input, Network::Bitcoin), Err(EscrowError::Selection(SelectionError::InsufficientFunds { .. }))));
+
+        input.amount = 80_000;
+        let (_, psbt, _) = build_funding_tx(&input, Network::Bitcoin).unwrap();
+        assert_eq!(psbt.unsigned_tx.input.len(), 2);
+
+        input.fee_rate = 2_000.0;
+        assert!(matches!(build_funding_tx(&input, Network::Bitcoin), Err(EscrowError::Selection(SelectionError::AbsurdFeeRate(_)))));
+
+        input.fee_rate = 2.0;
+        input.change_address = Some("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx".into());
+        assert!(matches!(build_funding_tx(&input, Network::Bitcoin), Err(EscrowError::InvalidChangeAddress(_))));
+
+        input.change_address = None;
+        input.utxos[0].script_pubkey = "76a914751e76e8199196d454941c45d1b3a323f1433bd688ac".into();
+        assert!(matches!(build_funding_tx(&input, Network::Bitcoin), Err(EscrowError::Selection(SelectionError::UnsupportedInput(_)))));
+    }
+
+    #[test]
+    fn test_testnet_escrows_stay_on_testnet() {
+        let mut input = input();
+        assert!(matches!(build_funding_tx(&input, Network::Testnet), Err(EscrowError::InvalidChangeAddress(_))));
+
+        input.change_address = Some("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx".into());
+        let (escrow, psbt, _) = build_funding_tx(&input, Network::Testnet).unwrap();
+        assert!(escrow.address.to_string().starts_with("tb1q"));
+
+        // Signet shares testnet's tb1 addresses
+        let (escrow, _, _) = build_funding_tx(&input, Network::Signet).unwrap();
+        assert!(escrow.address.to_string().starts_with("tb1q"));
+
+        let encoded = [psbt::encode(&psbt)];
+        assert!(matches!(
+            finalize_psbts(&encoded, None, Network::Bitcoin),
+            Err(EscrowError::InvalidPsbt(0, PsbtError::WrongNetwork(_, Network::Bitcoin))),
+        ));
+        // On its own network it only lacks signatures
+        assert!(matches!(finalize_psbts(&encoded, None, Network::Testnet), Err(EscrowError::Psbt(PsbtError::Incomplete(0, _)))));
+    }
+
+    #[test]
+    fn test_invalid_key_names_the_field() {
+        let mut input = input();
+        input.escrow_input.npub_2 = "not-a-key".into();
+        assert!(matches!(build_funding_tx(&input, Network::Bitcoin), Err(EscrowError::InvalidKey("npub_2", _))));
+
+        input.escrow_input.npub_2 = KEY_2.into();
+        input.escrow_input.npub_arbitrator = None;
+        assert!(matches!(build_funding_tx(&input, Network::Bitcoin), Err(EscrowError::Script(ScriptError::MissingArbitrator(_)))));
+    }
+
+    #[test]
+    fn test_timelock_fields_become_a_fallback_path() {
+        let mut input = input();
+        input.escrow_input.timelock_duration = Some(144);
+        assert!(matches!(build_funding_tx(&input, Network::Bitcoin), Err(EscrowError::MissingTimelockRecipient)));
+
+        input.escrow_input.timelock_recipient = Some(Role::Arbitrator);
+        let (escrow, _, _) = build_funding_tx(&input, Network::Bitcoin).unwrap();
+        assert_eq!(escrow.timelock.unwrap().timelock, Timelock::Blocks(144));
+        assert_eq!(escrow.address.to_string(), "bc1qhe9qzkjhy9xrkajcu5sy6xvp87vkttnp5h3nr8le204m92etyszs6jq4z3");
+
+        input.escrow_input.timelock_duration = Some(70_000);
+        assert!(matches!(build_funding_tx(&input, Network::Bitcoin), Err(EscrowError::Script(ScriptError::InvalidTimelock(_)))));
+    }
+
+    #[test]
+    fn test_taproot_output_type() {
+        let mut input = input();
+        input.escrow_input.hashlock = Some("66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925".into());
+        input.escrow_input.hashlock_recipient = Some(Role::Party2);
+        assert!(matches!(build_funding_tx(&input, Network::Bitcoin), Err(EscrowError::NeedsTaproot("hashlock"))));
+
+        input.escrow_input.output_type = OutputType::Taproot;
+        let (escrow, psbt, _) = build_funding_tx(&input, Network::Bitcoin).unwrap();
+        assert_eq!(psbt.unsigned_tx.output[0].script_pubkey, escrow.address.script_pubkey());
+        assert!(escrow.address.script_pubkey().is_v1_p2tr());
+        let leaves: Vec<Option<usize>> = escrow.spending_paths.iter().map(|path| path.leaf).collect();
+        assert_eq!(leaves, [Some(0), Some(1), Some(2)]);
+        // Signers can rebuild the output key from the PSBT output alone
+        let output = &psbt.outputs[0];
+        let secp = bitcoin::secp256k1::Secp256k1::verification_only();
+        let rebuilt = output.tap_tree.clone().unwrap().into_builder().finalize(&secp, output.tap_internal_key.unwrap()).unwrap();
+        assert_eq!(Script::new_v1_p2tr_tweaked(rebuilt.output_key()), escrow.address.script_pubkey());
+
+        input.escrow_input.hashlock = None;
+        input.escrow_input.timelock_duration = Some(144);
+        input.escrow_input.timelock_recipient = Some(Role::Arbitrator);
+        let (escrow, _, _) = build_funding_tx(&input, Network::Bitcoin).unwrap();
+        assert_eq!(escrow.address.to_string(), "bc1pf34v5ju3g5lwdyr8vuyc4w6gtpcrt42crz9tvrdwdkkps9sq84wq4s2tka");
+    }
+
+    #[test]
+    fn test_release_psbts_finalize_with_their_escrow() {
+        use crate::nostr::NostrSecretKey;
+        use crate::signer::LocalSigner;
+
+        let mut release = ReleaseEscrowTxInput {
+            escrow_input: input().escrow_input,
+            escrow_txid: "ab".repeat(32),
+            escrow_vout: 0,
+            amount: 50_000,
+            path: ReleasePath::Timeout,
+            payouts: vec![PayoutInput { address: "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4".into(), amount: None }],
+            fee: 1_000,
+        };
+        assert!(matches!(build_release_tx(&release, Network::Bitcoin), Err(EscrowError::Release(ReleaseError::PathUnavailable(_)))));
+
+        release.path = ReleasePath::Arbitrated;
+        let (path, unsigned) = build_release_tx(&release, Network::Bitcoin).unwrap();
+        assert_eq!(path.signers, [Role::Party1, Role::Arbitrator]);
+
+        // Each signer signs their own copy
+        let signed: Vec<String> = [1, 3].iter()
+            .map(|secret| {
+                let mut psbt = unsigned.clone();
+                let key = NostrSecretKey::from_str(&format!("{:064x}", secret)).unwrap();
+                LocalSigner::from_nostr(&key).sign_psbt(&mut psbt).unwrap();
+                psbt::encode(&psbt)
+            })
+            .collect();
+        let context = ReleaseContext { escrow_input: input().escrow_input, path: ReleasePath::Arbitrated, preimage: None };
+        assert!(matches!(finalize_psbts(&signed, None, Network::Bitcoin), Err(EscrowError::Psbt(PsbtError::Incomplete(0, _)))));
+        assert!(matches!(
+            finalize_psbts(&signed[..1], Some(&context), Network::Bitcoin),
+            Err(EscrowError::Release(ReleaseError::MissingSignature(Role::Arbitrator))),
+        ));
+        let tx = finalize_psbts(&signed, Some(&context), Network::Bitcoin).unwrap();
+        assert_eq!(tx.output[0].value, 49_000);
+        assert_eq!(tx.input[0].witness.len(), 4);
+    }
+}
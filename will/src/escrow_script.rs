@@ -0,0 +1,641 @@
+use bitcoin::blockdata::locktime::LOCK_TIME_THRESHOLD;
+use bitcoin::blockdata::opcodes::all::{
+    OP_CHECKMULTISIG, OP_CHECKSIG, OP_CHECKSIGVERIFY, OP_CLTV, OP_CSV, OP_DROP, OP_ELSE, OP_ENDIF, OP_EQUALVERIFY,
+    OP_IF, OP_SHA256, OP_SIZE,
+};
+use bitcoin::blockdata::script::Builder;
+use bitcoin::{Address, Network, Script};
+use chrono::{TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use thiserror::Error;
+use utoipa::ToSchema;
+use crate::nostr::NostrPublicKey;
+use crate::taproot::{self, TaprootTree};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ScriptError {
+    #[error("Template {0:?} needs an arbitrator key")]
+    MissingArbitrator(EscrowTemplate),
+    #[error("The arbitrator cannot receive the timelocked funds without an arbitrator key")]
+    TimelockRecipientWithoutKey,
+    #[error("The arbitrator cannot receive the hashlocked funds without an arbitrator key")]
+    HashlockRecipientWithoutKey,
+    #[error("{0} and {1} use the same key")]
+    DuplicateKey(Role, Role),
+    #[error("Invalid timelock: {0}")]
+    InvalidTimelock(String),
+    #[error("Invalid hashlock: {0}")]
+    InvalidHashlock(String),
+}
+
+// The escrow templates the front end offers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum EscrowTemplate {
+    // 2-of-2: both parties must sign
+    A,
+    // 2-of-3 with Party 1: Party 1 signs together with Party 2 or the arbitrator
+    B,
+    // 2-of-3 with Party 2: Party 2 signs together with Party 1 or the arbitrator
+    C,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum Role {
+    #[serde(rename = "party_1")]
+    Party1,
+    #[serde(rename = "party_2")]
+    Party2,
+    #[serde(rename = "arbitrator")]
+    Arbitrator,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Party1 => write!(f, "Party 1"),
+            Role::Party2 => write!(f, "Party 2"),
+            Role::Arbitrator => write!(f, "the arbitrator"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum Timelock {
+    // Relative (CSV): this many blocks after the funding transaction confirms
+    Blocks(u16),
+    // Absolute (CLTV): from this block height
+    Height(u32),
+    // Absolute (CLTV): from this unix time
+    Time(u32),
+}
+
+impl Timelock {
+    fn validate(&self) -> Result<(), ScriptError> {
+        match *self {
+            Timelock::Blocks(0) => Err(ScriptError::InvalidTimelock("a relative timelock needs at least 1 block".into())),
+            Timelock::Height(height) if height == 0 || height >= LOCK_TIME_THRESHOLD => {
+                Err(ScriptError::InvalidTimelock(format!("block heights run from 1 to {}", LOCK_TIME_THRESHOLD - 1)))
+            }
+            Timelock::Time(time) if time < LOCK_TIME_THRESHOLD => {
+                Err(ScriptError::InvalidTimelock(format!("lock times are unix times from {}", LOCK_TIME_THRESHOLD)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn push(&self, builder: Builder) -> Builder {
+        match *self {
+            Timelock::Blocks(blocks) => builder.push_int(blocks.into()).push_opcode(OP_CSV),
+            Timelock::Height(value) | Timelock::Time(value) => builder.push_int(value.into()).push_opcode(OP_CLTV),
+        }
+    }
+}
+
+impl fmt::Display for Timelock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Timelock::Blocks(blocks) => write!(f, "{} blocks after the escrow is funded", blocks),
+            Timelock::Height(height) => write!(f, "from block {}", height),
+            Timelock::Time(time) => match Utc.timestamp_opt(time.into(), 0).single() {
+                Some(at) => write!(f, "from {}", at.to_rfc3339()),
+                None => write!(f, "from unix time {}", time),
+            },
+        }
+    }
+}
+
+// A fallback path: once the timelock opens, `recipient` can spend alone
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TimelockPath {
+    pub timelock: Timelock,
+    pub recipient: Role,
+}
+
+#[derive(Debug, Clone)]
+pub struct EscrowParties {
+    pub party_1: NostrPublicKey,
+    pub party_2: NostrPublicKey,
+    // Used by templates B and C, and by fallbacks paying the arbitrator
+    pub arbitrator: Option<NostrPublicKey>,
+}
+
+impl EscrowParties {
+    pub fn key(&self, role: Role) -> Option<&NostrPublicKey> {
+        match role {
+            Role::Party1 => Some(&self.party_1),
+            Role::Party2 => Some(&self.party_2),
+            Role::Arbitrator => self.arbitrator.as_ref(),
+        }
+    }
+}
+
+// Lets `recipient` spend alone by revealing the preimage of `hash`, such as a payment secret
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Hashlock {
+    // SHA-256 digest as hex
+    pub hash: String,
+    pub recipient: Role,
+}
+
+impl Hashlock {
+    pub fn digest(&self) -> Result<[u8; 32], ScriptError> {
+        hex::decode(&self.hash).ok()
+            .and_then(|digest| digest.try_into().ok())
+            .ok_or_else(|| ScriptError::InvalidHashlock(format!("expected a SHA-256 digest as 64 hex characters, found {:?}", self.hash)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputType {
+    #[default]
+    P2wsh,
+    Taproot,
+}
+
+// One way the escrow can be spent
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct SpendingPath {
+    // Every listed role must sign
+    pub signers: Vec<Role>,
+    // When set, the path only opens once the timelock has passed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timelock: Option<Timelock>,
+    // When set, the spender must also reveal the preimage of this SHA-256 digest
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hashlock: Option<String>,
+    // Taproot only: the script leaf this path spends through; None for the key path
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leaf: Option<usize>,
+    pub description: String,
+}
+
+impl SpendingPath {
+    fn signed_by(signers: &[Role]) -> Self {
+        let names: Vec<String> = signers.iter().map(Role::to_string).collect();
+        Self {
+            signers: signers.to_vec(),
+            timelock: None,
+            hashlock: None,
+            leaf: None,
+            description: format!("{} sign together", names.join(" and ")),
+        }
+    }
+
+    fn after(path: &TimelockPath) -> Self {
+        Self {
+            signers: vec![path.recipient],
+            timelock: Some(path.timelock),
+            hashlock: None,
+            leaf: None,
+            description: format!("{} signs alone {}", path.recipient, path.timelock),
+        }
+    }
+
+    fn revealing(lock: &Hashlock) -> Self {
+        Self {
+            signers: vec![lock.recipient],
+            timelock: None,
+            hashlock: Some(lock.hash.clone()),
+            leaf: None,
+            description: format!("{} signs alone, revealing the preimage of {}", lock.recipient, lock.hash),
+        }
+    }
+}
+
+// How the output commits to its spending paths
+#[derive(Debug, Clone)]
+pub enum EscrowOutput {
+    P2wsh { witness_script: Script },
+    Taproot(TaprootTree),
+}
+
+#[derive(Debug, Clone)]
+pub struct EscrowScript {
+    pub template: EscrowTemplate,
+    pub parties: EscrowParties,
+    pub timelock: Option<TimelockPath>,
+    pub hashlock: Option<Hashlock>,
+    pub output: EscrowOutput,
+    pub address: Address,
+    pub spending_paths: Vec<SpendingPath>,
+}
+
+impl EscrowScript {
+    pub fn output_type(&self) -> OutputType {
+        match self.output {
+            EscrowOutput::P2wsh { .. } => OutputType::P2wsh,
+            EscrowOutput::Taproot(_) => OutputType::Taproot,
+        }
+    }
+
+    pub fn witness_script(&self) -> Option<&Script> {
+        match &self.output {
+            EscrowOutput::P2wsh { witness_script } => Some(witness_script),
+            EscrowOutput::Taproot(_) => None,
+        }
+    }
+
+    pub fn taproot(&self) -> Option<&TaprootTree> {
+        match &self.output {
+            EscrowOutput::P2wsh { .. } => None,
+            EscrowOutput::Taproot(tree) => Some(tree),
+        }
+    }
+}
+
+// The keys each path uses, once none is missing or repeated
+struct EscrowKeys<'a> {
+    arbitrator: Option<&'a NostrPublicKey>,
+    timelock_recipient: Option<&'a NostrPublicKey>,
+    hashlock_recipient: Option<&'a NostrPublicKey>,
+}
+
+fn escrow_keys<'a>(
+    template: EscrowTemplate,
+    parties: &'a EscrowParties,
+    timelock: Option<TimelockPath>,
+    hashlock: Option<&Hashlock>,
+) -> Result<EscrowKeys<'a>, ScriptError> {
+    let needs_arbitrator = template != EscrowTemplate::A;
+    let pays_arbitrator = timelock.is_some_and(|path| path.recipient == Role::Arbitrator)
+        || hashlock.is_some_and(|lock| lock.recipient == Role::Arbitrator);
+    let arbitrator = match (needs_arbitrator, &parties.arbitrator) {
+        (true, None) => return Err(ScriptError::MissingArbitrator(template)),
+        (true, Some(key)) => Some(key),
+        // Template A only uses an arbitrator key to pay it through a fallback
+        (false, key) => key.as_ref().filter(|_| pays_arbitrator),
+    };
+    let key_of = |role, missing| match role {
+        Role::Party1 => Ok(&parties.party_1),
+        Role::Party2 => Ok(&parties.party_2),
+        Role::Arbitrator => arbitrator.ok_or(missing),
+    };
+
+    let timelock_recipient = match timelock {
+        Some(path) => {
+            path.timelock.validate()?;
+            Some(key_of(path.recipient, ScriptError::TimelockRecipientWithoutKey)?)
+        }
+        None => None,
+    };
+    let hashlock_recipient = match hashlock {
+        Some(lock) => {
+            lock.digest()?;
+            Some(key_of(lock.recipient, ScriptError::HashlockRecipientWithoutKey)?)
+        }
+        None => None,
+    };
+
+    let mut keys = vec![(Role::Party1, &parties.party_1), (Role::Party2, &parties.party_2)];
+    keys.extend(arbitrator.map(|key| (Role::Arbitrator, key)));
+    for (i, (role, key)) in keys.iter().enumerate() {
+        if let Some((other, _)) = keys[..i].iter().find(|(_, earlier)| earlier == key) {
+            return Err(ScriptError::DuplicateKey(*other, *role));
+        }
+    }
+
+    Ok(EscrowKeys { arbitrator, timelock_recipient, hashlock_recipient })
+}
+
+// `required` must sign, along with one of `either`:
+// <required> CHECKSIGVERIFY 1 <either...> 2 CHECKMULTISIG
+fn required_plus_one(builder: Builder, required: &NostrPublicKey, either: [&NostrPublicKey; 2]) -> Builder {
+    builder
+        .push_key(&required.segwit_v0_key())
+        .push_opcode(OP_CHECKSIGVERIFY)
+        .push_int(1)
+        .push_key(&either[0].segwit_v0_key())
+        .push_key(&either[1].segwit_v0_key())
+        .push_int(2)
+        .push_opcode(OP_CHECKMULTISIG)
+}
+
+// Builds the template's P2WSH witness script and address, with the spending paths it allows.
+// With a timelock the template becomes the IF branch and the fallback the ELSE branch:
+// IF <template> ELSE <lock> CSV|CLTV DROP <recipient> CHECKSIG ENDIF
+pub fn escrow_scripts(
+    template: EscrowTemplate,
+    parties: &EscrowParties,
+    timelock: Option<TimelockPath>,
+    network: Network,
+) -> Result<EscrowScript, ScriptError> {
+    let keys = escrow_keys(template, parties, timelock, None)?;
+
+    let mut builder = Builder::new();
+    if timelock.is_some() {
+        builder = builder.push_opcode(OP_IF);
+    }
+
+    let (mut builder, mut spending_paths) = match (template, keys.arbitrator) {
+        (EscrowTemplate::B, Some(arbitrator)) => (
+            required_plus_one(builder, &parties.party_1, [&parties.party_2, arbitrator]),
+            vec![
+                SpendingPath::signed_by(&[Role::Party1, Role::Party2]),
+                SpendingPath::signed_by(&[Role::Party1, Role::Arbitrator]),
+            ],
+        ),
+        (EscrowTemplate::C, Some(arbitrator)) => (
+            required_plus_one(builder, &parties.party_2, [&parties.party_1, arbitrator]),
+            vec![
+                SpendingPath::signed_by(&[Role::Party2, Role::Party1]),
+                SpendingPath::signed_by(&[Role::Party2, Role::Arbitrator]),
+            ],
+        ),
+        _ => (
+            builder
+                .push_int(2)
+                .push_key(&parties.party_1.segwit_v0_key())
+                .push_key(&parties.party_2.segwit_v0_key())
+                .push_int(2)
+                .push_opcode(OP_CHECKMULTISIG),
+            vec![SpendingPath::signed_by(&[Role::Party1, Role::Party2])],
+        ),
+    };
+
+    if let (Some(path), Some(recipient)) = (timelock, keys.timelock_recipient) {
+        builder = path.timelock.push(builder.push_opcode(OP_ELSE))
+            .push_opcode(OP_DROP)
+            .push_key(&recipient.segwit_v0_key())
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_ENDIF);
+        spending_paths.push(SpendingPath::after(&path));
+    }
+
+    let witness_script = builder.into_script();
+    Ok(EscrowScript {
+        template,
+        parties: parties.clone(),
+        timelock,
+        hashlock: None,
+        address: Address::p2wsh(&witness_script, network),
+        output: EscrowOutput::P2wsh { witness_script },
+        spending_paths,
+    })
+}
+
+// <first> CHECKSIGVERIFY <second> CHECKSIG
+fn both_sign(first: &NostrPublicKey, second: &NostrPublicKey) -> Script {
+    Builder::new()
+        .push_x_only_key(&first.taproot_key())
+        .push_opcode(OP_CHECKSIGVERIFY)
+        .push_x_only_key(&second.taproot_key())
+        .push_opcode(OP_CHECKSIG)
+        .into_script()
+}
+
+// Builds a taproot escrow. With `musig_key_path` the internal key is the BIP-327 MuSig2 aggregate
+// of the two parties' keys, sorted, so they cooperate through the key path and only both of them
+// together can use it. Otherwise the internal key is the unspendable NUMS point and cooperation is
+// a leaf like every other path. Leaves, most likely first, so they get the shortest control blocks:
+//   cooperative:  <party 1> CHECKSIGVERIFY <party 2> CHECKSIG
+//   arbitrated:   <required party> CHECKSIGVERIFY <arbitrator> CHECKSIG
+//   timeout:      <lock> CSV|CLTV DROP <recipient> CHECKSIG
+//   hashlock:     SIZE 32 EQUALVERIFY SHA256 <hash> EQUALVERIFY <recipient> CHECKSIG
+pub fn taproot_escrow(
+    template: EscrowTemplate,
+    parties: &EscrowParties,
+    timelock: Option<TimelockPath>,
+    hashlock: Option<Hashlock>,
+    musig_key_path: bool,
+    network: Network,
+) -> Result<EscrowScript, ScriptError> {
+    let keys = escrow_keys(template, parties, timelock, hashlock.as_ref())?;
+
+    let mut spending_paths = Vec::new();
+    let mut leaves: Vec<(u32, Script, SpendingPath)> = Vec::new();
+    let cooperative = SpendingPath::signed_by(&[Role::Party1, Role::Party2]);
+    let internal_key = match musig_key_path {
+        true => {
+            spending_paths.push(SpendingPath {
+                description: format!("{} through their MuSig2 aggregate key", cooperative.description),
+                ..cooperative
+            });
+            let mut keys = [parties.party_1.segwit_v0_key().inner, parties.party_2.segwit_v0_key().inner];
+            keys.sort_by_key(|key| key.serialize());
+            taproot::musig_key_agg(&keys)
+        }
+        false => {
+            leaves.push((4, both_sign(&parties.party_1, &parties.party_2), cooperative));
+            taproot::nums_key()
+        }
+    };
+
+    match (template, keys.arbitrator) {
+        (EscrowTemplate::B, Some(arbitrator)) => {
+            leaves.push((2, both_sign(&parties.party_1, arbitrator), SpendingPath::signed_by(&[Role::Party1, Role::Arbitrator])));
+        }
+        (EscrowTemplate::C, Some(arbitrator)) => {
+            leaves.push((2, both_sign(&parties.party_2, arbitrator), SpendingPath::signed_by(&[Role::Party2, Role::Arbitrator])));
+        }
+        _ => {}
+    }
+
+    if let (Some(path), Some(recipient)) = (timelock, keys.timelock_recipient) {
+        let script = path.timelock.push(Builder::new())
+            .push_opcode(OP_DROP)
+            .push_x_only_key(&recipient.taproot_key())
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        leaves.push((1, script, SpendingPath::after(&path)));
+    }
+
+    if let (Some(lock), Some(recipient)) = (&hashlock, keys.hashlock_recipient) {
+        let script = Builder::new()
+            .push_opcode(OP_SIZE)
+            .push_int(32)
+            .push_opcode(OP_EQUALVERIFY)
+            .push_opcode(OP_SHA256)
+            .push_slice(&lock.digest()?)
+            .push_opcode(OP_EQUALVERIFY)
+            .push_x_only_key(&recipient.taproot_key())
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        leaves.push((1, script, SpendingPath::revealing(lock)));
+    }
+
+    let mut scripts = Vec::new();
+    for (i, (weight, script, path)) in leaves.into_iter().enumerate() {
+        scripts.push((weight, script));
+        spending_paths.push(SpendingPath { leaf: Some(i), ..path });
+    }
+    let tree = TaprootTree::weighted(internal_key, scripts).expect("at most four leaves always fit in a tree");
+
+    Ok(EscrowScript {
+        template,
+        parties: parties.clone(),
+        timelock,
+        hashlock,
+        address: tree.address(network),
+        output: EscrowOutput::Taproot(tree),
+        spending_paths,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::hex::ToHex;
+    use std::str::FromStr;
+
+    // The x-only keys of the secret keys 1, 2 and 3
+    const KEY_1: &str = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const KEY_2: &str = "c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+    const KEY_3: &str = "f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9";
+
+    fn parties(arbitrator: Option<&str>) -> EscrowParties {
+        EscrowParties {
+            party_1: NostrPublicKey::from_str(KEY_1).unwrap(),
+            party_2: NostrPublicKey::from_str(KEY_2).unwrap(),
+            arbitrator: arbitrator.map(|key| NostrPublicKey::from_str(key).unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_templates_match_known_vectors() {
+        // Scripts assembled by hand; addresses computed independently with the BIP-173 reference encoder
+        let vectors = [
+            (
+                EscrowTemplate::A,
+                format!("522102{}2102{}52ae", KEY_1, KEY_2),
+                "bc1qnwvyc7aw8m7acw3lpgs0lqdlaz0drls8luf72cs5nmn9f0kcghdse7d78q",
+            ),
+            (
+                EscrowTemplate::B,
+                format!("2102{}ad512102{}2102{}52ae", KEY_1, KEY_2, KEY_3),
+                "bc1q3725s9yuly0fpu6hx0g4avufj6p9v2j6a7uxsyg6elj7hymrc3gssmdgpa",
+            ),
+            (
+                EscrowTemplate::C,
+                format!("2102{}ad512102{}2102{}52ae", KEY_2, KEY_1, KEY_3),
+                "bc1q87yu9efsm73q3clg26wju40pmnhdaceyncmfpurd9gqfwjyquknsl2vzen",
+            ),
+        ];
+
+        for (template, script, address) in vectors {
+            let escrow = escrow_scripts(template, &parties(Some(KEY_3)), None, Network::Bitcoin).unwrap();
+            assert_eq!(escrow.witness_script().unwrap().to_hex(), script, "{:?}", template);
+            assert_eq!(escrow.address.to_string(), address, "{:?}", template);
+        }
+    }
+
+    #[test]
+    fn test_spending_paths_and_key_checks() {
+        let escrow = escrow_scripts(EscrowTemplate::C, &parties(Some(KEY_3)), None, Network::Bitcoin).unwrap();
+        let signers: Vec<&[Role]> = escrow.spending_paths.iter().map(|path| path.signers.as_slice()).collect();
+        assert_eq!(signers, [[Role::Party2, Role::Party1], [Role::Party2, Role::Arbitrator]]);
+        assert_eq!(escrow.spending_paths[1].description, "Party 2 and the arbitrator sign together");
+
+        let escrow = escrow_scripts(EscrowTemplate::A, &parties(None), None, Network::Bitcoin).unwrap();
+        assert_eq!(escrow.spending_paths.len(), 1);
+
+        assert_eq!(
+            escrow_scripts(EscrowTemplate::B, &parties(None), None, Network::Bitcoin).unwrap_err(),
+            ScriptError::MissingArbitrator(EscrowTemplate::B),
+        );
+        assert_eq!(
+            escrow_scripts(EscrowTemplate::B, &parties(Some(KEY_2)), None, Network::Bitcoin).unwrap_err(),
+            ScriptError::DuplicateKey(Role::Party2, Role::Arbitrator),
+        );
+    }
+
+    #[test]
+    fn test_timelocked_fallback_matches_known_vectors() {
+        let relative = TimelockPath { timelock: Timelock::Blocks(144), recipient: Role::Arbitrator };
+        let escrow = escrow_scripts(EscrowTemplate::B, &parties(Some(KEY_3)), Some(relative), Network::Bitcoin).unwrap();
+        let expected = format!("632102{}ad512102{}2102{}52ae67029000b2752102{}ac68", KEY_1, KEY_2, KEY_3, KEY_3);
+        assert_eq!(escrow.witness_script().unwrap().to_hex(), expected);
+        assert_eq!(escrow.address.to_string(), "bc1qhe9qzkjhy9xrkajcu5sy6xvp87vkttnp5h3nr8le204m92etyszs6jq4z3");
+        assert_eq!(escrow.spending_paths.len(), 3);
+        assert_eq!(escrow.spending_paths[2].description, "the arbitrator signs alone 144 blocks after the escrow is funded");
+
+        let absolute = TimelockPath { timelock: Timelock::Height(900_000), recipient: Role::Party1 };
+        let escrow = escrow_scripts(EscrowTemplate::A, &parties(None), Some(absolute), Network::Bitcoin).unwrap();
+        let expected = format!("63522102{}2102{}52ae6703a0bb0db1752102{}ac68", KEY_1, KEY_2, KEY_1);
+        assert_eq!(escrow.witness_script().unwrap().to_hex(), expected);
+        assert_eq!(escrow.address.to_string(), "bc1qep42y6g7z763spvcrf3k5jv5gqauauqdc25dl55ttk5h640vm6gql2uuqj");
+    }
+
+    #[test]
+    fn test_timelocks_are_validated() {
+        let path = |timelock, recipient| Some(TimelockPath { timelock, recipient });
+        let build = |template, arbitrator, timelock| escrow_scripts(template, &parties(arbitrator), timelock, Network::Bitcoin);
+
+        assert!(matches!(build(EscrowTemplate::A, None, path(Timelock::Blocks(0), Role::Party1)), Err(ScriptError::InvalidTimelock(_))));
+        assert!(matches!(build(EscrowTemplate::A, None, path(Timelock::Height(LOCK_TIME_THRESHOLD), Role::Party1)), Err(ScriptError::InvalidTimelock(_))));
+        assert!(matches!(build(EscrowTemplate::A, None, path(Timelock::Time(1_000), Role::Party1)), Err(ScriptError::InvalidTimelock(_))));
+        assert_eq!(
+            build(EscrowTemplate::A, None, path(Timelock::Blocks(6), Role::Arbitrator)).unwrap_err(),
+            ScriptError::TimelockRecipientWithoutKey,
+        );
+
+        // Template A takes the arbitrator's key only for the fallback
+        let escrow = build(EscrowTemplate::A, Some(KEY_3), path(Timelock::Time(1_767_225_600), Role::Arbitrator)).unwrap();
+        assert_eq!(escrow.spending_paths[1].description, "the arbitrator signs alone from 2026-01-01T00:00:00+00:00");
+    }
+
+    #[test]
+    fn test_taproot_escrows_match_known_vectors() {
+        // Tagged hashes and tweaks computed independently, with the NUMS point as internal key
+        let relative = TimelockPath { timelock: Timelock::Blocks(144), recipient: Role::Arbitrator };
+        let vectors = [
+            (EscrowTemplate::A, None, "bc1p4uenjajzpnqfwmpd4u7ta3vfavhyp4lqc6kwxj6mge86u4h3p8mq8vtkc4"),
+            (EscrowTemplate::B, Some(relative), "bc1pf34v5ju3g5lwdyr8vuyc4w6gtpcrt42crz9tvrdwdkkps9sq84wq4s2tka"),
+        ];
+
+        let secp = bitcoin::secp256k1::Secp256k1::verification_only();
+        for (template, timelock, address) in vectors {
+            let escrow = taproot_escrow(template, &parties(Some(KEY_3)), timelock, None, false, Network::Bitcoin).unwrap();
+            assert_eq!(escrow.address.to_string(), address, "{:?}", template);
+            assert_eq!(escrow.output_type(), OutputType::Taproot);
+            assert!(escrow.witness_script().is_none());
+
+            let tree = escrow.taproot().unwrap();
+            assert!(!tree.key_path_spendable());
+            let output_key = tree.spend_info.output_key().to_inner();
+            for (i, path) in escrow.spending_paths.iter().enumerate() {
+                assert_eq!(path.leaf, Some(i));
+                let script = &tree.scripts[i];
+                assert!(tree.control_block(script).unwrap().verify_taproot_commitment(&secp, output_key, script));
+            }
+        }
+
+        let escrow = taproot_escrow(EscrowTemplate::B, &parties(Some(KEY_3)), Some(relative), None, false, Network::Bitcoin).unwrap();
+        let tree = escrow.taproot().unwrap();
+        assert_eq!(tree.scripts[0].to_hex(), format!("20{}ad20{}ac", KEY_1, KEY_2));
+        assert_eq!(tree.scripts[2].to_hex(), format!("029000b27520{}ac", KEY_3));
+        // The cooperative leaf is the likeliest, so it sits right below the root
+        assert_eq!(tree.leaf(0).depth, 1);
+    }
+
+    #[test]
+    fn test_taproot_key_path_and_hashlock() {
+        let hash = "66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925";
+        let hashlock = Hashlock { hash: hash.into(), recipient: Role::Party2 };
+        let escrow = taproot_escrow(EscrowTemplate::A, &parties(None), None, Some(hashlock), true, Network::Bitcoin).unwrap();
+
+        let tree = escrow.taproot().unwrap();
+        assert!(tree.key_path_spendable());
+        // Derived from the parties' keys, so it is neither of them
+        let internal_key = tree.internal_key().to_hex();
+        assert!(internal_key != KEY_1 && internal_key != KEY_2);
+        assert_eq!(tree.scripts.len(), 1);
+        assert_eq!(tree.scripts[0].to_hex(), format!("82012088a820{}8820{}ac", hash, KEY_2));
+        assert_eq!(escrow.spending_paths[0].leaf, None);
+        assert_eq!(escrow.spending_paths[0].description, "Party 1 and Party 2 sign together through their MuSig2 aggregate key");
+        assert_eq!(escrow.spending_paths[1].hashlock.as_deref(), Some(hash));
+
+        let invalid = Hashlock { hash: "abcd".into(), recipient: Role::Party1 };
+        assert!(matches!(
+            taproot_escrow(EscrowTemplate::A, &parties(None), None, Some(invalid), false, Network::Bitcoin),
+            Err(ScriptError::InvalidHashlock(_)),
+        ));
+        let to_arbitrator = Hashlock { hash: hash.into(), recipient: Role::Arbitrator };
+        assert_eq!(
+            taproot_escrow(EscrowTemplate::A, &parties(None), None, Some(to_arbitrator), false, Network::Bitcoin).unwrap_err(),
+            ScriptError::HashlockRecipientWithoutKey,
+        );
+    }
+}
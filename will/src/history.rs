@@ -0,0 +1,118 @@
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+use crate::budget::BudgetCaps;
+use crate::import::SpendEstimate;
+use crate::spending::{ProjectSpend, Scenario, SpendingError, UserModel};
+
+// One change to a stored model. Replaying a user's changes in order rebuilds the model.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ModelChange {
+    // Starts the model over, as when a user is created again
+    Created { model: UserModel },
+    ProjectAdded { project: ProjectSpend },
+    BudgetSet { budget: BudgetCaps },
+    EstimatesApplied { estimates: Vec<SpendEstimate> },
+    ScenarioSet { scenario: Scenario },
+    ScenarioRemoved { name: String },
+}
+
+impl ModelChange {
+    pub fn apply(&self, model: &mut UserModel) -> Result<(), SpendingError> {
+        match self {
+            ModelChange::Created { model: initial } => *model = initial.clone(),
+            ModelChange::ProjectAdded { project } => model.add_project(project.clone()),
+            ModelChange::BudgetSet { budget } => model.set_budget(budget.clone())?,
+            ModelChange::EstimatesApplied { estimates } => model.apply_estimates(estimates)?,
+            ModelChange::ScenarioSet { scenario } => model.set_scenario(scenario.clone())?,
+            ModelChange::ScenarioRemoved { name } => {
+                model.remove_scenario(name);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ModelEvent {
+    // Position in the user's history, from 1; the model at version N has the first N changes applied
+    pub version: u64,
+    pub timestamp: DateTime<Utc>,
+    pub change: ModelChange,
+}
+
+// Rebuilds the model from its changes; None until the first Created
+pub fn replay<'a>(events: impl IntoIterator<Item = &'a ModelEvent>) -> Option<UserModel> {
+    let mut model: Option<UserModel> = None;
+    for event in events {
+        match (&event.change, &mut model) {
+            (ModelChange::Created { model: initial }, _) => model = Some(initial.clone()),
+            (change, Some(current)) => change.apply(current)
+                .expect("stored changes applied cleanly when they were recorded"),
+            (_, None) => {}
+        }
+    }
+    model
+}
+
+pub fn at_version(events: &[ModelEvent], version: u64) -> Option<UserModel> {
+    if version == 0 || version > events.len() as u64 {
+        return None;
+    }
+    replay(&events[..version as usize])
+}
+
+// The model as it stood at `at`; None if it did not exist yet
+pub fn as_of(events: &[ModelEvent], at: DateTime<Utc>) -> Option<UserModel> {
+    replay(events.iter().take_while(|event| event.timestamp <= at))
+}
+
+// Accepts an RFC 3339 timestamp, or a date standing for the end of that day in UTC
+pub fn parse_as_of(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(at) = DateTime::parse_from_rfc3339(value) {
+        return Some(at.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+    let end_of_day = NaiveTime::from_hms_nano_opt(23, 59, 59, 999_999_999)?;
+    Some(date.and_time(end_of_day).and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spending::GrowthType;
+    use chrono::TimeZone;
+
+    fn event(version: u64, day: u32, change: ModelChange) -> ModelEvent {
+        ModelEvent { version, timestamp: Utc.with_ymd_and_hms(2026, 1, day, 12, 0, 0).unwrap(), change }
+    }
+
+    #[test]
+    fn test_rebuilds_model_at_versions_and_times() {
+        let rent = ProjectSpend::new("Rent".into(), 10.0, 0.0, GrowthType::Flat).unwrap();
+        let events = vec![
+            event(1, 1, ModelChange::Created { model: UserModel::new("alice".into(), 3).unwrap() }),
+            event(2, 2, ModelChange::ProjectAdded { project: rent }),
+            event(3, 3, ModelChange::EstimatesApplied { estimates: vec![SpendEstimate {
+                project_name: "Rent".into(),
+                daily_spend: 12.0,
+                growth_rate: 0.02,
+                transactions: 4,
+            }] }),
+            event(4, 4, ModelChange::Created { model: UserModel::new("alice".into(), 5).unwrap() }),
+        ];
+
+        assert!(at_version(&events, 0).is_none());
+        assert_eq!(at_version(&events, 2).unwrap().projects[0].daily_spend, 10.0);
+        assert_eq!(at_version(&events, 3).unwrap().projects[0].daily_spend, 12.0);
+        assert!(at_version(&events, 4).unwrap().projects.is_empty());
+
+        let day = |value: &str| parse_as_of(value).unwrap();
+        assert!(as_of(&events, day("2025-12-31")).is_none());
+        assert!(as_of(&events, day("2026-01-02T11:59:59Z")).unwrap().projects.is_empty());
+        assert_eq!(as_of(&events, day("2026-01-02")).unwrap().projects.len(), 1);
+        assert_eq!(as_of(&events, day("2026-01-09")).unwrap().projection_years, 5);
+        assert!(parse_as_of("yesterday").is_none());
+    }
+}
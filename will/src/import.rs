@@ -0,0 +1,387 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use std::collections::{BTreeMap, HashMap};
+use thiserror::Error;
+use crate::spending::{GrowthType, ProjectSpend, SpendingError, UserModel};
+
+#[derive(Debug, Error)]
+pub enum ImportError {
+    #[error("CSV error: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("Missing column: {0}")]
+    MissingColumn(String),
+    #[error("Delimiter '{0}' must be a single ASCII character")]
+    InvalidDelimiter(char),
+    #[error("Invalid date '{0}' on line {1}")]
+    InvalidDate(String, u64),
+    #[error("Invalid amount '{0}' on line {1}")]
+    InvalidAmount(String, u64),
+    #[error("Invalid rule pattern: {0}")]
+    InvalidPattern(#[from] regex::Error),
+    #[error(transparent)]
+    Spending(#[from] SpendingError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SpendSign {
+    // Outgoing money is negative, as in most bank exports
+    Negative,
+    // Every row is a spend, as in our own ledger
+    Positive,
+}
+
+// Describes how to read one kind of CSV export
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CsvFormat {
+    pub date_column: String,
+    pub date_format: String,
+    pub amount_column: String,
+    pub description_column: String,
+    // Rows carrying a project here skip rule matching
+    #[serde(default)]
+    pub category_column: Option<String>,
+    pub spend_sign: SpendSign,
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+}
+
+fn default_delimiter() -> char {
+    ','
+}
+
+impl CsvFormat {
+    pub fn bank() -> Self {
+        Self {
+            date_column: "Date".into(),
+            date_format: "%Y-%m-%d".into(),
+            amount_column: "Amount".into(),
+            description_column: "Description".into(),
+            category_column: None,
+            spend_sign: SpendSign::Negative,
+            delimiter: ',',
+        }
+    }
+
+    pub fn exchange() -> Self {
+        Self {
+            date_column: "Timestamp".into(),
+            date_format: "%Y-%m-%d %H:%M:%S".into(),
+            amount_column: "Amount".into(),
+            description_column: "Notes".into(),
+            category_column: None,
+            spend_sign: SpendSign::Negative,
+            delimiter: ',',
+        }
+    }
+
+    pub fn ledger() -> Self {
+        Self {
+            date_column: "date".into(),
+            date_format: "%Y-%m-%d".into(),
+            amount_column: "amount".into(),
+            description_column: "description".into(),
+            category_column: Some("project".into()),
+            spend_sign: SpendSign::Positive,
+            delimiter: ',',
+        }
+    }
+
+    fn parse_date(&self, value: &str, line: u64) -> Result<NaiveDate, ImportError> {
+        NaiveDateTime::parse_from_str(value, &self.date_format)
+            .map(|datetime| datetime.date())
+            .or_else(|_| NaiveDate::parse_from_str(value, &self.date_format))
+            .map_err(|_| ImportError::InvalidDate(value.to_string(), line))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transaction {
+    pub date: NaiveDate,
+    pub description: String,
+    // Always positive; income rows are skipped while reading
+    pub amount: f64,
+    pub category: Option<String>,
+}
+
+pub fn read_transactions<R: std::io::Read>(reader: R, format: &CsvFormat) -> Result<Vec<Transaction>, ImportError> {
+    let delimiter = u8::try_from(format.delimiter).ok()
+        .filter(u8::is_ascii)
+        .ok_or(ImportError::InvalidDelimiter(format.delimiter))?;
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| {
+        headers.iter()
+            .position(|header| header.eq_ignore_ascii_case(name))
+            .ok_or_else(|| ImportError::MissingColumn(name.to_string()))
+    };
+
+    let date_idx = column(&format.date_column)?;
+    let amount_idx = column(&format.amount_column)?;
+    let description_idx = column(&format.description_column)?;
+    let category_idx = format.category_column.as_deref().map(column).transpose()?;
+
+    let mut transactions = Vec::new();
+
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map_or(0, |p| p.line());
+        let field = |idx: usize| record.get(idx).unwrap_or_default();
+
+        let raw_amount = field(amount_idx);
+        let amount: f64 = raw_amount.replace(',', "").parse()
+            .map_err(|_| ImportError::InvalidAmount(raw_amount.to_string(), line))?;
+
+        let amount = match format.spend_sign {
+            SpendSign::Negative if amount < 0.0 => -amount,
+            SpendSign::Positive if amount > 0.0 => amount,
+            _ => continue,
+        };
+
+        transactions.push(Transaction {
+            date: format.parse_date(field(date_idx), line)?,
+            description: field(description_idx).to_string(),
+            amount,
+            category: category_idx.map(field).filter(|c| !c.is_empty()).map(str::to_string),
+        });
+    }
+
+    Ok(transactions)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RuleDefinition {
+    pub project: String,
+    pub pattern: String,
+    // Plain patterns match as case-insensitive substrings
+    #[serde(default)]
+    pub regex: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct CategoryRule {
+    pub project: String,
+    matcher: Regex,
+}
+
+impl CategoryRule {
+    pub fn new(definition: &RuleDefinition) -> Result<Self, ImportError> {
+        let pattern = if definition.regex {
+            definition.pattern.clone()
+        } else {
+            format!("(?i){}", regex::escape(&definition.pattern))
+        };
+
+        Ok(Self {
+            project: definition.project.clone(),
+            matcher: Regex::new(&pattern)?,
+        })
+    }
+
+    pub fn matches(&self, description: &str) -> bool {
+        self.matcher.is_match(description)
+    }
+}
+
+// Assigns transactions to projects; the first matching rule wins
+#[derive(Debug, Clone, Default)]
+pub struct Categorizer {
+    rules: Vec<CategoryRule>,
+    fallback: Option<String>,
+}
+
+impl Categorizer {
+    pub fn new(rules: &[RuleDefinition], fallback: Option<String>) -> Result<Self, ImportError> {
+        Ok(Self {
+            rules: rules.iter().map(CategoryRule::new).collect::<Result<_, _>>()?,
+            fallback,
+        })
+    }
+
+    pub fn categorize(&self, transaction: &Transaction) -> Option<String> {
+        transaction.category.clone()
+            .or_else(|| {
+                self.rules.iter()
+                    .find(|rule| rule.matches(&transaction.description))
+                    .map(|rule| rule.project.clone())
+            })
+            .or_else(|| self.fallback.clone())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SpendEstimate {
+    pub project_name: String,
+    pub daily_spend: f64,
+    pub growth_rate: f64,
+    pub transactions: usize,
+}
+
+// Estimates daily spend over the trailing `window_days` and annual growth per project.
+// Exports covering fewer days than the window are averaged over the days they cover.
+// Transactions that no rule claims are left out.
+pub fn estimate_spend(
+    transactions: &[Transaction],
+    categorizer: &Categorizer,
+    window_days: u32,
+) -> Vec<SpendEstimate> {
+    let mut by_project: HashMap<String, Vec<&Transaction>> = HashMap::new();
+    for transaction in transactions {
+        if let Some(project) = categorizer.categorize(transaction) {
+            by_project.entry(project).or_default().push(transaction);
+        }
+    }
+
+    let dates = || transactions.iter().map(|t| t.date);
+    let (Some(first_date), Some(last_date)) = (dates().min(), dates().max()) else {
+        return Vec::new();
+    };
+    let window_days = (window_days.max(1) as i64).min((last_date - first_date).num_days() + 1);
+    let window_start = last_date - Duration::days(window_days - 1);
+
+    let mut estimates: Vec<SpendEstimate> = by_project
+        .into_iter()
+        .map(|(project_name, transactions)| {
+            let window_total: f64 = transactions.iter()
+                .filter(|t| t.date >= window_start)
+                .map(|t| t.amount)
+                .sum();
+
+            SpendEstimate {
+                project_name,
+                daily_spend: window_total / window_days as f64,
+                growth_rate: observed_growth(&transactions),
+                transactions: transactions.len(),
+            }
+        })
+        .collect();
+
+    estimates.sort_by(|a, b| a.project_name.cmp(&b.project_name));
+    estimates
+}
+
+// Annualised growth from a log-linear fit over monthly totals; 0 with under three months of data
+fn observed_growth(transactions: &[&Transaction]) -> f64 {
+    let mut monthly: BTreeMap<i32, f64> = BTreeMap::new();
+    for transaction in transactions {
+        let month = transaction.date.year() * 12 + transaction.date.month0() as i32;
+        *monthly.entry(month).or_default() += transaction.amount;
+    }
+
+    if monthly.len() < 3 {
+        return 0.0;
+    }
+
+    let points: Vec<(f64, f64)> = monthly.iter()
+        .map(|(month, total)| (*month as f64, total.ln()))
+        .collect();
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let covariance: f64 = points.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+    let variance: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+
+    (covariance / variance * 12.0).exp() - 1.0
+}
+
+impl UserModel {
+    // Updates matching projects in place and adds compounding projects for new categories
+    pub fn apply_estimates(&mut self, estimates: &[SpendEstimate]) -> Result<(), SpendingError> {
+        for estimate in estimates {
+            match self.projects.iter_mut().find(|p| p.project_name == estimate.project_name) {
+                Some(project) => {
+                    project.daily_spend = estimate.daily_spend;
+                    project.growth_rate = estimate.growth_rate;
+                }
+                None => self.add_project(ProjectSpend::new(
+                    estimate.project_name.clone(),
+                    estimate.daily_spend,
+                    estimate.growth_rate,
+                    GrowthType::Compound,
+                )?),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BANK_CSV: &str = "\
+Date,Description,Amount
+2024-01-05,SALARY ACME,3000.00
+2024-01-10,Tesco Stores 123,-100.00
+2024-02-10,TESCO STORES 123,-110.00
+2024-03-10,tesco stores 123,-121.00
+2024-03-15,Rent March,-900.00
+2024-03-20,Coffee,-4.50
+";
+
+    fn rules() -> Vec<RuleDefinition> {
+        vec![
+            RuleDefinition { project: "Groceries".into(), pattern: "tesco".into(), regex: false },
+            RuleDefinition { project: "Housing".into(), pattern: r"^Rent\b".into(), regex: true },
+        ]
+    }
+
+    #[test]
+    fn test_read_bank_export_skips_income() {
+        let transactions = read_transactions(BANK_CSV.as_bytes(), &CsvFormat::bank()).unwrap();
+        assert_eq!(transactions.len(), 5);
+        assert!(transactions.iter().all(|t| t.amount > 0.0));
+    }
+
+    #[test]
+    fn test_estimates_from_rules() {
+        let transactions = read_transactions(BANK_CSV.as_bytes(), &CsvFormat::bank()).unwrap();
+        let categorizer = Categorizer::new(&rules(), None).unwrap();
+        let estimates = estimate_spend(&transactions, &categorizer, 30);
+
+        assert_eq!(estimates.len(), 2);
+        let groceries = &estimates[0];
+        assert_eq!(groceries.project_name, "Groceries");
+        assert_eq!(groceries.transactions, 3);
+        // Only the March purchase is in the trailing 30 days
+        assert!((groceries.daily_spend - 121.0 / 30.0).abs() < 1e-9);
+        // 10% a month compounds to roughly 214% a year
+        assert!((groceries.growth_rate - (1.1f64.powi(12) - 1.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_ledger_categories_update_model() {
+        let ledger = "date,project,amount,description\n2024-03-01,Travel,300,Flights\n2024-03-02,,20,Taxi\n";
+        let transactions = read_transactions(ledger.as_bytes(), &CsvFormat::ledger()).unwrap();
+        let categorizer = Categorizer::new(&[], Some("Other".into())).unwrap();
+        let estimates = estimate_spend(&transactions, &categorizer, 10);
+
+        let mut user = UserModel::new("user1".into(), 5).unwrap();
+        user.add_project(ProjectSpend::new("Travel".into(), 1.0, 0.5, GrowthType::Flat).unwrap());
+        user.apply_estimates(&estimates).unwrap();
+
+        // The ledger covers two days of the ten-day window
+        assert_eq!(user.projects.len(), 2);
+        assert!((user.projects[0].daily_spend - 150.0).abs() < 1e-9);
+        assert_eq!(user.projects[1].project_name, "Other");
+        assert!((user.projects[1].daily_spend - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_non_ascii_delimiter_is_refused() {
+        // '€' is U+20AC, which a plain cast would truncate to a space
+        let format = CsvFormat { delimiter: '€', ..CsvFormat::bank() };
+        assert!(matches!(read_transactions(BANK_CSV.as_bytes(), &format), Err(ImportError::InvalidDelimiter('€'))));
+
+        let format = CsvFormat { delimiter: ';', ..CsvFormat::bank() };
+        let transactions = read_transactions(BANK_CSV.replace(',', ";").as_bytes(), &format).unwrap();
+        assert_eq!(transactions.len(), 5);
+    }
+}
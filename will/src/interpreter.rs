@@ -0,0 +1,624 @@
+use bitcoin::blockdata::opcodes::all::*;
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
+use bitcoin::schnorr::SchnorrSig;
+use bitcoin::secp256k1::{Message, Secp256k1, VerifyOnly, XOnlyPublicKey};
+use bitcoin::util::sighash::{Prevouts, SighashCache};
+use bitcoin::util::taproot::{ControlBlock, LeafVersion, TapLeafHash};
+use bitcoin::{EcdsaSig, PublicKey, Script, Transaction, TxOut};
+use thiserror::Error;
+
+// Checks a signed input the way a node would, for the scripts this crate writes: P2WPKH, P2WSH and
+// taproot key and script paths, using the opcodes escrows and Miniscript need. It is not a full
+// consensus implementation; anything outside that subset is reported as unsupported.
+#[derive(Debug, Error, PartialEq)]
+pub enum VerifyError {
+    #[error("Input {0} has no spent output to check against")]
+    MissingSpentOutput(usize),
+    #[error("Input {0} cannot be checked: {1}")]
+    Unsupported(usize, String),
+    #[error("Input {0} fails: {1}")]
+    Failed(usize, String),
+}
+
+// Relative lock time bits, from BIP-68
+const SEQUENCE_DISABLE: u32 = 1 << 31;
+const SEQUENCE_TYPE: u32 = 1 << 22;
+const SEQUENCE_MASK: u32 = 0x0000_ffff;
+const LOCK_TIME_THRESHOLD: i64 = 500_000_000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Version {
+    SegwitV0,
+    Tapscript(TapLeafHash),
+}
+
+// Why a script stopped; `Unsupported` means we could not tell either way
+enum Halt {
+    Failed(String),
+    Unsupported(String),
+}
+
+fn fail<T>(reason: impl Into<String>) -> Result<T, Halt> {
+    Err(Halt::Failed(reason.into()))
+}
+
+pub fn verify_input(tx: &Transaction, index: usize, spent: &[TxOut]) -> Result<(), VerifyError> {
+    let prevout = spent.get(index).ok_or(VerifyError::MissingSpentOutput(index))?;
+    if spent.len() != tx.input.len() {
+        return Err(VerifyError::MissingSpentOutput(index));
+    }
+    let checker = Checker { tx, index, spent, secp: Secp256k1::verification_only() };
+    checker.verify(&prevout.script_pubkey).map_err(|halt| match halt {
+        Halt::Failed(reason) => VerifyError::Failed(index, reason),
+        Halt::Unsupported(reason) => VerifyError::Unsupported(index, reason),
+    })
+}
+
+struct Checker<'a> {
+    tx: &'a Transaction,
+    index: usize,
+    spent: &'a [TxOut],
+    secp: Secp256k1<VerifyOnly>,
+}
+
+impl Checker<'_> {
+    fn verify(&self, script_pubkey: &Script) -> Result<(), Halt> {
+        let mut witness = self.tx.input[self.index].witness.to_vec();
+        if !self.tx.input[self.index].script_sig.is_empty() {
+            return fail("native segwit inputs have an empty scriptSig");
+        }
+
+        if script_pubkey.is_v0_p2wpkh() {
+            let [sig, key] = <[Vec<u8>; 2]>::try_from(witness).or_else(|_| fail("P2WPKH takes a signature and a key"))?;
+            let key_hash = hash160::Hash::hash(&key);
+            if script_pubkey.as_bytes()[2..] != key_hash[..] {
+                return fail("the key does not match the output");
+            }
+            let script_code = Script::new_p2pkh(&bitcoin::PubkeyHash::from_hash(key_hash));
+            return self.run(&script_code, vec![sig, key], Version::SegwitV0);
+        }
+
+        if script_pubkey.is_v0_p2wsh() {
+            let script = Script::from(witness.pop().ok_or_else(|| Halt::Failed("the witness is empty".into()))?);
+            if script_pubkey.as_bytes()[2..] != sha256::Hash::hash(script.as_bytes())[..] {
+                return fail("the witness script does not match the output");
+            }
+            return self.run(&script, witness, Version::SegwitV0);
+        }
+
+        if script_pubkey.is_v1_p2tr() {
+            let output_key = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..])
+                .or_else(|_| fail("the output key is not a valid point"))?;
+            if witness.len() >= 2 && witness.last().is_some_and(|last| last.first() == Some(&0x50)) {
+                return Err(Halt::Unsupported("annexes are not supported".into()));
+            }
+            return match witness.len() {
+                0 => fail("the witness is empty"),
+                // Key path
+                1 => {
+                    let sig = SchnorrSig::from_slice(&witness[0]).or_else(|e| fail(e.to_string()))?;
+                    let sighash = SighashCache::new(self.tx)
+                        .taproot_key_spend_signature_hash(self.index, &Prevouts::All(self.spent), sig.hash_ty)
+                        .or_else(|e| fail(e.to_string()))?;
+                    let message = Message::from_slice(&sighash[..]).expect("sighashes are 32 bytes");
+                    self.secp.verify_schnorr(&sig.sig, &message, &output_key)
+                        .or_else(|_| fail("the key path signature does not verify"))
+                }
+                _ => {
+                    let control_block = ControlBlock::from_slice(&witness.pop().unwrap()).or_else(|e| fail(e.to_string()))?;
+                    let script = Script::from(witness.pop().unwrap());
+                    if control_block.leaf_version != LeafVersion::TapScript {
+                        return Err(Halt::Unsupported("only tapscript leaves are supported".into()));
+                    }
+                    if !control_block.verify_taproot_commitment(&self.secp, output_key, &script) {
+                        return fail("the control block does not commit to the script");
+                    }
+                    let leaf_hash = TapLeafHash::from_script(&script, LeafVersion::TapScript);
+                    self.run(&script, witness, Version::Tapscript(leaf_hash))
+                }
+            };
+        }
+
+        Err(Halt::Unsupported("only native segwit outputs are supported".into()))
+    }
+
+    fn run(&self, script: &Script, stack: Vec<Vec<u8>>, version: Version) -> Result<(), Halt> {
+        let mut machine = Machine { checker: self, script, version, stack, alt: Vec::new(), branches: Vec::new() };
+        for instruction in script.instructions() {
+            let instruction = instruction.or_else(|e| fail(e.to_string()))?;
+            machine.step(instruction)?;
+        }
+        if !machine.branches.is_empty() {
+            return fail("unbalanced conditional");
+        }
+        // Segwit scripts must leave exactly one true element
+        match machine.stack.as_slice() {
+            [top] if truthy(top) => Ok(()),
+            [_] => fail("the script leaves false on the stack"),
+            _ => fail(format!("the script leaves {} elements instead of 1", machine.stack.len())),
+        }
+    }
+
+    fn check_ecdsa(&self, script: &Script, sig: &[u8], key: &[u8]) -> Result<bool, Halt> {
+        if sig.is_empty() {
+            return Ok(false);
+        }
+        let sig = EcdsaSig::from_slice(sig).or_else(|e| fail(e.to_string()))?;
+        let key = PublicKey::from_slice(key).or_else(|e| fail(e.to_string()))?;
+        let sighash = SighashCache::new(self.tx)
+            .segwit_signature_hash(self.index, script, self.spent[self.index].value, sig.hash_ty)
+            .or_else(|e| fail(e.to_string()))?;
+        let message = Message::from_slice(&sighash[..]).expect("sighashes are 32 bytes");
+        Ok(self.secp.verify_ecdsa(&message, &sig.sig, &key.inner).is_ok())
+    }
+
+    fn check_schnorr(&self, leaf_hash: TapLeafHash, sig: &[u8], key: &[u8]) -> Result<bool, Halt> {
+        if key.len() != 32 {
+            return Err(Halt::Unsupported("only 32-byte tapscript keys are supported".into()));
+        }
+        if sig.is_empty() {
+            return Ok(false);
+        }
+        let sig = SchnorrSig::from_slice(sig).or_else(|e| fail(e.to_string()))?;
+        let key = XOnlyPublicKey::from_slice(key).or_else(|e| fail(e.to_string()))?;
+        let sighash = SighashCache::new(self.tx)
+            .taproot_script_spend_signature_hash(self.index, &Prevouts::All(self.spent), leaf_hash, sig.hash_ty)
+            .or_else(|e| fail(e.to_string()))?;
+        let message = Message::from_slice(&sighash[..]).expect("sighashes are 32 bytes");
+        Ok(self.secp.verify_schnorr(&sig.sig, &message, &key).is_ok())
+    }
+
+    fn check_lock_time(&self, lock: i64) -> Result<(), Halt> {
+        let lock_time = i64::from(self.tx.lock_time.to_u32());
+        if (lock < LOCK_TIME_THRESHOLD) != (lock_time < LOCK_TIME_THRESHOLD) {
+            return fail("CHECKLOCKTIMEVERIFY mixes heights and times");
+        }
+        if lock > lock_time {
+            return fail(format!("CHECKLOCKTIMEVERIFY needs a lock time of at least {}", lock));
+        }
+        if self.tx.input[self.index].sequence.is_final() {
+            return fail("CHECKLOCKTIMEVERIFY needs a non-final nSequence");
+        }
+        Ok(())
+    }
+
+    fn check_sequence(&self, lock: i64) -> Result<(), Halt> {
+        let lock = lock as u32;
+        if lock & SEQUENCE_DISABLE != 0 {
+            return Ok(());
+        }
+        let sequence = self.tx.input[self.index].sequence.0;
+        if self.tx.version < 2 || sequence & SEQUENCE_DISABLE != 0 {
+            return fail("CHECKSEQUENCEVERIFY needs version 2 and a relative lock time in nSequence");
+        }
+        if lock & SEQUENCE_TYPE != sequence & SEQUENCE_TYPE {
+            return fail("CHECKSEQUENCEVERIFY mixes blocks and time");
+        }
+        if lock & SEQUENCE_MASK > sequence & SEQUENCE_MASK {
+            return fail(format!("CHECKSEQUENCEVERIFY needs an nSequence of at least {}", lock & SEQUENCE_MASK));
+        }
+        Ok(())
+    }
+}
+
+struct Machine<'a> {
+    checker: &'a Checker<'a>,
+    script: &'a Script,
+    version: Version,
+    stack: Vec<Vec<u8>>,
+    alt: Vec<Vec<u8>>,
+    // Whether each open IF branch is executing
+    branches: Vec<bool>,
+}
+
+impl Machine<'_> {
+    fn pop(&mut self) -> Result<Vec<u8>, Halt> {
+        self.stack.pop().ok_or_else(|| Halt::Failed("the stack is empty".into()))
+    }
+
+    fn pop_num(&mut self, max_len: usize) -> Result<i64, Halt> {
+        let bytes = self.pop()?;
+        decode_num(&bytes, max_len).ok_or_else(|| Halt::Failed("a number is out of range or not minimally encoded".into()))
+    }
+
+    fn push_bool(&mut self, value: bool) {
+        self.stack.push(if value { vec![1] } else { Vec::new() });
+    }
+
+    fn verify_top(&mut self, what: &str) -> Result<(), Halt> {
+        if truthy(&self.pop()?) { Ok(()) } else { fail(format!("{} failed", what)) }
+    }
+
+    fn checksig(&mut self) -> Result<bool, Halt> {
+        let key = self.pop()?;
+        let sig = self.pop()?;
+        let valid = match self.version {
+            Version::SegwitV0 => self.checker.check_ecdsa(self.script, &sig, &key)?,
+            Version::Tapscript(leaf_hash) => self.checker.check_schnorr(leaf_hash, &sig, &key)?,
+        };
+        // NULLFAIL, and BIP-342 for tapscript: only an empty signature may fail without failing the script
+        if !valid && !sig.is_empty() {
+            return fail("a signature does not verify");
+        }
+        Ok(valid)
+    }
+
+    fn step(&mut self, instruction: Instruction) -> Result<(), Halt> {
+        let executing = self.branches.iter().all(|branch| *branch);
+        let op = match instruction {
+            Instruction::PushBytes(bytes) => {
+                if executing {
+                    self.stack.push(bytes.to_vec());
+                }
+                return Ok(());
+            }
+            Instruction::Op(op) => op,
+        };
+
+        match op {
+            OP_IF | OP_NOTIF => {
+                let taken = if executing {
+                    // MINIMALIF: the condition must be empty or exactly 1
+                    let condition = self.pop()?;
+                    if condition.len() > 1 || condition.first().is_some_and(|byte| *byte != 1) {
+                        return fail("IF takes an empty element or 1");
+                    }
+                    (condition == [1]) == (op == OP_IF)
+                } else {
+                    false
+                };
+                self.branches.push(taken);
+                return Ok(());
+            }
+            OP_ELSE => {
+                let branch = self.branches.last_mut().ok_or_else(|| Halt::Failed("ELSE without IF".into()))?;
+                *branch = !*branch;
+                return Ok(());
+            }
+            OP_ENDIF => {
+                self.branches.pop().ok_or_else(|| Halt::Failed("ENDIF without IF".into()))?;
+                return Ok(());
+            }
+            _ if !executing => return Ok(()),
+            _ => {}
+        }
+
+        let code = op.to_u8();
+        if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&code) {
+            self.stack.push(encode_num(i64::from(code - OP_PUSHNUM_1.to_u8() + 1)));
+            return Ok(());
+        }
+
+        match op {
+            OP_PUSHNUM_NEG1 => self.stack.push(encode_num(-1)),
+            OP_VERIFY => self.verify_top("VERIFY")?,
+            OP_RETURN => return fail("OP_RETURN"),
+            OP_TOALTSTACK => {
+                let top = self.pop()?;
+                self.alt.push(top);
+            }
+            OP_FROMALTSTACK => {
+                let top = self.alt.pop().ok_or_else(|| Halt::Failed("the alt stack is empty".into()))?;
+                self.stack.push(top);
+            }
+            OP_IFDUP => {
+                let top = self.stack.last().cloned().ok_or_else(|| Halt::Failed("the stack is empty".into()))?;
+                if truthy(&top) {
+                    self.stack.push(top);
+                }
+            }
+            OP_DROP => {
+                self.pop()?;
+            }
+            OP_DUP => {
+                let top = self.stack.last().cloned().ok_or_else(|| Halt::Failed("the stack is empty".into()))?;
+                self.stack.push(top);
+            }
+            OP_SWAP => {
+                let top = self.pop()?;
+                let below = self.pop()?;
+                self.stack.extend([top, below]);
+            }
+            OP_SIZE => {
+                let size = self.stack.last().map(Vec::len).ok_or_else(|| Halt::Failed("the stack is empty".into()))?;
+                self.stack.push(encode_num(size as i64));
+            }
+            OP_EQUAL | OP_EQUALVERIFY => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.push_bool(a == b);
+                if op == OP_EQUALVERIFY {
+                    self.verify_top("EQUALVERIFY")?;
+                }
+            }
+            OP_0NOTEQUAL => {
+                let n = self.pop_num(4)?;
+                self.push_bool(n != 0);
+            }
+            OP_ADD | OP_BOOLAND | OP_BOOLOR | OP_NUMEQUAL | OP_NUMEQUALVERIFY => {
+                let b = self.pop_num(4)?;
+                let a = self.pop_num(4)?;
+                match op {
+                    OP_ADD => self.stack.push(encode_num(a + b)),
+                    OP_BOOLAND => self.push_bool(a != 0 && b != 0),
+                    OP_BOOLOR => self.push_bool(a != 0 || b != 0),
+                    _ => {
+                        self.push_bool(a == b);
+                        if op == OP_NUMEQUALVERIFY {
+                            self.verify_top("NUMEQUALVERIFY")?;
+                        }
+                    }
+                }
+            }
+            OP_RIPEMD160 | OP_SHA256 | OP_HASH160 | OP_HASH256 => {
+                let data = self.pop()?;
+                self.stack.push(match op {
+                    OP_RIPEMD160 => ripemd160::Hash::hash(&data).to_vec(),
+                    OP_SHA256 => sha256::Hash::hash(&data).to_vec(),
+                    OP_HASH160 => hash160::Hash::hash(&data).to_vec(),
+                    _ => sha256d::Hash::hash(&data).to_vec(),
+                });
+            }
+            OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                let valid = self.checksig()?;
+                self.push_bool(valid);
+                if op == OP_CHECKSIGVERIFY {
+                    self.verify_top("CHECKSIGVERIFY")?;
+                }
+            }
+            OP_CHECKSIGADD if matches!(self.version, Version::Tapscript(_)) => {
+                let key = self.pop()?;
+                let n = self.pop_num(4)?;
+                let sig = self.pop()?;
+                self.stack.extend([sig, key]);
+                let valid = self.checksig()?;
+                self.stack.push(encode_num(n + i64::from(valid)));
+            }
+            OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY if self.version == Version::SegwitV0 => {
+                let key_count = self.pop_num(4)?;
+                if !(0..=20).contains(&key_count) {
+                    return fail("CHECKMULTISIG takes at most 20 keys");
+                }
+                let keys = (0..key_count).map(|_| self.pop()).collect::<Result<Vec<_>, _>>()?;
+                let sig_count = self.pop_num(4)?;
+                if !(0..=key_count).contains(&sig_count) {
+                    return fail("CHECKMULTISIG wants more signatures than keys");
+                }
+                let sigs = (0..sig_count).map(|_| self.pop()).collect::<Result<Vec<_>, _>>()?;
+                // NULLDUMMY: the extra element CHECKMULTISIG pops must be empty
+                if !self.pop()?.is_empty() {
+                    return fail("the CHECKMULTISIG dummy element must be empty");
+                }
+
+                // Both were popped top first, so both are in reverse script order, which keeps them matched
+                let mut keys = keys.iter();
+                let mut valid = true;
+                for sig in &sigs {
+                    let mut matched = false;
+                    for key in keys.by_ref() {
+                        if self.checker.check_ecdsa(self.script, sig, key)? {
+                            matched = true;
+                            break;
+                        }
+                    }
+                    if !matched {
+                        valid = false;
+                        break;
+                    }
+                }
+                if !valid && sigs.iter().any(|sig| !sig.is_empty()) {
+                    return fail("a signature does not verify");
+                }
+                self.push_bool(valid);
+                if op == OP_CHECKMULTISIGVERIFY {
+                    self.verify_top("CHECKMULTISIGVERIFY")?;
+                }
+            }
+            OP_CLTV => {
+                let lock = self.stack.last().ok_or_else(|| Halt::Failed("the stack is empty".into()))?;
+                let lock = decode_num(lock, 5).filter(|lock| *lock >= 0).ok_or_else(|| Halt::Failed("invalid lock time".into()))?;
+                self.checker.check_lock_time(lock)?;
+            }
+            OP_CSV => {
+                let lock = self.stack.last().ok_or_else(|| Halt::Failed("the stack is empty".into()))?;
+                let lock = decode_num(lock, 5).filter(|lock| *lock >= 0).ok_or_else(|| Halt::Failed("invalid sequence".into()))?;
+                self.checker.check_sequence(lock)?;
+            }
+            _ => return Err(Halt::Unsupported(format!("{:?} is not supported", op))),
+        }
+        Ok(())
+    }
+}
+
+fn truthy(element: &[u8]) -> bool {
+    match element.split_last() {
+        None => false,
+        // Negative zero is false too
+        Some((last, rest)) => rest.iter().any(|byte| *byte != 0) || (*last != 0 && *last != 0x80),
+    }
+}
+
+// Script numbers are little-endian with a sign bit. Like MINIMALIF, MINIMALDATA is node policy, so
+// numbers padded with a needless zero byte are refused.
+fn decode_num(bytes: &[u8], max_len: usize) -> Option<i64> {
+    if bytes.len() > max_len {
+        return None;
+    }
+    let Some((last, rest)) = bytes.split_last() else {
+        return Some(0);
+    };
+    if last & 0x7f == 0 && rest.last().is_none_or(|byte| byte & 0x80 == 0) {
+        return None;
+    }
+    let mut value = bytes.iter().rev().fold(0i64, |value, byte| value << 8 | i64::from(*byte));
+    if last & 0x80 != 0 {
+        value &= !(0x80i64 << (8 * (bytes.len() - 1)));
+        value = -value;
+    }
+    Some(value)
+}
+
+fn encode_num(value: i64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut magnitude = value.unsigned_abs();
+    while magnitude > 0 {
+        bytes.push((magnitude & 0xff) as u8);
+        magnitude >>= 8;
+    }
+    if let Some(last) = bytes.last_mut() {
+        if *last & 0x80 != 0 {
+            bytes.push(if value < 0 { 0x80 } else { 0 });
+        } else if value < 0 {
+            *last |= 0x80;
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::blockdata::script::Builder;
+    use bitcoin::secp256k1::SecretKey;
+    use bitcoin::{EcdsaSighashType, OutPoint, PackedLockTime, Sequence, TxIn, Witness};
+
+    const VALUE: u64 = 10_000;
+
+    // Spends a P2WSH output of `script` with `stack` beneath the witness script
+    fn spend(script: &Script, stack: Vec<Vec<u8>>, version: i32, lock_time: u32, sequence: u32) -> Result<(), VerifyError> {
+        let mut tx = unsigned(version, lock_time, sequence);
+        tx.input[0].witness = Witness::from_vec(stack.into_iter().chain([script.to_bytes()]).collect());
+        verify_input(&tx, 0, &[TxOut { value: VALUE, script_pubkey: script.to_v0_p2wsh() }])
+    }
+
+    fn unsigned(version: i32, lock_time: u32, sequence: u32) -> Transaction {
+        Transaction {
+            version,
+            lock_time: PackedLockTime(lock_time),
+            input: vec![TxIn { previous_output: OutPoint::default(), script_sig: Script::new(), sequence: Sequence(sequence), witness: Witness::new() }],
+            output: vec![TxOut { value: VALUE - 1_000, script_pubkey: Script::new() }],
+        }
+    }
+
+    fn failed(reason: &str) -> Result<(), VerifyError> {
+        Err(VerifyError::Failed(0, reason.to_string()))
+    }
+
+    #[test]
+    fn test_if_takes_only_minimal_conditions() {
+        let script = Builder::new()
+            .push_opcode(OP_IF).push_int(1).push_opcode(OP_ELSE).push_int(1).push_opcode(OP_ENDIF)
+            .into_script();
+        assert_eq!(spend(&script, vec![vec![1]], 2, 0, 0), Ok(()));
+        assert_eq!(spend(&script, vec![vec![]], 2, 0, 0), Ok(()));
+        for condition in [vec![2], vec![1, 0], vec![0]] {
+            assert_eq!(spend(&script, vec![condition], 2, 0, 0), failed("IF takes an empty element or 1"));
+        }
+
+        // NOTIF takes the first branch on the empty element
+        let script = Builder::new()
+            .push_opcode(OP_NOTIF).push_int(1).push_opcode(OP_ELSE).push_int(0).push_opcode(OP_ENDIF)
+            .into_script();
+        assert_eq!(spend(&script, vec![vec![]], 2, 0, 0), Ok(()));
+        assert_eq!(spend(&script, vec![vec![1]], 2, 0, 0), failed("the script leaves false on the stack"));
+    }
+
+    #[test]
+    fn test_checkmultisig_matches_signatures_in_key_order() {
+        let secp = Secp256k1::new();
+        let secrets: Vec<SecretKey> = (1..=3).map(|byte| SecretKey::from_slice(&[byte; 32]).unwrap()).collect();
+        let keys: Vec<PublicKey> = secrets.iter()
+            .map(|secret| PublicKey::new(bitcoin::secp256k1::PublicKey::from_secret_key(&secp, secret)))
+            .collect();
+        let script = keys.iter()
+            .fold(Builder::new().push_int(2), |builder, key| builder.push_key(key))
+            .push_int(3)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+
+        let tx = unsigned(2, 0, u32::MAX);
+        let sighash = SighashCache::new(&tx).segwit_signature_hash(0, &script, VALUE, EcdsaSighashType::All).unwrap();
+        let message = Message::from_slice(&sighash[..]).unwrap();
+        let sig = |index: usize| EcdsaSig::sighash_all(secp.sign_ecdsa(&message, &secrets[index])).to_vec();
+
+        assert_eq!(spend(&script, vec![vec![], sig(0), sig(1)], 2, 0, u32::MAX), Ok(()));
+        assert_eq!(spend(&script, vec![vec![], sig(0), sig(2)], 2, 0, u32::MAX), Ok(()));
+        // Signatures out of key order never match
+        assert_eq!(spend(&script, vec![vec![], sig(1), sig(0)], 2, 0, u32::MAX), failed("a signature does not verify"));
+        assert_eq!(spend(&script, vec![vec![], sig(0), sig(0)], 2, 0, u32::MAX), failed("a signature does not verify"));
+        // Empty signatures fail without failing the script, which then leaves false
+        assert_eq!(spend(&script, vec![vec![], vec![], vec![]], 2, 0, u32::MAX), failed("the script leaves false on the stack"));
+        // NULLDUMMY
+        assert_eq!(spend(&script, vec![vec![0], sig(0), sig(1)], 2, 0, u32::MAX), failed("the CHECKMULTISIG dummy element must be empty"));
+        assert_eq!(spend(&script, vec![sig(0), sig(1)], 2, 0, u32::MAX), failed("the stack is empty"));
+
+        let greedy = Builder::new().push_int(2).push_key(&keys[0]).push_int(1).push_opcode(OP_CHECKMULTISIG).into_script();
+        assert_eq!(spend(&greedy, vec![vec![], sig(0), sig(0)], 2, 0, u32::MAX), failed("CHECKMULTISIG wants more signatures than keys"));
+    }
+
+    #[test]
+    fn test_checksequenceverify_edge_cases() {
+        let csv = |lock: i64| Builder::new().push_int(lock).push_opcode(OP_CSV).push_opcode(OP_DROP).push_int(1).into_script();
+
+        assert_eq!(spend(&csv(10), vec![], 2, 0, 10), Ok(()));
+        assert_eq!(spend(&csv(10), vec![], 2, 0, 9), failed("CHECKSEQUENCEVERIFY needs an nSequence of at least 10"));
+        let needs_relative = failed("CHECKSEQUENCEVERIFY needs version 2 and a relative lock time in nSequence");
+        assert_eq!(spend(&csv(10), vec![], 1, 0, 10), needs_relative);
+        assert_eq!(spend(&csv(10), vec![], 2, 0, SEQUENCE_DISABLE | 10), needs_relative);
+        // Time-based locks only compare against time-based sequences
+        let time = i64::from(SEQUENCE_TYPE | 10);
+        assert_eq!(spend(&csv(time), vec![], 2, 0, 10), failed("CHECKSEQUENCEVERIFY mixes blocks and time"));
+        assert_eq!(spend(&csv(time), vec![], 2, 0, SEQUENCE_TYPE | 10), Ok(()));
+        // A disabled lock passes whatever the transaction says
+        assert_eq!(spend(&csv(i64::from(SEQUENCE_DISABLE)), vec![], 1, 0, u32::MAX), Ok(()));
+        assert_eq!(spend(&csv(-1), vec![], 2, 0, 10), failed("invalid sequence"));
+    }
+
+    #[test]
+    fn test_checklocktimeverify_edge_cases() {
+        let cltv = |lock: i64| Builder::new().push_int(lock).push_opcode(OP_CLTV).push_opcode(OP_DROP).push_int(1).into_script();
+        let non_final = u32::MAX - 1;
+
+        assert_eq!(spend(&cltv(100), vec![], 2, 100, non_final), Ok(()));
+        assert_eq!(spend(&cltv(100), vec![], 2, 99, non_final), failed("CHECKLOCKTIMEVERIFY needs a lock time of at least 100"));
+        assert_eq!(spend(&cltv(100), vec![], 2, 500_000_001, non_final), failed("CHECKLOCKTIMEVERIFY mixes heights and times"));
+        assert_eq!(spend(&cltv(100), vec![], 2, 100, u32::MAX), failed("CHECKLOCKTIMEVERIFY needs a non-final nSequence"));
+        assert_eq!(spend(&cltv(-1), vec![], 2, 100, non_final), failed("invalid lock time"));
+        // Lock times take five bytes, one more than other numbers
+        let five_bytes = i64::from(u32::MAX);
+        assert_eq!(spend(&cltv(five_bytes), vec![], 2, u32::MAX, non_final), Ok(()));
+        let six_bytes = Builder::new().push_slice(&[0, 0, 0, 0, 0, 1]).push_opcode(OP_CLTV).into_script();
+        assert_eq!(spend(&six_bytes, vec![], 2, u32::MAX, non_final), failed("invalid lock time"));
+    }
+
+    #[test]
+    fn test_script_numbers_are_minimal_and_bounded() {
+        for value in [0, 1, -1, 127, -127, 128, -128, 255, 256, 0x7fff_ffff, -0x7fff_ffff] {
+            assert_eq!(decode_num(&encode_num(value), 4), Some(value), "{}", value);
+        }
+        assert_eq!(encode_num(0), Vec::<u8>::new());
+        assert_eq!(encode_num(-1), [0x81]);
+        assert_eq!(encode_num(128), [0x80, 0x00]);
+        assert_eq!(encode_num(-128), [0x80, 0x80]);
+
+        // Padding and negative zero are not minimal
+        assert_eq!(decode_num(&[0x01, 0x00], 4), None);
+        assert_eq!(decode_num(&[0x80], 4), None);
+        assert_eq!(decode_num(&[0x00], 4), None);
+        assert_eq!(decode_num(&[0xff, 0x00], 4), Some(255));
+        assert_eq!(decode_num(&[0, 0, 0, 0x80, 0], 4), None);
+        assert_eq!(decode_num(&[0, 0, 0, 0x80, 0], 5), Some(0x8000_0000));
+
+        // Sums may overflow four bytes, but cannot then be used as operands
+        let max = 0x7fff_ffff;
+        let script = Builder::new().push_int(max).push_int(1).push_opcode(OP_ADD)
+            .push_slice(&encode_num(max + 1)).push_opcode(OP_EQUAL).into_script();
+        assert_eq!(spend(&script, vec![], 2, 0, 0), Ok(()));
+        let script = Builder::new().push_int(max).push_int(1).push_opcode(OP_ADD).push_int(1).push_opcode(OP_ADD).into_script();
+        assert_eq!(spend(&script, vec![], 2, 0, 0), failed("a number is out of range or not minimally encoded"));
+        // A padded operand is refused as well
+        let script = Builder::new().push_slice(&[1, 0]).push_opcode(OP_0NOTEQUAL).into_script();
+        assert_eq!(spend(&script, vec![], 2, 0, 0), failed("a number is out of range or not minimally encoded"));
+    }
+}
@@ -0,0 +1,274 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::watch;
+use utoipa::ToSchema;
+use crate::budget::BudgetBreach;
+use crate::engine::ProjectionEngine;
+use crate::error::{ApiError, ErrorBody};
+use crate::metrics::metrics;
+use crate::projection::{self, Period, ProjectionOptions};
+use crate::spending::UserModel;
+
+// Finished jobs kept around for late subscribers before the oldest are dropped
+const MAX_FINISHED_JOBS: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JobEvent {
+    // One projected year, sent as soon as it is ready
+    Progress { completed: u32, total: u32, periods: Vec<Period> },
+    Completed { breaches: Vec<BudgetBreach> },
+    Failed { error: ErrorBody },
+    Cancelled,
+}
+
+impl JobEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobEvent::Progress { .. } => "progress",
+            JobEvent::Completed { .. } => "completed",
+            JobEvent::Failed { .. } => "failed",
+            JobEvent::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct JobSnapshot {
+    pub job_id: String,
+    pub user_id: String,
+    pub status: JobStatus,
+    pub completed: u32,
+    pub total: u32,
+}
+
+struct JobState {
+    status: JobStatus,
+    completed: u32,
+    events: Vec<JobEvent>,
+}
+
+// A projection running in the background; its events are kept so subscribers can replay them
+pub struct Job {
+    seq: u64,
+    id: String,
+    user_id: String,
+    total: u32,
+    state: Mutex<JobState>,
+    // Carries the event count so subscribers wake when something new arrives
+    updates: watch::Sender<usize>,
+}
+
+impl Job {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn user_id(&self) -> &str {
+        &self.user_id
+    }
+
+    pub fn status(&self) -> JobStatus {
+        self.state.lock().unwrap().status
+    }
+
+    pub fn snapshot(&self) -> JobSnapshot {
+        let state = self.state.lock().unwrap();
+        JobSnapshot {
+            job_id: self.id.clone(),
+            user_id: self.user_id.clone(),
+            status: state.status,
+            completed: state.completed,
+            total: self.total,
+        }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<usize> {
+        self.updates.subscribe()
+    }
+
+    // Events from `cursor` on, and whether the job has finished
+    pub fn events_since(&self, cursor: usize) -> (Vec<JobEvent>, bool) {
+        let state = self.state.lock().unwrap();
+        let events = state.events.get(cursor..).map(<[JobEvent]>::to_vec).unwrap_or_default();
+        (events, state.status != JobStatus::Running)
+    }
+
+    // Records an event unless the job has already finished; returns whether it was recorded
+    fn push(&self, event: JobEvent, status: JobStatus) -> bool {
+        let count = {
+            let mut state = self.state.lock().unwrap();
+            if state.status != JobStatus::Running {
+                return false;
+            }
+            if let JobEvent::Progress { completed, .. } = &event {
+                state.completed = *completed;
+            }
+            state.events.push(event);
+            state.status = status;
+            state.events.len()
+        };
+        self.updates.send_replace(count);
+        true
+    }
+
+    // Stops the job; the worker notices before its next year. Returns false if it had already finished
+    pub fn cancel(&self) -> bool {
+        self.push(JobEvent::Cancelled, JobStatus::Cancelled)
+    }
+
+    pub fn fail(&self, error: ApiError) -> bool {
+        self.push(JobEvent::Failed { error: error.body }, JobStatus::Failed)
+    }
+
+    // Projects the model one year at a time on the current thread, reporting each year as it completes
+    pub fn run(&self, model: &UserModel, options: &ProjectionOptions, engine: &ProjectionEngine) {
+        for year in 0..options.horizon {
+            if self.status() != JobStatus::Running {
+                return;
+            }
+
+            match projection::project_year(model, options, engine, year) {
+                Ok(periods) => {
+                    self.push(JobEvent::Progress { completed: year + 1, total: options.horizon, periods }, JobStatus::Running);
+                }
+                Err(e) => {
+                    self.fail(e.into());
+                    return;
+                }
+            }
+        }
+
+        metrics().observe_projection((options.horizon * options.granularity.periods_per_year()) as usize);
+
+        match model.check_budget() {
+            Ok(breaches) => self.push(JobEvent::Completed { breaches }, JobStatus::Completed),
+            Err(e) => self.fail(e.into()),
+        };
+    }
+}
+
+pub struct JobStore {
+    jobs: RwLock<HashMap<String, Arc<Job>>>,
+    next_seq: AtomicU64,
+}
+
+impl Default for JobStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JobStore {
+    pub fn new() -> Self {
+        Self {
+            jobs: RwLock::new(HashMap::new()),
+            next_seq: AtomicU64::new(1),
+        }
+    }
+
+    // Registers a job of `total` years once `admit` accepts the user's running job count
+    pub fn create<E>(&self, user_id: &str, total: u32, admit: impl FnOnce(usize) -> Result<(), E>) -> Result<Arc<Job>, E> {
+        let mut jobs = self.jobs.write().unwrap();
+
+        let running = jobs.values()
+            .filter(|job| job.user_id == user_id && job.status() == JobStatus::Running)
+            .count();
+        admit(running)?;
+
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        let job = Arc::new(Job {
+            seq,
+            id: format!("job-{}", seq),
+            user_id: user_id.to_string(),
+            total,
+            state: Mutex::new(JobState {
+                status: JobStatus::Running,
+                completed: 0,
+                events: Vec::new(),
+            }),
+            updates: watch::channel(0).0,
+        });
+        jobs.insert(job.id.clone(), job.clone());
+
+        Self::prune(&mut jobs);
+        Ok(job)
+    }
+
+    pub fn get(&self, job_id: &str) -> Option<Arc<Job>> {
+        self.jobs.read().unwrap().get(job_id).cloned()
+    }
+
+    fn prune(jobs: &mut HashMap<String, Arc<Job>>) {
+        let mut finished: Vec<(u64, String)> = jobs.values()
+            .filter(|job| job.status() != JobStatus::Running)
+            .map(|job| (job.seq, job.id.clone()))
+            .collect();
+
+        if finished.len() > MAX_FINISHED_JOBS {
+            finished.sort_unstable();
+            for (_, id) in &finished[..finished.len() - MAX_FINISHED_JOBS] {
+                jobs.remove(id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spending::{GrowthType, ProjectSpend};
+
+    fn model() -> UserModel {
+        let mut user = UserModel::new("alice".into(), 3).unwrap();
+        user.add_project(ProjectSpend::new("Rent".into(), 10.0, 0.0, GrowthType::Flat).unwrap());
+        user
+    }
+
+    #[test]
+    fn test_job_reports_each_year_then_completes() {
+        let store = JobStore::new();
+        let user = model();
+        let job = store.create("alice", 3, |_| Ok::<_, ()>(())).unwrap();
+
+        job.run(&user, &ProjectionOptions::for_model(&user), &ProjectionEngine::new());
+
+        let (events, finished) = job.events_since(0);
+        assert!(finished);
+        let names: Vec<&str> = events.iter().map(JobEvent::name).collect();
+        assert_eq!(names, ["progress", "progress", "progress", "completed"]);
+        assert_eq!(job.snapshot().completed, 3);
+        assert_eq!(job.events_since(2).0.len(), 2);
+    }
+
+    #[test]
+    fn test_cancelled_job_stops_and_frees_its_slot() {
+        let store = JobStore::new();
+        let user = model();
+        let admit = |running: usize| if running < 1 { Ok(()) } else { Err(running) };
+
+        let job = store.create("alice", 3, admit).unwrap();
+        assert_eq!(store.create("alice", 3, admit).err(), Some(1));
+        assert!(store.create("bob", 3, admit).is_ok());
+
+        assert!(job.cancel());
+        assert!(!job.cancel());
+        job.run(&user, &ProjectionOptions::for_model(&user), &ProjectionEngine::new());
+
+        let (events, _) = job.events_since(0);
+        assert_eq!(events.len(), 1);
+        assert_eq!(job.status(), JobStatus::Cancelled);
+        assert!(store.create("alice", 3, admit).is_ok());
+    }
+}
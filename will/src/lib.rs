@@ -1,2 +1,34 @@
 pub mod spending;
-pub mod web; 
\ No newline at end of file
+pub mod engine;
+pub mod batch;
+pub mod budget;
+pub mod projection;
+pub mod draw;
+pub mod chart;
+pub mod jobs;
+pub mod alerts;
+pub mod import;
+pub mod store;
+pub mod history;
+pub mod audit;
+pub mod rpc;
+pub mod escrow_script;
+pub mod miniscript;
+pub mod taproot;
+pub mod coin_selection;
+pub mod psbt;
+#[cfg(any(test, feature = "local-signer"))]
+pub mod interpreter;
+#[cfg(any(test, feature = "local-signer"))]
+pub mod signer;
+pub mod release;
+pub mod escrow;
+pub mod error;
+pub mod nostr;
+pub mod auth;
+pub mod config;
+pub mod ratelimit;
+pub mod metrics;
+pub mod web;
+#[cfg(test)]
+pub(crate) mod test_support;
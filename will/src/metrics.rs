@@ -0,0 +1,129 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use prometheus::{Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+// Process-wide metrics, shared by the web layer and the projection engine
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    formula_eval_duration: Histogram,
+    projection_periods: Histogram,
+    escrow_broadcasts: IntCounterVec,
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::new)
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route, method and status"),
+            &["route", "method", "status"],
+        ).expect("valid metric");
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency by route"),
+            &["route"],
+        ).expect("valid metric");
+        let formula_eval_duration = Histogram::with_opts(
+            HistogramOpts::new("formula_eval_duration_seconds", "Time spent evaluating one custom Rhai growth formula")
+                .buckets(vec![0.00001, 0.0001, 0.001, 0.01, 0.1, 1.0]),
+        ).expect("valid metric");
+        let projection_periods = Histogram::with_opts(
+            HistogramOpts::new("projection_periods", "Periods produced per projection")
+                .buckets(vec![1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0]),
+        ).expect("valid metric");
+        let escrow_broadcasts = IntCounterVec::new(
+            Opts::new("escrow_broadcasts_total", "Escrow transaction broadcasts by result"),
+            &["result"],
+        ).expect("valid metric");
+
+        let registry = Registry::new();
+        registry.register(Box::new(http_requests.clone())).expect("unique metric");
+        registry.register(Box::new(http_request_duration.clone())).expect("unique metric");
+        registry.register(Box::new(formula_eval_duration.clone())).expect("unique metric");
+        registry.register(Box::new(projection_periods.clone())).expect("unique metric");
+        registry.register(Box::new(escrow_broadcasts.clone())).expect("unique metric");
+
+        Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            formula_eval_duration,
+            projection_periods,
+            escrow_broadcasts,
+        }
+    }
+
+    pub fn observe_request(&self, route: &str, method: &str, status: u16, elapsed: Duration) {
+        self.http_requests.with_label_values(&[route, method, &status.to_string()]).inc();
+        self.http_request_duration.with_label_values(&[route]).observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_formula(&self, elapsed: Duration) {
+        self.formula_eval_duration.observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_projection(&self, periods: usize) {
+        self.projection_periods.observe(periods as f64);
+    }
+
+    pub fn record_broadcast(&self, success: bool) {
+        let result = if success { "success" } else { "failure" };
+        self.escrow_broadcasts.with_label_values(&[result]).inc();
+    }
+
+    // The Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("metrics encode to text");
+        String::from_utf8(buffer).expect("metrics text is UTF-8")
+    }
+}
+
+// Counts and times every request, labelled by the matched route pattern to keep cardinality bounded
+pub async fn track_requests<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<B>, actix_web::Error> {
+    let started = Instant::now();
+    let method = req.method().to_string();
+    let result = next.call(req).await;
+
+    let (route, status) = match &result {
+        Ok(res) => (
+            res.request().match_pattern().unwrap_or_else(|| "unmatched".to_string()),
+            res.status().as_u16(),
+        ),
+        Err(e) => ("unmatched".to_string(), e.as_response_error().status_code().as_u16()),
+    };
+    metrics().observe_request(&route, &method, status, started.elapsed());
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_includes_recorded_series() {
+        let metrics = Metrics::new();
+        metrics.observe_request("/users/{user_id}", "GET", 200, Duration::from_millis(5));
+        metrics.record_broadcast(false);
+        metrics.observe_projection(12);
+
+        let text = metrics.render();
+        assert!(text.contains(r#"http_requests_total{method="GET",route="/users/{user_id}",status="200"} 1"#));
+        assert!(text.contains(r#"escrow_broadcasts_total{result="failure"} 1"#));
+        assert!(text.contains("projection_periods_count 1"));
+    }
+}
@@ -0,0 +1,749 @@
+use bitcoin::blockdata::locktime::LOCK_TIME_THRESHOLD;
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::hashes::{hash160, ripemd160, sha256};
+use bitcoin::secp256k1::{PublicKey, XOnlyPublicKey};
+use bitcoin::Network;
+use chrono::{TimeZone, Utc};
+use miniscript::descriptor::{Tr, Wsh, WshInner};
+use miniscript::policy::{Concrete, Liftable, Semantic};
+use miniscript::{hash256, Miniscript, MiniscriptKey, ScriptContext, Segwitv0, SigType, Tap, TranslatePk, Translator};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+use utoipa::ToSchema;
+use crate::nostr::NostrPublicKey;
+use crate::taproot::{self, TaprootError, TaprootSummary, TaprootTree, NUMS_KEY};
+
+// Policies with more ways to spend than this are not broken down into paths
+const MAX_PATHS: usize = 256;
+// Relative timelocks with this bit set count units of 512 seconds (BIP-68)
+const SEQUENCE_TYPE_FLAG: u32 = 1 << 22;
+
+// Parsing, typing, malleability, resource limits, checksums and policy compilation come from
+// rust-miniscript; this module resolves keys and describes the result in plain language
+#[derive(Debug, Error, PartialEq)]
+pub enum MiniscriptError {
+    #[error("Syntax error: {0}")]
+    Syntax(String),
+    #[error("Invalid key {0}")]
+    InvalidKey(String),
+    #[error("Invalid hash {0}: expected {1} hex characters")]
+    InvalidHash(String, usize),
+    #[error("Bad descriptor checksum: {0}")]
+    BadChecksum(String),
+    #[error("Unsupported descriptor {0}; expected wsh(...) or tr(...)")]
+    UnsupportedDescriptor(String),
+    #[error("tr() needs an internal key; use {nums} to allow only the script paths", nums = NUMS_KEY)]
+    MissingInternalKey,
+    #[error(transparent)]
+    InvalidTapTree(#[from] TaprootError),
+    #[error("More than {0} spending paths to describe")]
+    TooManyPaths(usize),
+    #[error(transparent)]
+    Miniscript(#[from] miniscript::Error),
+}
+
+// Accepts a compressed key as 66 hex characters, or an x-only key as an npub or 64 hex characters.
+// An x-only key stands for the point with an even y coordinate.
+fn parse_key(s: &str) -> Result<PublicKey, MiniscriptError> {
+    let key = match s.len() {
+        66 => PublicKey::from_str(s).map_err(|e| e.to_string()),
+        _ => NostrPublicKey::from_str(s).map(|key| key.segwit_v0_key().inner).map_err(|e| e.to_string()),
+    };
+    key.map_err(|e| MiniscriptError::InvalidKey(format!("{}: {}", s, e)))
+}
+
+fn parse_hash<H: FromStr>(s: &str, hex_len: usize) -> Result<H, MiniscriptError> {
+    match s.len() == hex_len {
+        true => H::from_str(s).map_err(|_| MiniscriptError::InvalidHash(s.to_string(), hex_len)),
+        false => Err(MiniscriptError::InvalidHash(s.to_string(), hex_len)),
+    }
+}
+
+// The key types scripts are resolved to: compressed keys for wsh(), x-only keys for tr()
+trait ResolvedKey: MiniscriptKey<
+    Sha256 = sha256::Hash,
+    Hash256 = hash256::Hash,
+    Ripemd160 = ripemd160::Hash,
+    Hash160 = hash160::Hash,
+> {
+    fn from_key(key: PublicKey) -> Self;
+}
+
+impl ResolvedKey for bitcoin::PublicKey {
+    fn from_key(key: PublicKey) -> Self {
+        bitcoin::PublicKey::new(key)
+    }
+}
+
+impl ResolvedKey for XOnlyPublicKey {
+    fn from_key(key: PublicKey) -> Self {
+        key.x_only_public_key().0
+    }
+}
+
+// Expressions are parsed with string keys and hashes, then resolved with this
+struct Resolve;
+
+impl<Q: ResolvedKey> Translator<String, Q, MiniscriptError> for Resolve {
+    fn pk(&mut self, pk: &String) -> Result<Q, MiniscriptError> {
+        parse_key(pk).map(Q::from_key)
+    }
+
+    fn sha256(&mut self, hash: &String) -> Result<sha256::Hash, MiniscriptError> {
+        parse_hash(hash, 64)
+    }
+
+    fn hash256(&mut self, hash: &String) -> Result<hash256::Hash, MiniscriptError> {
+        parse_hash(hash, 64)
+    }
+
+    fn ripemd160(&mut self, hash: &String) -> Result<ripemd160::Hash, MiniscriptError> {
+        parse_hash(hash, 40)
+    }
+
+    fn hash160(&mut self, hash: &String) -> Result<hash160::Hash, MiniscriptError> {
+        parse_hash(hash, 40)
+    }
+}
+
+fn strip_whitespace(s: &str) -> String {
+    s.chars().filter(|c| !c.is_whitespace()).collect()
+}
+
+// Miniscript for one context, with keys and hashes resolved; top-level checks only, so that
+// the analysis can report what is wrong with an unsafe script rather than refusing it
+fn parse_miniscript<Q: ResolvedKey, Ctx: ScriptContext>(s: &str) -> Result<Miniscript<Q, Ctx>, MiniscriptError> {
+    let ms = Miniscript::<String, Ctx>::from_str_insane(&strip_whitespace(s))?;
+    ms.translate_pk(&mut Resolve)
+}
+
+// Compiles policy such as and(pk(A),or(99@pk(B),after(4326153))); or() weights steer the compiler
+fn compile_policy<Q: ResolvedKey, Ctx: ScriptContext>(s: &str) -> Result<Miniscript<Q, Ctx>, MiniscriptError> {
+    let policy = Concrete::<String>::from_str(&strip_whitespace(s))?;
+    let policy: Concrete<Q> = policy.translate_pk(&mut Resolve)?;
+    policy.compile::<Ctx>().map_err(|e| miniscript::Error::from(e).into())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HashKind {
+    Sha256,
+    Hash256,
+    Ripemd160,
+    Hash160,
+}
+
+impl fmt::Display for HashKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashKind::Sha256 => write!(f, "SHA-256"),
+            HashKind::Hash256 => write!(f, "double SHA-256"),
+            HashKind::Ripemd160 => write!(f, "RIPEMD-160"),
+            HashKind::Hash160 => write!(f, "HASH160"),
+        }
+    }
+}
+
+// One condition a spender must meet
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Condition {
+    Signature { key: String },
+    Signatures { threshold: usize, keys: Vec<String> },
+    Preimage { hash_type: HashKind, hash: String },
+    // Absolute (CLTV): a block height, or a unix time from 500000000
+    After { value: u32 },
+    // Relative (CSV) per BIP-68: blocks, or units of 512 seconds when bit 22 is set
+    Older { value: u32 },
+}
+
+impl Condition {
+    fn is_signature(&self) -> bool {
+        matches!(self, Condition::Signature { .. } | Condition::Signatures { .. })
+    }
+
+    // Whether a timelock counts time rather than blocks; None for other conditions
+    fn counts_time(&self) -> Option<bool> {
+        match *self {
+            Condition::After { value } => Some(value >= LOCK_TIME_THRESHOLD),
+            Condition::Older { value } => Some(value & SEQUENCE_TYPE_FLAG != 0),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Signature { key } => write!(f, "a signature from {}", key),
+            Condition::Signatures { threshold, keys } => {
+                write!(f, "signatures from {} of {} keys ({})", threshold, keys.len(), keys.join(", "))
+            }
+            Condition::Preimage { hash_type, hash } => write!(f, "the {} preimage of {}", hash_type, hash),
+            Condition::After { value } if *value < LOCK_TIME_THRESHOLD => write!(f, "once block {} is reached", value),
+            Condition::After { value } => match Utc.timestamp_opt((*value).into(), 0).single() {
+                Some(at) => write!(f, "once {} has passed", at.to_rfc3339()),
+                None => write!(f, "once unix time {} has passed", value),
+            },
+            Condition::Older { value } if value & SEQUENCE_TYPE_FLAG != 0 => {
+                write!(f, "{} seconds after the output confirms", (value & 0xffff) * 512)
+            }
+            Condition::Older { value } => write!(f, "{} blocks after the output confirms", value & 0xffff),
+        }
+    }
+}
+
+// One way to satisfy a script, described in plain language
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct PolicyPath {
+    // Index into the compiled leaves; None for a taproot key path spend
+    pub leaf: Option<usize>,
+    pub conditions: Vec<Condition>,
+    pub description: String,
+}
+
+impl PolicyPath {
+    fn new(leaf: Option<usize>, mut conditions: Vec<Condition>) -> Self {
+        // Signatures first, then preimages, then timelocks
+        conditions.sort_by_key(|condition| match condition {
+            Condition::Signature { .. } | Condition::Signatures { .. } => 0,
+            Condition::Preimage { .. } => 1,
+            _ => 2,
+        });
+        let described: Vec<String> = conditions.iter().map(Condition::to_string).collect();
+        let description = match described.split_last() {
+            None => "Anyone can spend".to_string(),
+            Some((last, [])) => format!("Spendable with {}", last),
+            Some((last, rest)) => format!("Spendable with {} and {}", rest.join(", "), last),
+        };
+        Self { leaf, conditions, description }
+    }
+
+    fn mixes_timelocks(&self) -> bool {
+        let kinds = |absolute: bool| -> Vec<bool> {
+            self.conditions.iter()
+                .filter(|condition| matches!(condition, Condition::After { .. }) == absolute)
+                .filter_map(Condition::counts_time)
+                .collect()
+        };
+        [true, false].into_iter().any(|absolute| {
+            let kinds = kinds(absolute);
+            kinds.contains(&true) && kinds.contains(&false)
+        })
+    }
+}
+
+// Every pairing of a path from `a` with a path from `b`
+fn product(a: Vec<Vec<Condition>>, b: &[Vec<Condition>]) -> Result<Vec<Vec<Condition>>, MiniscriptError> {
+    if a.len() * b.len() > MAX_PATHS {
+        return Err(MiniscriptError::TooManyPaths(MAX_PATHS));
+    }
+    Ok(a.iter().flat_map(|x| b.iter().map(move |y| [x.as_slice(), y].concat())).collect())
+}
+
+fn union(mut a: Vec<Vec<Condition>>, b: Vec<Vec<Condition>>) -> Result<Vec<Vec<Condition>>, MiniscriptError> {
+    a.extend(b);
+    match a.len() > MAX_PATHS {
+        true => Err(MiniscriptError::TooManyPaths(MAX_PATHS)),
+        false => Ok(a),
+    }
+}
+
+
+fn preimage<H: fmt::Display>(hash_type: HashKind, hash: &H) -> Vec<Vec<Condition>> {
+    vec![vec![Condition::Preimage { hash_type, hash: hash.to_string() }]]
+}
+
+// Each way to satisfy a lifted policy, as the conditions it needs
+fn satisfactions<Pk: MiniscriptKey>(policy: &Semantic<Pk>) -> Result<Vec<Vec<Condition>>, MiniscriptError> {
+    Ok(match policy {
+        Semantic::Unsatisfiable => vec![],
+        Semantic::Trivial => vec![vec![]],
+        Semantic::Key(key) => vec![vec![Condition::Signature { key: key.to_string() }]],
+        Semantic::After(lock_time) => vec![vec![Condition::After { value: lock_time.0 }]],
+        Semantic::Older(sequence) => vec![vec![Condition::Older { value: sequence.0 }]],
+        Semantic::Sha256(hash) => preimage(HashKind::Sha256, hash),
+        Semantic::Hash256(hash) => preimage(HashKind::Hash256, hash),
+        Semantic::Ripemd160(hash) => preimage(HashKind::Ripemd160, hash),
+        Semantic::Hash160(hash) => preimage(HashKind::Hash160, hash),
+        // k of n keys, as from multi(), reads better as one condition than as a path per combination
+        Semantic::Threshold(k, subs) if *k > 1 && *k < subs.len() && subs.iter().all(|sub| matches!(sub, Semantic::Key(_))) => {
+            let keys = subs.iter().map(|sub| match sub {
+                Semantic::Key(key) => key.to_string(),
+                _ => unreachable!("every sub-policy is a key"),
+            });
+            vec![vec![Condition::Signatures { threshold: *k, keys: keys.collect() }]]
+        }
+        Semantic::Threshold(k, subs) => {
+            let subs = subs.iter().map(satisfactions).collect::<Result<Vec<_>, _>>()?;
+            // Grow combinations one sub-policy at a time, as (next index, chosen, paths)
+            let mut partial = vec![(0, 0, vec![vec![]])];
+            let mut paths = Vec::new();
+            while let Some((next, chosen, so_far)) = partial.pop() {
+                if chosen == *k {
+                    paths = union(paths, so_far)?;
+                    continue;
+                }
+                for (i, sub) in subs.iter().enumerate().skip(next) {
+                    partial.push((i + 1, chosen + 1, product(so_far.clone(), sub)?));
+                }
+                if partial.len() > MAX_PATHS {
+                    return Err(MiniscriptError::TooManyPaths(MAX_PATHS));
+                }
+            }
+            paths
+        }
+    })
+}
+
+// The Miniscript type of a whole script, e.g. "Bondu"
+fn type_string<Pk: MiniscriptKey, Ctx: ScriptContext>(ms: &Miniscript<Pk, Ctx>) -> String {
+    use miniscript::miniscript::types::{Base, Input};
+
+    let corr = &ms.ty.corr;
+    let base = match corr.base {
+        Base::B => "B",
+        Base::K => "K",
+        Base::V => "V",
+        Base::W => "W",
+    };
+    let input = match corr.input {
+        Input::Zero => "z",
+        Input::One => "o",
+        Input::OneNonZero => "on",
+        Input::AnyNonZero => "n",
+        Input::Any => "",
+    };
+    let dissatisfiable = if corr.dissatisfiable { "d" } else { "" };
+    let unit = if corr.unit { "u" } else { "" };
+    format!("{}{}{}{}", base, input, dissatisfiable, unit)
+}
+
+// Satisfiability, malleability and resource limits, with the spending paths of `leaf`
+fn analyze<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    ms: &Miniscript<Pk, Ctx>,
+    leaf: Option<usize>,
+) -> Result<(Analysis, Vec<PolicyPath>), MiniscriptError> {
+    // Lifting the node rather than the script skips the timelock check, so mixed paths are still listed
+    let paths: Vec<PolicyPath> = satisfactions(&ms.as_inner().lift()?)?
+        .into_iter()
+        .map(|conditions| PolicyPath::new(leaf, conditions))
+        .collect();
+    let tapscript = matches!(Ctx::sig_type(), SigType::Schnorr);
+    let context = if tapscript { "tapscript" } else { "segwit v0 scripts" };
+
+    let timelock_mix = ms.has_mixed_timelocks();
+    let satisfiable = paths.iter().any(|path| !path.mixes_timelocks());
+    let within_limits = ms.within_resource_limits();
+
+    let mut issues = Vec::new();
+    if !satisfiable {
+        issues.push("No spending path can be satisfied, so coins sent here are lost".to_string());
+    }
+    if timelock_mix {
+        issues.push("A spending path combines a block-height timelock with a time-based one and can never be satisfied".to_string());
+    }
+    if !ms.is_non_malleable() {
+        issues.push("Some satisfactions are malleable: a third party can change the witness of a spend".to_string());
+    }
+    for (i, path) in paths.iter().enumerate() {
+        if !path.conditions.iter().any(Condition::is_signature) {
+            issues.push(format!(
+                "Path {} needs no signature: anyone who learns its conditions can spend, including whoever relays the transaction",
+                i + 1,
+            ));
+        }
+    }
+    if ms.has_repeated_keys() {
+        issues.push("The same key appears more than once".to_string());
+    }
+    if !within_limits {
+        issues.push(format!("The script exceeds the {} limits on size, opcodes or witness elements", context));
+    }
+
+    let analysis = Analysis {
+        miniscript_type: type_string(ms),
+        satisfiable,
+        non_malleable: ms.is_non_malleable(),
+        requires_signature: ms.requires_sig(),
+        timelock_mix,
+        script_size: ms.script_size(),
+        op_count: if tapscript { None } else { ms.ext.ops.op_count() },
+        max_witness_elements: ms.ext.stack_elem_count_sat,
+        within_limits,
+        sane: ms.sanity_check().is_ok() && issues.is_empty(),
+        issues,
+    };
+    Ok((analysis, paths))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct Analysis {
+    // Miniscript type of the whole script, e.g. "Bondu"
+    pub miniscript_type: String,
+    // At least one spending path can be met
+    pub satisfiable: bool,
+    // No third party can alter a valid witness
+    pub non_malleable: bool,
+    // Every spending path needs a signature
+    pub requires_signature: bool,
+    // Some path combines height and time timelocks of the same kind
+    pub timelock_mix: bool,
+    pub script_size: usize,
+    // Segwit v0 only; at most 201
+    pub op_count: Option<usize>,
+    pub max_witness_elements: Option<usize>,
+    pub within_limits: bool,
+    // Meets every check above and has no issues
+    pub sane: bool,
+    pub issues: Vec<String>,
+}
+
+// An output descriptor: wsh(MINISCRIPT), or tr(KEY) with an optional tree of Miniscript leaves
+#[derive(Debug, Clone, PartialEq)]
+pub enum Descriptor {
+    Wsh(Wsh<bitcoin::PublicKey>),
+    Tr(Tr<XOnlyPublicKey>),
+}
+
+impl Descriptor {
+    pub fn wsh(ms: Miniscript<bitcoin::PublicKey, Segwitv0>) -> Result<Self, MiniscriptError> {
+        Ok(Descriptor::Wsh(Wsh::new(ms)?))
+    }
+
+    // Wraps Miniscript in tr() with a single leaf; without an internal key only the leaf can spend
+    pub fn tr(internal_key: Option<XOnlyPublicKey>, ms: Miniscript<XOnlyPublicKey, Tap>) -> Result<Self, MiniscriptError> {
+        let internal_key = internal_key.unwrap_or_else(taproot::nums_key);
+        let leaf = miniscript::descriptor::TapTree::Leaf(ms.into());
+        Ok(Descriptor::Tr(Tr::new(internal_key, Some(leaf))?))
+    }
+
+    fn wsh_miniscript(wsh: &Wsh<bitcoin::PublicKey>) -> Result<&Miniscript<bitcoin::PublicKey, Segwitv0>, MiniscriptError> {
+        match wsh.as_inner() {
+            WshInner::Ms(ms) => Ok(ms),
+            WshInner::SortedMulti(_) => Err(MiniscriptError::UnsupportedDescriptor("wsh(sortedmulti)".into())),
+        }
+    }
+
+    fn taproot_tree(tr: &Tr<XOnlyPublicKey>) -> Result<TaprootTree, MiniscriptError> {
+        let leaves = tr.iter_scripts().map(|(depth, ms)| (depth, ms.encode())).collect();
+        Ok(TaprootTree::with_depths(*tr.internal_key(), leaves)?)
+    }
+
+    // Compiles the descriptor to its address and scripts, describing every way to spend it
+    pub fn compile(&self, network: Network) -> Result<CompiledDescriptor, MiniscriptError> {
+        let address = match self {
+            Descriptor::Wsh(wsh) => wsh.address(network),
+            Descriptor::Tr(tr) => tr.address(network),
+        };
+        let mut compiled = CompiledDescriptor {
+            descriptor: self.to_string(),
+            address: address.to_string(),
+            script_pubkey: address.script_pubkey().to_hex(),
+            taproot: None,
+            leaves: Vec::new(),
+            spending_paths: Vec::new(),
+            sane: true,
+        };
+
+        let leaves = match self {
+            Descriptor::Wsh(wsh) => {
+                let ms = Descriptor::wsh_miniscript(wsh)?;
+                vec![(ms.to_string(), ms.encode(), analyze(ms, Some(0))?)]
+            }
+            Descriptor::Tr(tr) => {
+                let tree = Descriptor::taproot_tree(tr)?;
+                if tree.key_path_spendable() {
+                    let key = tr.internal_key().to_string();
+                    compiled.spending_paths.push(PolicyPath::new(None, vec![Condition::Signature { key }]));
+                }
+                compiled.taproot = Some(tree.summary());
+                tr.iter_scripts()
+                    .enumerate()
+                    .map(|(i, (_, ms))| Ok((ms.to_string(), ms.encode(), analyze(ms, Some(i))?)))
+                    .collect::<Result<Vec<_>, MiniscriptError>>()?
+            }
+        };
+        for (miniscript, script, (analysis, paths)) in leaves {
+            compiled.sane &= analysis.sane;
+            compiled.spending_paths.extend(paths);
+            compiled.leaves.push(CompiledLeaf { miniscript, script: script.to_hex(), asm: script.asm(), analysis });
+        }
+        Ok(compiled)
+    }
+}
+
+// Accepts a trailing #checksum, which must then match
+impl FromStr for Descriptor {
+    type Err = MiniscriptError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = strip_whitespace(s);
+        if s.starts_with("tr(,") {
+            return Err(MiniscriptError::MissingInternalKey);
+        }
+        let parsed = miniscript::Descriptor::<String>::from_str(&s).map_err(|e| match e {
+            // rust-miniscript reports a wrong checksum as a generic descriptor error
+            miniscript::Error::BadDescriptor(message) if message.starts_with("Invalid checksum") => {
+                MiniscriptError::BadChecksum(message)
+            }
+            e => e.into(),
+        })?;
+        match parsed {
+            miniscript::Descriptor::Wsh(wsh) => {
+                let wsh = wsh.translate_pk(&mut Resolve)?;
+                Descriptor::wsh_miniscript(&wsh)?;
+                Ok(Descriptor::Wsh(wsh))
+            }
+            miniscript::Descriptor::Tr(tr) => Ok(Descriptor::Tr(tr.translate_pk(&mut Resolve)?)),
+            _ => Err(MiniscriptError::UnsupportedDescriptor(s.split('(').next().unwrap_or_default().to_string())),
+        }
+    }
+}
+
+// The canonical form, with its checksum
+impl fmt::Display for Descriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Descriptor::Wsh(wsh) => wsh.fmt(f),
+            Descriptor::Tr(tr) => tr.fmt(f),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CompiledLeaf {
+    pub miniscript: String,
+    // Hex; the witness script for wsh, the leaf script for tr
+    pub script: String,
+    pub asm: String,
+    pub analysis: Analysis,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CompiledDescriptor {
+    // Canonical form with its checksum
+    pub descriptor: String,
+    pub address: String,
+    pub script_pubkey: String,
+    // tr only: the keys, merkle root and each leaf's control block, in the order of `leaves`
+    pub taproot: Option<TaprootSummary>,
+    pub leaves: Vec<CompiledLeaf>,
+    pub spending_paths: Vec<PolicyPath>,
+    // Every leaf passed every check
+    pub sane: bool,
+}
+
+// What to compile; exactly one of the fields is given
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CompileRequest {
+    // Policy language, e.g. and(pk(A),or(9@pk(B),after(4326153))); compiled to wsh(). An or()
+    // branch may carry a weight N@ saying how likely it is to be used, which the compiler favours
+    pub policy: Option<String>,
+    // Miniscript, e.g. and_v(v:pk(A),after(4326153)); wrapped in wsh()
+    pub miniscript: Option<String>,
+    // A wsh() or tr() output descriptor
+    pub descriptor: Option<String>,
+    // Compile a policy or Miniscript to a tr() leaf instead of wsh()
+    #[serde(default)]
+    pub taproot: bool,
+    // Key path for a taproot policy, such as an aggregate of the cooperating keys; defaults to
+    // the provably unspendable NUMS key
+    pub internal_key: Option<String>,
+}
+
+impl CompileRequest {
+    pub fn descriptor(&self) -> Result<Descriptor, MiniscriptError> {
+        let internal_key = || -> Result<Option<XOnlyPublicKey>, MiniscriptError> {
+            Ok(self.internal_key.as_deref().map(parse_key).transpose()?.map(|key| key.x_only_public_key().0))
+        };
+        match (&self.policy, &self.miniscript, &self.descriptor, self.taproot) {
+            (Some(policy), None, None, false) => Descriptor::wsh(compile_policy(policy)?),
+            (Some(policy), None, None, true) => Descriptor::tr(internal_key()?, compile_policy(policy)?),
+            (None, Some(ms), None, false) => Descriptor::wsh(parse_miniscript(ms)?),
+            (None, Some(ms), None, true) => Descriptor::tr(internal_key()?, parse_miniscript(ms)?),
+            (None, None, Some(descriptor), _) => Descriptor::from_str(descriptor),
+            _ => Err(MiniscriptError::Syntax("give exactly one of policy, miniscript or descriptor".into())),
+        }
+    }
+
+    // The request field errors are reported against
+    pub fn field(&self) -> &'static str {
+        match (&self.miniscript, &self.descriptor) {
+            (Some(_), None) if self.policy.is_none() => "miniscript",
+            (None, Some(_)) if self.policy.is_none() => "descriptor",
+            _ => "policy",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ApiError;
+
+    // The x-only keys of the secret keys 1, 2 and 3
+    const KEY_1: &str = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const KEY_2: &str = "c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+    const KEY_3: &str = "f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9";
+    // From the plantman script
+    const PREIMAGE_HASH: &str = "79c0198ac27c52e56323db0f5ab0ff7fa090257263049748793babd18c07e9c7";
+
+    fn request(policy: Option<String>, miniscript: Option<String>, taproot: bool) -> CompileRequest {
+        CompileRequest { policy, miniscript, descriptor: None, taproot, internal_key: None }
+    }
+
+    fn compile(request: CompileRequest) -> Result<CompiledDescriptor, MiniscriptError> {
+        request.descriptor()?.compile(Network::Bitcoin)
+    }
+
+    fn code(err: MiniscriptError) -> &'static str {
+        ApiError::from(err).body.code
+    }
+
+    #[test]
+    fn test_plantman_script_compiles_to_known_vector() {
+        // Script assembled by hand; address and checksum computed with the BIP-173 and BIP-380 reference code
+        let descriptor = Descriptor::from_str(&format!("wsh(and_v(\n    v:after(4326153),\n    sha256({})\n))", PREIMAGE_HASH)).unwrap();
+        let compiled = descriptor.compile(Network::Bitcoin).unwrap();
+        assert_eq!(compiled.descriptor, format!("wsh(and_v(v:after(4326153),sha256({})))#8eeqxfst", PREIMAGE_HASH));
+        assert_eq!(compiled.address, "bc1q5scw556sxhqm0x8h56ejv9z9v0a9kwl5xjgu6kahtlwmafz8p9uq2pfr68");
+        assert_eq!(compiled.leaves[0].script, format!("03090342b16982012088a820{}87", PREIMAGE_HASH));
+
+        let analysis = &compiled.leaves[0].analysis;
+        assert!(analysis.satisfiable && analysis.non_malleable && analysis.within_limits);
+        assert!(!analysis.requires_signature && !analysis.sane && !compiled.sane);
+        assert_eq!(analysis.miniscript_type, "Bonu");
+        assert_eq!(analysis.max_witness_elements, Some(1));
+        assert_eq!(compiled.spending_paths[0].description, format!(
+            "Spendable with the SHA-256 preimage of {} and once block 4326153 is reached",
+            PREIMAGE_HASH,
+        ));
+
+        // As written in the file, without the v: wrapper or the internal key
+        let err = compile(request(None, Some(format!("and_v(after(4326153),sha256({}))", PREIMAGE_HASH)), false)).unwrap_err();
+        assert_eq!(code(err), "type_error");
+        assert_eq!(
+            Descriptor::from_str(&format!("tr(,and_v(v:after(4326153),sha256({})))", PREIMAGE_HASH)).unwrap_err(),
+            MiniscriptError::MissingInternalKey,
+        );
+        assert!(matches!(
+            Descriptor::from_str(&format!("wsh(and_v(v:after(4326153),sha256({})))#8eeqxfsq", PREIMAGE_HASH)),
+            Err(MiniscriptError::BadChecksum(_)),
+        ));
+    }
+
+    #[test]
+    fn test_taproot_descriptors_match_known_vectors() {
+        // Addresses computed with an independent BIP-341 implementation
+        let vectors = [
+            (format!("tr({},pk({}))#v7hhggq3", KEY_1, KEY_2), "bc1pg44et8f66qnjn5fd0hu6dnnx7tczqslmt3dkzpccjlzeg99psshqn82yk5"),
+            (
+                format!("tr({},{{pk({}),and_v(v:pk({}),after(4326153))}})#f3d7p7k6", KEY_1, KEY_2, KEY_3),
+                "bc1p2kxsgn230g2e06qtmtfc5704zqj6xwwews0959wkh4vwqpxvdkrqyh0dat",
+            ),
+        ];
+        for (descriptor, address) in &vectors {
+            let compiled = Descriptor::from_str(descriptor).unwrap().compile(Network::Bitcoin).unwrap();
+            assert_eq!(&compiled.descriptor, descriptor);
+            assert_eq!(&compiled.address, address);
+            assert!(compiled.sane);
+        }
+
+        let compiled = Descriptor::from_str(&vectors[1].0).unwrap().compile(Network::Bitcoin).unwrap();
+        let leaves: Vec<Option<usize>> = compiled.spending_paths.iter().map(|path| path.leaf).collect();
+        assert_eq!(leaves, [None, Some(0), Some(1)]);
+        assert_eq!(compiled.spending_paths[0].description, format!("Spendable with a signature from {}", KEY_1));
+        assert_eq!(compiled.leaves[1].script, format!("20{}ad03090342b1", KEY_3));
+        assert_eq!(compiled.taproot.unwrap().leaves[1].script, compiled.leaves[1].script);
+
+        let err = Descriptor::from_str(&format!("tr({},multi(1,{},{}))", KEY_1, KEY_2, KEY_3)).unwrap_err();
+        assert_eq!(code(err), "wrong_context");
+        let err = Descriptor::from_str(&format!("pkh({})", KEY_1)).unwrap_err();
+        assert_eq!(err, MiniscriptError::UnsupportedDescriptor("pkh".into()));
+    }
+
+    #[test]
+    fn test_taproot_policies_default_to_the_nums_key() {
+        let compiled = compile(request(Some(format!("and(pk({}),after(4326153))", KEY_3)), None, true)).unwrap();
+        // Address computed with an independent BIP-341 implementation
+        assert_eq!(compiled.address, "bc1pzuu0yhu8zgx55t4cnj9cksgwqmhecnf8mf84s6dzu82kn3wm592s63j79e");
+        assert_eq!(compiled.descriptor, format!("tr({},and_v(v:pk({}),after(4326153)))#yehat520", NUMS_KEY, KEY_3));
+
+        // Nobody can use the NUMS key path, so the leaf is the only way to spend
+        let taproot = compiled.taproot.unwrap();
+        assert!(!taproot.key_path_spendable);
+        assert_eq!(compiled.spending_paths.len(), 1);
+        assert_eq!(taproot.leaves[0].script, compiled.leaves[0].script);
+        assert_eq!(taproot.leaves[0].control_block, format!("c0{}", NUMS_KEY));
+    }
+
+    #[test]
+    fn test_keys_resolve_from_hex_x_only_and_npub() {
+        let npub = NostrPublicKey::from_hex(KEY_1).unwrap().to_npub();
+        let compressed = format!("02{}", KEY_1);
+        for key in [KEY_1, npub.as_str(), compressed.as_str()] {
+            let compiled = compile(request(None, Some(format!("pk({})", key)), false)).unwrap();
+            assert_eq!(compiled.leaves[0].miniscript, format!("pk({})", compressed));
+            let compiled = compile(request(None, Some(format!("pk({})", key)), true)).unwrap();
+            assert_eq!(compiled.leaves[0].miniscript, format!("pk({})", KEY_1));
+        }
+
+        assert_eq!(code(compile(request(None, Some("pk(02abcd)".into()), false)).unwrap_err()), "invalid_key");
+        let err = compile(request(None, Some(format!("and_v(v:pk({}),sha256(abcd))", KEY_1)), false)).unwrap_err();
+        assert_eq!(err, MiniscriptError::InvalidHash("abcd".into(), 64));
+        let both = CompileRequest { descriptor: Some("wsh(1)".into()), ..request(Some("pk(A)".into()), None, false) };
+        assert_eq!(code(both.descriptor().unwrap_err()), "syntax_error");
+    }
+
+    #[test]
+    fn test_policies_compile_and_analysis_flags_problems() {
+        let compiled = compile(request(Some(format!("or(pk({}),and(pk({}),older(144)))", KEY_1, KEY_2)), None, false)).unwrap();
+        let leaf = &compiled.leaves[0];
+        assert!(leaf.analysis.sane, "{:?}", leaf.analysis.issues);
+        assert!(leaf.analysis.op_count.is_some());
+        let with_older = compiled.spending_paths.iter().find(|path| path.conditions.len() == 2).unwrap();
+        assert_eq!(with_older.description, format!(
+            "Spendable with a signature from 02{} and 144 blocks after the output confirms",
+            KEY_2,
+        ));
+
+        let compiled = compile(request(Some(format!("thresh(2,pk({}),pk({}),after(4326153))", KEY_1, KEY_2)), None, false)).unwrap();
+        assert!(compiled.sane, "{:?}", compiled.leaves[0].analysis.issues);
+        assert_eq!(compiled.spending_paths.len(), 3);
+
+        // 2 of 3 keys is one condition rather than three paths
+        let ms = format!("multi(2,02{},02{},02{})", KEY_1, KEY_2, KEY_3);
+        let compiled = compile(request(None, Some(ms), false)).unwrap();
+        assert_eq!(compiled.spending_paths.len(), 1);
+        assert!(matches!(compiled.spending_paths[0].conditions[0], Condition::Signatures { threshold: 2, .. }));
+
+        // A block height and a unix time can never both be met
+        let ms = format!("and_v(v:pk({}),and_v(v:after(4326153),after(1700000000)))", KEY_1);
+        let analysis = &compile(request(None, Some(ms), false)).unwrap().leaves[0].analysis;
+        assert!(analysis.timelock_mix && !analysis.satisfiable && !analysis.sane);
+
+        let ms = format!("or_b(pk({}),s:pk({}))", KEY_1, KEY_1);
+        let analysis = &compile(request(None, Some(ms), false)).unwrap().leaves[0].analysis;
+        assert!(analysis.issues.iter().any(|issue| issue.contains("same key")));
+
+        assert_eq!(code(compile(request(Some("older(0)".into()), None, false)).unwrap_err()), "invalid_timelock");
+        assert_eq!(code(compile(request(Some(format!("thresh(3,pk({}),pk({}))", KEY_1, KEY_2)), None, false)).unwrap_err()), "invalid_threshold");
+        // Without a signature on every path the compiler refuses rather than produce an unsafe script
+        let err = compile(request(Some(format!("sha256({})", PREIMAGE_HASH)), None, false)).unwrap_err();
+        assert_eq!(code(err), "uncompilable_policy");
+    }
+
+    #[test]
+    fn test_or_weights_steer_the_compiler() {
+        let likely_first = compile(request(Some(format!("or(99@pk({}),1@pk({}))", KEY_1, KEY_2)), None, false)).unwrap();
+        let likely_second = compile(request(Some(format!("or(1@pk({}),99@pk({}))", KEY_1, KEY_2)), None, false)).unwrap();
+        assert_ne!(likely_first.leaves[0].miniscript, likely_second.leaves[0].miniscript);
+        // The likelier key is checked first, where its satisfaction is cheapest
+        let position = |compiled: &CompiledDescriptor, key: &str| compiled.leaves[0].miniscript.find(key).unwrap();
+        assert!(position(&likely_first, KEY_1) < position(&likely_first, KEY_2));
+        assert!(position(&likely_second, KEY_2) < position(&likely_second, KEY_1));
+    }
+}
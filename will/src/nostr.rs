@@ -0,0 +1,320 @@
+use bech32::{FromBase32, ToBase32, Variant};
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{schnorr, KeyPair, Message, Parity, Secp256k1, SecretKey, XOnlyPublicKey};
+use bitcoin::PublicKey;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum NostrError {
+    #[error("Invalid public key: {0}")]
+    InvalidPublicKey(String),
+    #[error("Invalid secret key: {0}")]
+    InvalidSecretKey(String),
+    #[error("Expected an {expected} key, found prefix {found:?}")]
+    WrongPrefix { expected: &'static str, found: String },
+    #[error("Bech32 checksum does not match; the key is mistyped or truncated")]
+    BadChecksum,
+    #[error("Invalid bech32: {0}")]
+    InvalidBech32(String),
+    #[error("Expected {expected} {unit}, found {found}")]
+    WrongLength { expected: usize, found: usize, unit: &'static str },
+    #[error("Invalid hex: {0}")]
+    InvalidHex(String),
+    #[error("Event id does not match its contents")]
+    IdMismatch,
+    #[error("Invalid signature: {0}")]
+    InvalidSignature(String),
+}
+
+const NPUB: &str = "npub";
+const NSEC: &str = "nsec";
+
+// The 32 bytes behind a NIP-19 string with the `hrp` prefix, or behind 64 hex characters
+fn decode_key(s: &str, hrp: &'static str) -> Result<[u8; 32], NostrError> {
+    let s = s.trim();
+    let bytes = if s.chars().all(|c| c.is_ascii_hexdigit()) {
+        if s.len() != 64 {
+            return Err(NostrError::WrongLength { expected: 64, found: s.len(), unit: "hex characters" });
+        }
+        hex::decode(s).map_err(|e| NostrError::InvalidHex(e.to_string()))?
+    } else {
+        let (found, data, variant) = bech32::decode(s).map_err(|e| match e {
+            bech32::Error::InvalidChecksum => NostrError::BadChecksum,
+            e => NostrError::InvalidBech32(e.to_string()),
+        })?;
+        if found != hrp {
+            return Err(NostrError::WrongPrefix { expected: hrp, found });
+        }
+        if variant != Variant::Bech32 {
+            return Err(NostrError::InvalidBech32("NIP-19 keys use bech32, not bech32m".into()));
+        }
+        Vec::<u8>::from_base32(&data).map_err(|e| NostrError::InvalidBech32(e.to_string()))?
+    };
+
+    bytes.as_slice().try_into()
+        .map_err(|_| NostrError::WrongLength { expected: 32, found: bytes.len(), unit: "bytes" })
+}
+
+fn encode_key(hrp: &str, bytes: &[u8]) -> String {
+    bech32::encode(hrp, bytes.to_base32(), Variant::Bech32).expect("npub and nsec are valid prefixes")
+}
+
+// A Nostr identity: the x-only secp256k1 key behind an npub
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NostrPublicKey(XOnlyPublicKey);
+
+impl NostrPublicKey {
+    // Only the hex form, as NIP-01 requires inside events
+    pub fn from_hex(s: &str) -> Result<Self, NostrError> {
+        if s.len() != 64 {
+            return Err(NostrError::WrongLength { expected: 64, found: s.len(), unit: "hex characters" });
+        }
+        XOnlyPublicKey::from_str(s)
+            .map(Self)
+            .map_err(|e| NostrError::InvalidPublicKey(e.to_string()))
+    }
+
+    pub fn x_only(&self) -> XOnlyPublicKey {
+        self.0
+    }
+
+    pub fn to_hex(&self) -> String {
+        self.0.to_string()
+    }
+
+    pub fn to_npub(&self) -> String {
+        encode_key(NPUB, &self.0.serialize())
+    }
+
+    // For segwit v0 scripts, which take full keys. As in BIP-340 an x-only key stands for
+    // the point with an even y coordinate, so signers whose point is odd must negate their
+    // secret; see NostrSecretKey::segwit_v0_secret.
+    pub fn segwit_v0_key(&self) -> PublicKey {
+        PublicKey::new(self.0.public_key(Parity::Even))
+    }
+
+    // Taproot scripts and output keys take x-only keys as they are
+    pub fn taproot_key(&self) -> XOnlyPublicKey {
+        self.0
+    }
+}
+
+impl From<XOnlyPublicKey> for NostrPublicKey {
+    fn from(key: XOnlyPublicKey) -> Self {
+        Self(key)
+    }
+}
+
+// Accepts an npub or 64 hex characters
+impl FromStr for NostrPublicKey {
+    type Err = NostrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        XOnlyPublicKey::from_slice(&decode_key(s, NPUB)?)
+            .map(Self)
+            .map_err(|e| NostrError::InvalidPublicKey(e.to_string()))
+    }
+}
+
+impl fmt::Display for NostrPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// A Nostr secret key. Debug output never includes it.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct NostrSecretKey(SecretKey);
+
+impl NostrSecretKey {
+    pub fn secret_key(&self) -> SecretKey {
+        self.0
+    }
+
+    pub fn keypair(&self) -> KeyPair {
+        KeyPair::from_secret_key(&Secp256k1::signing_only(), &self.0)
+    }
+
+    pub fn public_key(&self) -> NostrPublicKey {
+        NostrPublicKey(self.keypair().x_only_public_key().0)
+    }
+
+    // The secret behind NostrPublicKey::segwit_v0_key: negated when this key's point has an odd y
+    pub fn segwit_v0_secret(&self) -> SecretKey {
+        match self.keypair().x_only_public_key().1 {
+            Parity::Even => self.0,
+            Parity::Odd => self.0.negate(),
+        }
+    }
+
+    pub fn to_nsec(&self) -> String {
+        encode_key(NSEC, &self.0.secret_bytes())
+    }
+}
+
+// Accepts an nsec or 64 hex characters
+impl FromStr for NostrSecretKey {
+    type Err = NostrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SecretKey::from_slice(&decode_key(s, NSEC)?)
+            .map(Self)
+            .map_err(|e| NostrError::InvalidSecretKey(e.to_string()))
+    }
+}
+
+impl fmt::Debug for NostrSecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NostrSecretKey({})", self.public_key())
+    }
+}
+
+// A NIP-01 event as it appears on the wire
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NostrEvent {
+    pub id: String,
+    pub pubkey: String,
+    pub created_at: i64,
+    pub kind: u32,
+    pub tags: Vec<Vec<String>>,
+    pub content: String,
+    pub sig: String,
+}
+
+impl NostrEvent {
+    // Builds and signs an event, filling in `id`, `pubkey` and `sig`
+    pub fn sign(keypair: &KeyPair, created_at: i64, kind: u32, tags: Vec<Vec<String>>, content: String) -> Self {
+        let secp = Secp256k1::signing_only();
+        let (pubkey, _) = keypair.x_only_public_key();
+        let mut event = Self {
+            id: String::new(),
+            pubkey: pubkey.to_string(),
+            created_at,
+            kind,
+            tags,
+            content,
+            sig: String::new(),
+        };
+
+        let id = event.compute_id();
+        let message = Message::from_slice(&id).expect("sha256 digests are 32 bytes");
+        event.id = id.to_string();
+        event.sig = secp.sign_schnorr_no_aux_rand(&message, keypair).to_string();
+        event
+    }
+
+    fn compute_id(&self) -> sha256::Hash {
+        let serialized = serde_json::json!([0, self.pubkey, self.created_at, self.kind, self.tags, self.content]);
+        sha256::Hash::hash(serialized.to_string().as_bytes())
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter()
+            .find(|tag| tag.first().map(String::as_str) == Some(name))
+            .and_then(|tag| tag.get(1))
+            .map(String::as_str)
+    }
+
+    // Checks the id against the event contents and the signature against the id
+    pub fn verify(&self) -> Result<NostrPublicKey, NostrError> {
+        let pubkey = NostrPublicKey::from_hex(&self.pubkey)?;
+
+        let id = self.compute_id();
+        if id.to_string() != self.id {
+            return Err(NostrError::IdMismatch);
+        }
+
+        let signature = schnorr::Signature::from_str(&self.sig)
+            .map_err(|e| NostrError::InvalidSignature(e.to_string()))?;
+        let message = Message::from_slice(&id).expect("sha256 digests are 32 bytes");
+
+        Secp256k1::verification_only()
+            .verify_schnorr(&signature, &message, &pubkey.x_only())
+            .map_err(|e| NostrError::InvalidSignature(e.to_string()))?;
+
+        Ok(pubkey)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::secp256k1::SecretKey;
+
+    fn keypair() -> KeyPair {
+        let secret = SecretKey::from_slice(&[7u8; 32]).unwrap();
+        KeyPair::from_secret_key(&Secp256k1::new(), &secret)
+    }
+
+    #[test]
+    fn test_signed_event_verifies() {
+        let event = NostrEvent::sign(&keypair(), 1_700_000_000, 1, vec![vec!["t".into(), "will".into()]], "hello\n\"world\"".into());
+        let pubkey = event.verify().unwrap();
+        assert_eq!(pubkey.to_hex(), event.pubkey);
+        assert_eq!(event.tag("t"), Some("will"));
+    }
+
+    #[test]
+    fn test_tampered_event_rejected() {
+        let mut event = NostrEvent::sign(&keypair(), 1_700_000_000, 1, vec![], "hello".into());
+        event.content = "goodbye".into();
+        assert_eq!(event.verify(), Err(NostrError::IdMismatch));
+    }
+
+    #[test]
+    fn test_nip19_vectors_round_trip() {
+        let npub = "npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptg";
+        let pubkey = NostrPublicKey::from_str(npub).unwrap();
+        assert_eq!(pubkey.to_hex(), "7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e");
+        assert_eq!(pubkey.to_npub(), npub);
+        assert_eq!(NostrPublicKey::from_str(&pubkey.to_hex()).unwrap(), pubkey);
+
+        let nsec = "nsec1vl029mgpspedva04g90vltkh6fvh240zqtv9k0t9af8935ke9laqsnlfe5";
+        let secret = NostrSecretKey::from_str(nsec).unwrap();
+        assert_eq!(hex::encode(secret.secret_key().secret_bytes()), "67dea2ed018072d675f5415ecfaed7d2597555e202d85b3d65ea4e58d2d92ffa");
+        assert_eq!(secret.to_nsec(), nsec);
+        assert!(!format!("{:?}", secret).contains("67dea2ed"));
+    }
+
+    #[test]
+    fn test_key_errors_are_precise() {
+        let nsec = "nsec1vl029mgpspedva04g90vltkh6fvh240zqtv9k0t9af8935ke9laqsnlfe5";
+        assert_eq!(
+            NostrPublicKey::from_str(nsec),
+            Err(NostrError::WrongPrefix { expected: "npub", found: "nsec".into() }),
+        );
+
+        let mistyped = "npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptq";
+        assert_eq!(NostrPublicKey::from_str(mistyped), Err(NostrError::BadChecksum));
+
+        assert_eq!(
+            NostrPublicKey::from_str(&"7e".repeat(31)),
+            Err(NostrError::WrongLength { expected: 64, found: 62, unit: "hex characters" }),
+        );
+        let short = encode_key("npub", &[7; 31]);
+        assert_eq!(
+            NostrPublicKey::from_str(&short),
+            Err(NostrError::WrongLength { expected: 32, found: 31, unit: "bytes" }),
+        );
+        // Events must carry hex keys
+        assert!(NostrPublicKey::from_hex("npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptg").is_err());
+    }
+
+    #[test]
+    fn test_segwit_secret_matches_even_key_for_either_parity() {
+        let secp = Secp256k1::new();
+        let mut parities = Vec::new();
+        for seed in 1..=8u8 {
+            let secret = NostrSecretKey(SecretKey::from_slice(&[seed; 32]).unwrap());
+            parities.push(secret.keypair().x_only_public_key().1);
+
+            let derived = PublicKey::new(secret.segwit_v0_secret().public_key(&secp));
+            assert_eq!(derived, secret.public_key().segwit_v0_key());
+            assert_eq!(secret.public_key().taproot_key(), secret.public_key().x_only());
+        }
+        assert!(parities.contains(&Parity::Odd) && parities.contains(&Parity::Even));
+    }
+}
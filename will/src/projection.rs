@@ -0,0 +1,218 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use crate::engine::ProjectionEngine;
+use crate::metrics::metrics;
+use crate::spending::{SpendingError, UserModel};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    #[default]
+    Yearly,
+    Quarterly,
+    Monthly,
+}
+
+impl Granularity {
+    pub fn periods_per_year(&self) -> u32 {
+        match self {
+            Granularity::Yearly => 1,
+            Granularity::Quarterly => 4,
+            Granularity::Monthly => 12,
+        }
+    }
+}
+
+// Nominal figures are as projected; real figures are deflated back to year-0 money
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Basis {
+    #[default]
+    Nominal,
+    Real,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProjectionOptions {
+    pub horizon: u32,
+    pub granularity: Granularity,
+    pub basis: Basis,
+    pub inflation_rate: f64,
+    pub currency: String,
+    // Units of `currency` per unit of the model's currency
+    pub fx_rate: f64,
+}
+
+impl ProjectionOptions {
+    // Yearly, nominal figures in the model's own currency over its own horizon
+    pub fn for_model(model: &UserModel) -> Self {
+        Self {
+            horizon: model.projection_years,
+            granularity: Granularity::Yearly,
+            basis: Basis::Nominal,
+            inflation_rate: 0.0,
+            currency: model.currency.clone(),
+            fx_rate: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ProjectTotal {
+    pub project: String,
+    pub total: f64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Period {
+    pub year: u32,
+    // 0-based quarter or month within the year; always 0 for yearly projections
+    pub period: u32,
+    pub total: f64,
+    pub projects: Vec<ProjectTotal>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Projection {
+    pub currency: String,
+    pub granularity: Granularity,
+    pub basis: Basis,
+    pub periods: Vec<Period>,
+}
+
+impl Projection {
+    pub fn yearly_totals(&self) -> Vec<(u32, f64)> {
+        let mut totals: Vec<(u32, f64)> = Vec::new();
+        for period in &self.periods {
+            match totals.last_mut() {
+                Some((year, total)) if *year == period.year => *total += period.total,
+                _ => totals.push((period.year, period.total)),
+            }
+        }
+        totals
+    }
+
+    // One row per period with a column for each project
+    pub fn to_csv(&self) -> Result<String, csv::Error> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+
+        let mut header = vec!["year".to_string(), "period".to_string(), "total".to_string()];
+        if let Some(first) = self.periods.first() {
+            header.extend(first.projects.iter().map(|p| p.project.clone()));
+        }
+        writer.write_record(&header)?;
+
+        for period in &self.periods {
+            let mut record = vec![period.year.to_string(), period.period.to_string(), period.total.to_string()];
+            record.extend(period.projects.iter().map(|p| p.total.to_string()));
+            writer.write_record(&record)?;
+        }
+
+        let bytes = writer.into_inner().map_err(|e| e.into_error())?;
+        Ok(String::from_utf8(bytes).expect("csv output is built from strings"))
+    }
+}
+
+// Projects the model over the requested horizon, splitting each year's spend evenly across its periods
+pub fn project(model: &UserModel, options: &ProjectionOptions, engine: &ProjectionEngine) -> Result<Projection, SpendingError> {
+    let mut periods = Vec::with_capacity((options.horizon * options.granularity.periods_per_year()) as usize);
+
+    for year in 0..options.horizon {
+        periods.extend(project_year(model, options, engine, year)?);
+    }
+    metrics().observe_projection(periods.len());
+
+    Ok(Projection {
+        currency: options.currency.clone(),
+        granularity: options.granularity,
+        basis: options.basis,
+        periods,
+    })
+}
+
+// The periods of a single projected year, so long-running callers can report progress between years
+pub fn project_year(model: &UserModel, options: &ProjectionOptions, engine: &ProjectionEngine, year: u32) -> Result<Vec<Period>, SpendingError> {
+    let per_year = options.granularity.periods_per_year();
+    let deflator = match options.basis {
+        Basis::Nominal => 1.0,
+        Basis::Real => (1.0 + options.inflation_rate).powi(year as i32),
+    };
+    let scale = options.fx_rate / deflator / per_year as f64;
+
+    let mut yearly = Vec::with_capacity(model.projects.len());
+    for project in &model.projects {
+        yearly.push((project.project_name.clone(), engine.yearly_spend(project, year)? * scale));
+    }
+
+    Ok((0..per_year)
+        .map(|period| {
+            let projects: Vec<ProjectTotal> = yearly.iter()
+                .map(|(project, total)| ProjectTotal { project: project.clone(), total: *total })
+                .collect();
+            let total = projects.iter().map(|p| p.total).sum();
+            Period { year, period, total, projects }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spending::{GrowthType, ProjectSpend};
+
+    fn model() -> UserModel {
+        let mut user = UserModel::new("alice".into(), 2).unwrap();
+        user.add_project(ProjectSpend::new("Rent".into(), 10.0, 0.1, GrowthType::Compound).unwrap());
+        user.add_project(ProjectSpend::new("Food".into(), 2.0, 0.0, GrowthType::Flat).unwrap());
+        user
+    }
+
+    #[test]
+    fn test_quarterly_periods_sum_to_yearly_totals() {
+        let user = model();
+        let options = ProjectionOptions {
+            granularity: Granularity::Quarterly,
+            ..ProjectionOptions::for_model(&user)
+        };
+
+        let projection = project(&user, &options, &ProjectionEngine::new()).unwrap();
+        assert_eq!(projection.periods.len(), 8);
+
+        let totals = projection.yearly_totals();
+        let expected = user.calculate_total_spend().unwrap();
+        for (year, total) in totals {
+            assert!((total - expected[&year]).abs() < 1e-9);
+        }
+
+        let csv = projection.to_csv().unwrap();
+        assert!(csv.starts_with("year,period,total,Rent,Food\n"));
+        assert_eq!(csv.lines().count(), 9);
+    }
+
+    #[test]
+    fn test_real_basis_deflates_and_converts() {
+        let user = model();
+        let options = ProjectionOptions {
+            basis: Basis::Real,
+            inflation_rate: 0.1,
+            currency: "EUR".into(),
+            fx_rate: 0.5,
+            ..ProjectionOptions::for_model(&user)
+        };
+
+        // Rent grows at exactly the inflation rate, so its real spend stays flat
+        let projection = project(&user, &options, &ProjectionEngine::new()).unwrap();
+        let rent: Vec<f64> = projection.periods.iter().map(|p| p.projects[0].total).collect();
+        assert!((rent[0] - 1825.0).abs() < 1e-9);
+        assert!((rent[1] - 1825.0).abs() < 1e-9);
+        assert_eq!(projection.currency, "EUR");
+    }
+}
@@ -0,0 +1,238 @@
+use base64::Engine;
+use bitcoin::blockdata::opcodes::all::{OP_CHECKMULTISIG, OP_PUSHNUM_1, OP_PUSHNUM_16};
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::consensus::encode::{deserialize, serialize};
+use bitcoin::psbt::raw::ProprietaryKey;
+use bitcoin::psbt::{Input, PartiallySignedTransaction};
+use bitcoin::{Network, PublicKey, Script, Transaction, Witness};
+use thiserror::Error;
+
+pub type Psbt = PartiallySignedTransaction;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum PsbtError {
+    #[error("Invalid PSBT: {0}")]
+    Invalid(String),
+    #[error("No PSBTs were given")]
+    Empty,
+    #[error("PSBT {0} is for a different transaction than the first")]
+    DifferentTransaction(usize),
+    #[error("Input {0} cannot be finalized yet: {1}")]
+    Incomplete(usize, String),
+    #[error("The PSBT is for {0}, not {1}")]
+    WrongNetwork(String, Network),
+}
+
+pub fn encode(psbt: &Psbt) -> String {
+    base64::engine::general_purpose::STANDARD.encode(serialize(psbt))
+}
+
+pub fn decode(s: &str) -> Result<Psbt, PsbtError> {
+    let bytes = base64::engine::general_purpose::STANDARD.decode(s.trim())
+        .map_err(|e| PsbtError::Invalid(e.to_string()))?;
+    deserialize(&bytes).map_err(|e| PsbtError::Invalid(e.to_string()))
+}
+
+// Our proprietary global field naming the network a PSBT was built for, as its magic bytes
+fn network_key() -> ProprietaryKey {
+    ProprietaryKey { prefix: b"will".to_vec(), subtype: 0, key: Vec::new() }
+}
+
+pub fn set_network(psbt: &mut Psbt, network: Network) {
+    psbt.proprietary.insert(network_key(), network.magic().to_le_bytes().to_vec());
+}
+
+// Refuses PSBTs tagged for another network, or carrying extended keys of one. Untagged PSBTs
+// from other tools pass; the node check before broadcasting still applies to them.
+pub fn check_network(psbt: &Psbt, network: Network) -> Result<(), PsbtError> {
+    if let Some(magic) = psbt.proprietary.get(&network_key()) {
+        let tagged = <[u8; 4]>::try_from(magic.as_slice()).ok()
+            .and_then(|magic| Network::from_magic(u32::from_le_bytes(magic)));
+        if tagged != Some(network) {
+            let name = tagged.map_or_else(|| "an unknown network".to_string(), |tagged| tagged.to_string());
+            return Err(PsbtError::WrongNetwork(name, network));
+        }
+    }
+    // xpubs only tell mainnet from the test networks
+    if let Some(xpub) = psbt.xpub.keys().find(|xpub| (xpub.network == Network::Bitcoin) != (network == Network::Bitcoin)) {
+        return Err(PsbtError::WrongNetwork(xpub.network.to_string(), network));
+    }
+    Ok(())
+}
+
+// The BIP-174 combiner: merges what each party added to copies of the same unsigned transaction
+pub fn combine(psbts: Vec<Psbt>) -> Result<Psbt, PsbtError> {
+    let mut psbts = psbts.into_iter().enumerate();
+    let (_, mut combined) = psbts.next().ok_or(PsbtError::Empty)?;
+    for (i, psbt) in psbts {
+        combined.combine(psbt).map_err(|_| PsbtError::DifferentTransaction(i))?;
+    }
+    Ok(combined)
+}
+
+// The BIP-174 finalizer for the input types our escrows are funded from: P2WPKH, taproot key path
+// and P2WSH multisig. Inputs the signer's wallet already finalized are left alone.
+pub fn finalize(psbt: &mut Psbt) -> Result<(), PsbtError> {
+    for (i, input) in psbt.inputs.iter_mut().enumerate() {
+        if input.final_script_witness.is_some() || input.final_script_sig.is_some() {
+            continue;
+        }
+        let witness = final_witness(input, i, &psbt.unsigned_tx)?;
+
+        // Once final, the signing data is no longer needed
+        *input = Input {
+            non_witness_utxo: input.non_witness_utxo.take(),
+            witness_utxo: input.witness_utxo.take(),
+            final_script_witness: Some(witness),
+            proprietary: std::mem::take(&mut input.proprietary),
+            unknown: std::mem::take(&mut input.unknown),
+            ..Default::default()
+        };
+    }
+    Ok(())
+}
+
+// Finalizes and extracts the network transaction
+pub fn extract(mut psbt: Psbt) -> Result<Transaction, PsbtError> {
+    finalize(&mut psbt)?;
+    Ok(psbt.extract_tx())
+}
+
+fn final_witness(input: &Input, index: usize, tx: &Transaction) -> Result<Witness, PsbtError> {
+    let incomplete = |reason: &str| PsbtError::Incomplete(index, reason.to_string());
+    let spent = match (&input.witness_utxo, &input.non_witness_utxo) {
+        (Some(utxo), _) => utxo.clone(),
+        (None, Some(prev)) => prev.output.get(tx.input[index].previous_output.vout as usize)
+            .cloned()
+            .ok_or_else(|| incomplete("the previous transaction has no such output"))?,
+        (None, None) => return Err(incomplete("the spent output is missing")),
+    };
+    let script_pubkey = &spent.script_pubkey;
+
+    if script_pubkey.is_v1_p2tr() {
+        let sig = input.tap_key_sig.ok_or_else(|| incomplete("no taproot key path signature"))?;
+        return Ok(Witness::from_vec(vec![sig.to_vec()]));
+    }
+
+    if script_pubkey.is_v0_p2wpkh() {
+        return input.partial_sigs.iter()
+            .find(|(key, _)| key.wpubkey_hash().is_some_and(|hash| Script::new_v0_p2wpkh(&hash) == *script_pubkey))
+            .map(|(key, sig)| Witness::from_vec(vec![sig.to_vec(), key.to_bytes()]))
+            .ok_or_else(|| incomplete("no signature from the key the output pays"));
+    }
+
+    if script_pubkey.is_v0_p2wsh() {
+        let witness_script = input.witness_script.as_ref().ok_or_else(|| incomplete("the witness script is missing"))?;
+        if Script::new_v0_p2wsh(&witness_script.wscript_hash()) != *script_pubkey {
+            return Err(incomplete("the witness script does not match the spent output"));
+        }
+        let (threshold, keys) = multisig(witness_script).ok_or_else(|| incomplete("only multisig witness scripts are supported"))?;
+
+        // CHECKMULTISIG pops one element too many, and wants the signatures in key order
+        let mut stack = vec![Vec::new()];
+        stack.extend(keys.iter().filter_map(|key| input.partial_sigs.get(key)).take(threshold).map(|sig| sig.to_vec()));
+        if stack.len() <= threshold {
+            return Err(incomplete(&format!("{} of {} signatures", stack.len() - 1, threshold)));
+        }
+        stack.push(witness_script.to_bytes());
+        return Ok(Witness::from_vec(stack));
+    }
+
+    Err(incomplete("unsupported output type"))
+}
+
+// <m> <keys...> <n> CHECKMULTISIG
+fn multisig(script: &Script) -> Option<(usize, Vec<PublicKey>)> {
+    let small_int = |instruction: &Instruction| match instruction {
+        Instruction::Op(op) if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&op.to_u8()) => {
+            Some((op.to_u8() - OP_PUSHNUM_1.to_u8() + 1) as usize)
+        }
+        _ => None,
+    };
+
+    let instructions = script.instructions().collect::<Result<Vec<_>, _>>().ok()?;
+    let [first, keys @ .., count, Instruction::Op(last)] = instructions.as_slice() else {
+        return None;
+    };
+    if *last != OP_CHECKMULTISIG {
+        return None;
+    }
+    let keys = keys.iter()
+        .map(|instruction| match instruction {
+            Instruction::PushBytes(bytes) => PublicKey::from_slice(bytes).ok(),
+            Instruction::Op(_) => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let threshold = small_int(first)?;
+    (small_int(count)? == keys.len() && threshold <= keys.len()).then_some((threshold, keys))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::blockdata::script::Builder;
+    use bitcoin::secp256k1::{Message, Secp256k1, SecretKey};
+    use bitcoin::{EcdsaSig, OutPoint, PackedLockTime, Sequence, TxIn, TxOut};
+
+    fn unsigned_tx() -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn { previous_output: OutPoint::default(), script_sig: Script::new(), sequence: Sequence::MAX, witness: Witness::new() }],
+            output: vec![TxOut { value: 9_000, script_pubkey: Script::new() }],
+        }
+    }
+
+    #[test]
+    fn test_partial_signatures_combine_into_a_multisig_witness() {
+        let secp = Secp256k1::new();
+        let secrets: Vec<SecretKey> = (1..=3u8).map(|i| SecretKey::from_slice(&[i; 32]).unwrap()).collect();
+        let keys: Vec<PublicKey> = secrets.iter().map(|secret| PublicKey::new(secret.public_key(&secp))).collect();
+        let witness_script = Builder::new().push_int(2)
+            .push_key(&keys[0]).push_key(&keys[1]).push_key(&keys[2])
+            .push_int(3).push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+
+        let mut unsigned = Psbt::from_unsigned_tx(unsigned_tx()).unwrap();
+        unsigned.inputs[0].witness_utxo = Some(TxOut { value: 10_000, script_pubkey: witness_script.to_v0_p2wsh() });
+        unsigned.inputs[0].witness_script = Some(witness_script.clone());
+
+        // Each party signs its own copy; the signatures need not be valid to test the layout
+        let message = Message::from_slice(&[7; 32]).unwrap();
+        let signed_by = |i: usize| {
+            let mut psbt = unsigned.clone();
+            psbt.inputs[0].partial_sigs.insert(keys[i], EcdsaSig::sighash_all(secp.sign_ecdsa(&message, &secrets[i])));
+            decode(&encode(&psbt)).unwrap()
+        };
+
+        let one = combine(vec![signed_by(2)]).unwrap();
+        assert_eq!(extract(one).unwrap_err(), PsbtError::Incomplete(0, "1 of 2 signatures".into()));
+
+        let tx = extract(combine(vec![signed_by(2), signed_by(0)]).unwrap()).unwrap();
+        let witness = tx.input[0].witness.to_vec();
+        assert_eq!(witness.len(), 4);
+        assert!(witness[0].is_empty());
+        // Signatures follow the key order, not the order they arrived in
+        assert_eq!(witness[1], EcdsaSig::sighash_all(secp.sign_ecdsa(&message, &secrets[0])).to_vec());
+        assert_eq!(witness[3], witness_script.to_bytes());
+
+        let mut other = unsigned_tx();
+        other.output[0].value = 1;
+        assert_eq!(
+            combine(vec![unsigned.clone(), Psbt::from_unsigned_tx(other).unwrap()]).unwrap_err(),
+            PsbtError::DifferentTransaction(1),
+        );
+        assert!(matches!(decode("not a psbt"), Err(PsbtError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_network_tag_survives_encoding() {
+        let mut psbt = Psbt::from_unsigned_tx(unsigned_tx()).unwrap();
+        assert_eq!(check_network(&psbt, Network::Bitcoin), Ok(()));
+
+        set_network(&mut psbt, Network::Testnet);
+        let psbt = decode(&encode(&psbt)).unwrap();
+        assert_eq!(check_network(&psbt, Network::Testnet), Ok(()));
+        assert_eq!(check_network(&psbt, Network::Bitcoin), Err(PsbtError::WrongNetwork("testnet".into(), Network::Bitcoin)));
+    }
+}
@@ -0,0 +1,217 @@
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpMessage, ResponseError};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::error::ApiError;
+use crate::nostr::NostrPublicKey;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub requests_per_minute_per_ip: u32,
+    pub requests_per_minute_per_pubkey: u32,
+    // Requests a client may make in a burst before the per-minute rate applies
+    pub burst: u32,
+    pub max_projects: usize,
+    pub max_projection_years: u32,
+    // Background projection jobs a user may have running at once
+    pub max_running_jobs: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_minute_per_ip: 120,
+            requests_per_minute_per_pubkey: 60,
+            burst: 20,
+            max_projects: 100,
+            max_projection_years: 100,
+            max_running_jobs: 4,
+        }
+    }
+}
+
+impl LimitsConfig {
+    pub fn check_projects(&self, count: usize) -> Result<(), ApiError> {
+        if count > self.max_projects {
+            return Err(limit_exceeded(format!("A model may have at most {} projects", self.max_projects))
+                .with_field("projects"));
+        }
+        Ok(())
+    }
+
+    pub fn check_projection_years(&self, years: u32) -> Result<(), ApiError> {
+        if years > self.max_projection_years {
+            return Err(limit_exceeded(format!("Projections may cover at most {} years", self.max_projection_years))
+                .with_field("projection_years"));
+        }
+        Ok(())
+    }
+
+    pub fn check_running_jobs(&self, running: usize) -> Result<(), ApiError> {
+        if running >= self.max_running_jobs {
+            return Err(limit_exceeded(format!("At most {} jobs may run at once", self.max_running_jobs))
+                .with_field("jobs"));
+        }
+        Ok(())
+    }
+}
+
+fn limit_exceeded(message: String) -> ApiError {
+    ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "limit_exceeded", message)
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// Token buckets keyed by client; each key may burst up to `capacity` and then refills steadily
+pub struct RateLimiter<K> {
+    capacity: f64,
+    refill_per_sec: f64,
+    buckets: Mutex<HashMap<K, Bucket>>,
+}
+
+// Buckets are pruned once the map grows past this many clients
+const PRUNE_THRESHOLD: usize = 10_000;
+
+impl<K: Eq + Hash + Clone> RateLimiter<K> {
+    pub fn new(per_minute: u32, burst: u32) -> Self {
+        Self {
+            capacity: burst.max(1) as f64,
+            refill_per_sec: per_minute as f64 / 60.0,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Takes one token for `key`, or reports how long until one is available
+    pub fn check(&self, key: &K) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: &K, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() > PRUNE_THRESHOLD {
+            let full_after = Duration::from_secs_f64(self.capacity / self.refill_per_sec.max(f64::EPSILON));
+            buckets.retain(|_, bucket| now.duration_since(bucket.updated) < full_after);
+        }
+
+        let bucket = buckets.entry(key.clone()).or_insert(Bucket { tokens: self.capacity, updated: now });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else if self.refill_per_sec > 0.0 {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.refill_per_sec))
+        } else {
+            Err(Duration::MAX)
+        }
+    }
+}
+
+pub struct RateLimits {
+    pub config: LimitsConfig,
+    pub payload_limit: usize,
+    by_ip: RateLimiter<IpAddr>,
+    by_pubkey: RateLimiter<NostrPublicKey>,
+}
+
+impl RateLimits {
+    pub fn new(config: LimitsConfig, payload_limit: usize) -> Self {
+        Self {
+            by_ip: RateLimiter::new(config.requests_per_minute_per_ip, config.burst),
+            by_pubkey: RateLimiter::new(config.requests_per_minute_per_pubkey, config.burst),
+            config,
+            payload_limit,
+        }
+    }
+}
+
+fn too_many_requests(retry_after: Duration) -> ApiError {
+    let seconds = retry_after.as_secs_f64().ceil().min(u32::MAX as f64) as u64;
+    ApiError::new(StatusCode::TOO_MANY_REQUESTS, "rate_limited", "Too many requests, slow down")
+        .with_details(serde_json::json!({ "retry_after": seconds }))
+        .with_retry_after(seconds)
+}
+
+fn reject<B>(req: ServiceRequest, e: ApiError) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    Ok(req.into_response(e.error_response()).map_into_right_body())
+}
+
+// Throttles by client address and refuses bodies that declare more than the payload limit
+pub async fn limit_by_ip<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    if let Some(limits) = req.app_data::<web::Data<RateLimits>>().cloned() {
+        let declared = req.headers().get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+
+        if let Some(length) = declared.filter(|length| *length > limits.payload_limit) {
+            let e = ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", "Request body is too large")
+                .with_details(serde_json::json!({ "length": length, "limit": limits.payload_limit }));
+            return reject(req, e);
+        }
+
+        if let Some(ip) = req.peer_addr().map(|addr| addr.ip()) {
+            if let Err(retry_after) = limits.by_ip.check(&ip) {
+                return reject(req, too_many_requests(retry_after));
+            }
+        }
+    }
+
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+// Throttles by the NIP-98 signer; must run inside the auth middleware
+pub async fn limit_by_pubkey<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    let pubkey = req.extensions().get::<NostrPublicKey>().copied();
+
+    if let (Some(limits), Some(pubkey)) = (req.app_data::<web::Data<RateLimits>>().cloned(), pubkey) {
+        if let Err(retry_after) = limits.by_pubkey.check(&pubkey) {
+            return reject(req, too_many_requests(retry_after));
+        }
+    }
+
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_allows_burst_then_refills() {
+        let limiter = RateLimiter::new(60, 3);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check_at(&"client", start).is_ok());
+        }
+        let retry_after = limiter.check_at(&"client", start).unwrap_err();
+        assert!((retry_after.as_secs_f64() - 1.0).abs() < 1e-6);
+
+        // Other clients have their own bucket
+        assert!(limiter.check_at(&"other", start).is_ok());
+
+        // One request per second refills
+        assert!(limiter.check_at(&"client", start + Duration::from_millis(1000)).is_ok());
+        assert!(limiter.check_at(&"client", start + Duration::from_millis(1000)).is_err());
+    }
+}
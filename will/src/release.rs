@@ -0,0 +1,402 @@
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::util::taproot::{LeafVersion, TapLeafHash};
+use bitcoin::{Address, Network, OutPoint, PackedLockTime, Script, Sequence, Transaction, TxIn, TxOut, Witness};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use thiserror::Error;
+use utoipa::ToSchema;
+use crate::coin_selection::{dust_threshold, MAX_FEE};
+use crate::escrow_script::{EscrowOutput, EscrowScript, EscrowTemplate, Role, SpendingPath, Timelock};
+use crate::psbt::Psbt;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ReleaseError {
+    #[error("This escrow has no {0} path")]
+    PathUnavailable(ReleasePath),
+    #[error("Invalid payouts: {0}")]
+    InvalidPayouts(String),
+    #[error("{0} is not a {1} address")]
+    WrongNetwork(String, Network),
+    #[error("The payout to {0} of {1} sats is below its {2} sat dust limit")]
+    Dust(String, u64, u64),
+    #[error("A fee of {0} sats is absurd; the limit is {MAX_FEE} sats")]
+    AbsurdFee(u64),
+    #[error("The PSBT does not spend this escrow")]
+    WrongInput,
+    #[error("{0} has not signed yet")]
+    MissingSignature(Role),
+    #[error("The hashlock preimage is missing")]
+    MissingPreimage,
+    // The server holds no keys and runs no MuSig2 sessions
+    #[error("The key path needs the parties' aggregate MuSig2 signature as tap_key_sig; produce it with an external MuSig2 signer")]
+    MissingMuSig2Signature,
+}
+
+// The ways out of an escrow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReleasePath {
+    // The parties agree: both sign
+    Cooperative,
+    // The arbitrator settles a dispute together with the template's required party
+    Arbitrated,
+    // The timelock has passed and its recipient spends alone
+    Timeout,
+    // The hashlock recipient spends alone with the preimage
+    Hashlock,
+}
+
+impl fmt::Display for ReleasePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReleasePath::Cooperative => write!(f, "cooperative"),
+            ReleasePath::Arbitrated => write!(f, "arbitrated"),
+            ReleasePath::Timeout => write!(f, "timeout"),
+            ReleasePath::Hashlock => write!(f, "hashlock"),
+        }
+    }
+}
+
+impl ReleasePath {
+    fn matches(&self, path: &SpendingPath) -> bool {
+        match self {
+            ReleasePath::Cooperative => path.timelock.is_none() && path.hashlock.is_none() && !path.signers.contains(&Role::Arbitrator),
+            ReleasePath::Arbitrated => path.timelock.is_none() && path.hashlock.is_none() && path.signers.contains(&Role::Arbitrator),
+            ReleasePath::Timeout => path.timelock.is_some(),
+            ReleasePath::Hashlock => path.hashlock.is_some(),
+        }
+    }
+}
+
+// One destination of the released funds; at most one payout may leave out its amount to take
+// whatever remains after the other payouts and the fee
+#[derive(Debug, Clone)]
+pub struct Payout {
+    pub address: Address,
+    pub amount: Option<u64>,
+}
+
+impl EscrowScript {
+    pub fn path(&self, release: ReleasePath) -> Result<&SpendingPath, ReleaseError> {
+        self.spending_paths.iter()
+            .find(|path| release.matches(path))
+            .ok_or(ReleaseError::PathUnavailable(release))
+    }
+}
+
+// Builds the unsigned PSBT spending the escrow output at `outpoint`, worth `value`, through
+// `release`. Timeouts set nSequence to the relative lock or nLockTime to the absolute one.
+pub fn build_release(
+    escrow: &EscrowScript,
+    release: ReleasePath,
+    outpoint: OutPoint,
+    value: u64,
+    payouts: &[Payout],
+    fee: u64,
+    network: Network,
+) -> Result<Psbt, ReleaseError> {
+    let path = escrow.path(release)?;
+    let output = split(value, payouts, fee, network)?;
+
+    let (sequence, lock_time) = match path.timelock {
+        Some(Timelock::Blocks(blocks)) => (Sequence::from_height(blocks), PackedLockTime::ZERO),
+        // CLTV needs a non-final input
+        Some(Timelock::Height(lock) | Timelock::Time(lock)) => (Sequence::ENABLE_LOCKTIME_NO_RBF, PackedLockTime(lock)),
+        None => (Sequence::MAX, PackedLockTime::ZERO),
+    };
+    let tx = Transaction {
+        // Version 2 enables relative lock times
+        version: 2,
+        lock_time,
+        input: vec![TxIn { previous_output: outpoint, script_sig: Script::new(), sequence, witness: Witness::new() }],
+        output,
+    };
+
+    let mut psbt = Psbt::from_unsigned_tx(tx).expect("the release transaction is built unsigned");
+    let input = &mut psbt.inputs[0];
+    input.witness_utxo = Some(TxOut { value, script_pubkey: escrow.address.script_pubkey() });
+    match &escrow.output {
+        EscrowOutput::P2wsh { witness_script } => input.witness_script = Some(witness_script.clone()),
+        EscrowOutput::Taproot(tree) => {
+            input.tap_internal_key = Some(tree.internal_key());
+            input.tap_merkle_root = tree.spend_info.merkle_root();
+            // Only the path's own leaf, so signers sign nothing else
+            if let Some(leaf) = path.leaf {
+                let script = tree.scripts[leaf].clone();
+                let control_block = tree.control_block(&script).expect("every leaf is in the tree");
+                input.tap_scripts.insert(control_block, (script, LeafVersion::TapScript));
+            }
+        }
+    }
+    Ok(psbt)
+}
+
+fn split(value: u64, payouts: &[Payout], fee: u64, network: Network) -> Result<Vec<TxOut>, ReleaseError> {
+    let invalid = |reason: &str| ReleaseError::InvalidPayouts(reason.to_string());
+    if payouts.is_empty() {
+        return Err(invalid("at least one payout is needed"));
+    }
+    if let Some(payout) = payouts.iter().find(|payout| !payout.address.is_valid_for_network(network)) {
+        return Err(ReleaseError::WrongNetwork(payout.address.to_string(), network));
+    }
+    if payouts.iter().filter(|payout| payout.amount.is_none()).count() > 1 {
+        return Err(invalid("only one payout can take the remainder"));
+    }
+    if payouts.iter().any(|payout| payout.amount == Some(0)) {
+        return Err(invalid("amounts must be greater than 0"));
+    }
+    if fee > MAX_FEE {
+        return Err(ReleaseError::AbsurdFee(fee));
+    }
+
+    let fixed = payouts.iter().filter_map(|payout| payout.amount).try_fold(fee, u64::checked_add);
+    let remainder = fixed.and_then(|fixed| value.checked_sub(fixed))
+        .ok_or_else(|| invalid(&format!("the payouts and fee exceed the escrow's {} sats", value)))?;
+    let has_remainder = payouts.iter().any(|payout| payout.amount.is_none());
+    match (has_remainder, remainder) {
+        (true, 0) => return Err(invalid("nothing remains for the remainder payout")),
+        // Anything unassigned would silently go to the miners
+        (false, remainder) if remainder > 0 => {
+            return Err(invalid(&format!("{} sats are unassigned; give one payout no amount to take them", remainder)));
+        }
+        _ => {}
+    }
+
+    // Dust outputs are non-standard, so the transaction would not relay
+    payouts.iter()
+        .map(|payout| {
+            let output = TxOut { value: payout.amount.unwrap_or(remainder), script_pubkey: payout.address.script_pubkey() };
+            let threshold = dust_threshold(&output.script_pubkey);
+            if output.value < threshold {
+                return Err(ReleaseError::Dust(payout.address.to_string(), output.value, threshold));
+            }
+            Ok(output)
+        })
+        .collect()
+}
+
+// Assembles the witness for `release` from the signatures (and preimage) gathered in the PSBT.
+// Whether the witness satisfies the script is left to the node the transaction is broadcast to.
+pub fn finalize_release(escrow: &EscrowScript, release: ReleasePath, psbt: &mut Psbt) -> Result<(), ReleaseError> {
+    let path = escrow.path(release)?;
+    let spends_escrow = match psbt.inputs.as_slice() {
+        [input] => input.witness_utxo.as_ref().is_some_and(|utxo| utxo.script_pubkey == escrow.address.script_pubkey()),
+        _ => false,
+    };
+    if !spends_escrow {
+        return Err(ReleaseError::WrongInput);
+    }
+    let input = &psbt.inputs[0];
+
+    let stack = match &escrow.output {
+        EscrowOutput::P2wsh { witness_script } => {
+            let sig = |role: Role| {
+                let key = escrow.parties.key(role).ok_or(ReleaseError::MissingSignature(role))?.segwit_v0_key();
+                input.partial_sigs.get(&key).map(|sig| sig.to_vec()).ok_or(ReleaseError::MissingSignature(role))
+            };
+            let mut stack = match (release, escrow.template) {
+                (ReleasePath::Timeout, _) => vec![sig(path.signers[0])?],
+                // CHECKMULTISIG pops an extra element and takes signatures in key order
+                (_, EscrowTemplate::A) => vec![Vec::new(), sig(Role::Party1)?, sig(Role::Party2)?],
+                // <required> CHECKSIGVERIFY 1 <either...> 2 CHECKMULTISIG: the required signature is on top
+                _ => vec![Vec::new(), sig(path.signers[1])?, sig(path.signers[0])?],
+            };
+            // With a timelock, the template is the IF branch and the fallback the ELSE branch
+            if escrow.timelock.is_some() {
+                stack.push(if release == ReleasePath::Timeout { Vec::new() } else { vec![1] });
+            }
+            stack.push(witness_script.to_bytes());
+            stack
+        }
+        EscrowOutput::Taproot(tree) => match path.leaf {
+            None => vec![input.tap_key_sig.ok_or(ReleaseError::MissingMuSig2Signature)?.to_vec()],
+            Some(leaf) => {
+                let script = &tree.scripts[leaf];
+                let leaf_hash = TapLeafHash::from_script(script, LeafVersion::TapScript);
+                let sig = |role: Role| {
+                    let key = escrow.parties.key(role).ok_or(ReleaseError::MissingSignature(role))?.taproot_key();
+                    input.tap_script_sigs.get(&(key, leaf_hash)).map(|sig| sig.to_vec()).ok_or(ReleaseError::MissingSignature(role))
+                };
+                let mut stack = match release {
+                    // <first> CHECKSIGVERIFY <second> CHECKSIG: the first signature is checked first, so it goes on top
+                    ReleasePath::Cooperative | ReleasePath::Arbitrated => vec![sig(path.signers[1])?, sig(path.signers[0])?],
+                    ReleasePath::Timeout => vec![sig(path.signers[0])?],
+                    ReleasePath::Hashlock => {
+                        let digest = escrow.hashlock.as_ref().and_then(|lock| lock.digest().ok())
+                            .ok_or(ReleaseError::PathUnavailable(release))?;
+                        let preimage = input.sha256_preimages.get(&sha256::Hash::from_inner(digest)).ok_or(ReleaseError::MissingPreimage)?;
+                        vec![sig(path.signers[0])?, preimage.clone()]
+                    }
+                };
+                stack.push(script.to_bytes());
+                stack.push(tree.control_block(script).expect("every leaf is in the tree").serialize());
+                stack
+            }
+        },
+    };
+
+    psbt.inputs[0] = bitcoin::psbt::Input {
+        witness_utxo: psbt.inputs[0].witness_utxo.take(),
+        final_script_witness: Some(Witness::from_vec(stack)),
+        ..Default::default()
+    };
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::escrow_script::{self, EscrowParties, Hashlock, TimelockPath};
+    use crate::interpreter::{self, VerifyError};
+    use crate::nostr::NostrSecretKey;
+    use crate::signer::LocalSigner;
+    use crate::taproot;
+    use bitcoin::hashes::hex::ToHex;
+    use bitcoin::Txid;
+    use std::str::FromStr;
+
+    // Party 1, Party 2 and the arbitrator hold the secret keys 1, 2 and 3
+    fn secret(role: Role) -> NostrSecretKey {
+        let byte = match role {
+            Role::Party1 => 1,
+            Role::Party2 => 2,
+            Role::Arbitrator => 3,
+        };
+        NostrSecretKey::from_str(&format!("{:064x}", byte)).unwrap()
+    }
+
+    fn parties() -> EscrowParties {
+        EscrowParties {
+            party_1: secret(Role::Party1).public_key(),
+            party_2: secret(Role::Party2).public_key(),
+            arbitrator: Some(secret(Role::Arbitrator).public_key()),
+        }
+    }
+
+    fn payouts() -> Vec<Payout> {
+        let address = |role| Address::p2wpkh(&LocalSigner::from_nostr(&secret(role)).public_key(), Network::Bitcoin).unwrap();
+        vec![
+            Payout { address: address(Role::Party1), amount: Some(30_000) },
+            Payout { address: address(Role::Party2), amount: None },
+        ]
+    }
+
+    fn release(escrow: &EscrowScript, path: ReleasePath, signers: &[Role]) -> Result<Psbt, ReleaseError> {
+        let outpoint = OutPoint { txid: Txid::from_str(&"ab".repeat(32)).unwrap(), vout: 0 };
+        let mut psbt = build_release(escrow, path, outpoint, 100_000, &payouts(), 500, Network::Bitcoin)?;
+        for role in signers {
+            LocalSigner::from_nostr(&secret(*role)).sign_psbt(&mut psbt).unwrap();
+        }
+        finalize_release(escrow, path, &mut psbt)?;
+        Ok(psbt)
+    }
+
+    #[test]
+    fn test_p2wsh_paths_release() {
+        let timelock = TimelockPath { timelock: Timelock::Blocks(144), recipient: Role::Arbitrator };
+        let escrow = escrow_script::escrow_scripts(EscrowTemplate::B, &parties(), Some(timelock), Network::Bitcoin).unwrap();
+
+        let psbt = release(&escrow, ReleasePath::Cooperative, &[Role::Party1, Role::Party2]).unwrap();
+        let tx = psbt.extract_tx();
+        assert_eq!(tx.output.iter().map(|output| output.value).collect::<Vec<_>>(), [30_000, 69_500]);
+        // Dummy, Party 2, Party 1, the IF selector and the script
+        let witness = tx.input[0].witness.to_vec();
+        assert_eq!((witness.len(), witness[0].is_empty(), witness[3].as_slice()), (5, true, &[1u8][..]));
+
+        release(&escrow, ReleasePath::Arbitrated, &[Role::Party1, Role::Arbitrator]).unwrap();
+        assert_eq!(
+            release(&escrow, ReleasePath::Arbitrated, &[Role::Party1]).unwrap_err(),
+            ReleaseError::MissingSignature(Role::Arbitrator),
+        );
+
+        let tx = release(&escrow, ReleasePath::Timeout, &[Role::Arbitrator]).unwrap().extract_tx();
+        assert_eq!(tx.input[0].sequence, Sequence(144));
+        assert!(tx.input[0].witness.to_vec()[1].is_empty());
+
+        // A signature over too short a relative lock still fails CHECKSEQUENCEVERIFY
+        let outpoint = OutPoint { txid: Txid::from_str(&"ab".repeat(32)).unwrap(), vout: 0 };
+        let mut psbt = build_release(&escrow, ReleasePath::Timeout, outpoint, 100_000, &payouts(), 500, Network::Bitcoin).unwrap();
+        psbt.unsigned_tx.input[0].sequence = Sequence(100);
+        LocalSigner::from_nostr(&secret(Role::Arbitrator)).sign_psbt(&mut psbt).unwrap();
+        let spent = psbt.inputs[0].witness_utxo.clone().unwrap();
+        finalize_release(&escrow, ReleasePath::Timeout, &mut psbt).unwrap();
+        let verified = interpreter::verify_input(&psbt.extract_tx(), 0, &[spent]);
+        assert!(matches!(verified, Err(VerifyError::Failed(0, _))));
+
+        let escrow = escrow_script::escrow_scripts(EscrowTemplate::A, &parties(), None, Network::Bitcoin).unwrap();
+        release(&escrow, ReleasePath::Cooperative, &[Role::Party2, Role::Party1]).unwrap();
+        assert_eq!(release(&escrow, ReleasePath::Timeout, &[]).unwrap_err(), ReleaseError::PathUnavailable(ReleasePath::Timeout));
+    }
+
+    #[test]
+    fn test_taproot_paths_release() {
+        let preimage = [42u8; 32];
+        let hashlock = Hashlock { hash: sha256::Hash::hash(&preimage).to_hex(), recipient: Role::Party2 };
+        let timelock = TimelockPath { timelock: Timelock::Height(900_000), recipient: Role::Party1 };
+        let escrow = escrow_script::taproot_escrow(EscrowTemplate::C, &parties(), Some(timelock), Some(hashlock), false, Network::Bitcoin).unwrap();
+
+        let tx = release(&escrow, ReleasePath::Cooperative, &[Role::Party1, Role::Party2]).unwrap().extract_tx();
+        // Two signatures, the leaf script and its control block
+        assert_eq!(tx.input[0].witness.len(), 4);
+        release(&escrow, ReleasePath::Arbitrated, &[Role::Party2, Role::Arbitrator]).unwrap();
+
+        let tx = release(&escrow, ReleasePath::Timeout, &[Role::Party1]).unwrap().extract_tx();
+        assert_eq!((tx.lock_time, tx.input[0].sequence), (PackedLockTime(900_000), Sequence::ENABLE_LOCKTIME_NO_RBF));
+
+        assert_eq!(release(&escrow, ReleasePath::Hashlock, &[Role::Party2]).unwrap_err(), ReleaseError::MissingPreimage);
+        let outpoint = OutPoint { txid: Txid::from_str(&"ab".repeat(32)).unwrap(), vout: 0 };
+        let mut psbt = build_release(&escrow, ReleasePath::Hashlock, outpoint, 100_000, &payouts(), 500, Network::Bitcoin).unwrap();
+        LocalSigner::from_nostr(&secret(Role::Party2)).sign_psbt(&mut psbt).unwrap();
+        psbt.inputs[0].sha256_preimages.insert(sha256::Hash::hash(&preimage), preimage.to_vec());
+        finalize_release(&escrow, ReleasePath::Hashlock, &mut psbt).unwrap();
+        assert_eq!(psbt.extract_tx().input[0].witness.to_vec()[1], preimage);
+
+        // With MuSig2 the parties cooperate through the key path. Holding both secrets, the test
+        // signs with the aggregate secret instead of running the two-round protocol.
+        let escrow = escrow_script::taproot_escrow(EscrowTemplate::A, &parties(), None, None, true, Network::Bitcoin).unwrap();
+        let secrets = [secret(Role::Party1).segwit_v0_secret(), secret(Role::Party2).segwit_v0_secret()];
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let mut keyed: Vec<_> = secrets.iter().map(|secret| (secret.public_key(&secp), *secret)).collect();
+        keyed.sort_by_key(|(key, _)| key.serialize());
+        let keys: Vec<_> = keyed.iter().map(|(key, _)| *key).collect();
+        let aggregate = keyed.iter().zip(taproot::musig_coefficients(&keys))
+            .map(|((_, secret), coefficient)| secret.mul_tweak(&coefficient).unwrap())
+            .reduce(|sum, term| sum.add_tweak(&term.into()).unwrap())
+            .unwrap();
+        assert_eq!(aggregate.x_only_public_key(&secp).0, escrow.taproot().unwrap().internal_key());
+        let mut psbt = build_release(&escrow, ReleasePath::Cooperative, outpoint, 100_000, &payouts(), 500, Network::Bitcoin).unwrap();
+        // Signatures from each party alone cannot spend through the aggregate key
+        LocalSigner::from_nostr(&secret(Role::Party1)).sign_psbt(&mut psbt).unwrap();
+        assert_eq!(finalize_release(&escrow, ReleasePath::Cooperative, &mut psbt.clone()).unwrap_err(), ReleaseError::MissingMuSig2Signature);
+        assert_eq!(LocalSigner::new(aggregate).sign_psbt(&mut psbt).unwrap(), 1);
+        finalize_release(&escrow, ReleasePath::Cooperative, &mut psbt).unwrap();
+        assert_eq!(psbt.extract_tx().input[0].witness.len(), 1);
+    }
+
+    #[test]
+    fn test_payouts_are_checked() {
+        let escrow = escrow_script::escrow_scripts(EscrowTemplate::A, &parties(), None, Network::Bitcoin).unwrap();
+        let build = |payouts: &[Payout], fee| build_release(&escrow, ReleasePath::Cooperative, OutPoint::default(), 100_000, payouts, fee, Network::Bitcoin);
+
+        let mut fixed = payouts();
+        fixed[1].amount = Some(60_000);
+        assert!(matches!(build(&fixed, 500), Err(ReleaseError::InvalidPayouts(reason)) if reason.starts_with("9500 sats are unassigned")));
+        fixed[1].amount = Some(69_500);
+        assert_eq!(build(&fixed, 500).unwrap().unsigned_tx.output[1].value, 69_500);
+
+        let mut remainders = payouts();
+        remainders[0].amount = None;
+        assert!(matches!(build(&remainders, 500), Err(ReleaseError::InvalidPayouts(_))));
+        assert!(matches!(build(&payouts(), 70_000), Err(ReleaseError::InvalidPayouts(_))));
+        assert_eq!(build(&payouts(), 69_800).unwrap_err(), ReleaseError::Dust(payouts()[1].address.to_string(), 200, 294));
+        assert_eq!(build(&payouts(), 20_000_000).unwrap_err(), ReleaseError::AbsurdFee(20_000_000));
+
+        let mut testnet = payouts();
+        testnet[0].address.network = Network::Testnet;
+        assert!(matches!(build(&testnet, 500), Err(ReleaseError::WrongNetwork(_, Network::Bitcoin))));
+        // tb1 addresses parse as testnet but are signet's too
+        testnet[1].address.network = Network::Testnet;
+        let signet = build_release(&escrow, ReleasePath::Cooperative, OutPoint::default(), 100_000, &testnet, 500, Network::Signet);
+        assert_eq!(signet.unwrap().unsigned_tx.output[0].script_pubkey, testnet[0].address.script_pubkey());
+    }
+}
@@ -0,0 +1,169 @@
+use base64::Engine;
+use bitcoin::consensus::encode::serialize_hex;
+use bitcoin::{Network, Transaction, Txid};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum RpcError {
+    #[error("Cannot reach the Bitcoin node: {0}")]
+    Transport(String),
+    #[error("Bitcoin node rejected {method}: {message} (code {code})")]
+    Rejected { method: String, code: i64, message: String },
+    #[error("Unexpected response from the Bitcoin node: {0}")]
+    InvalidResponse(String),
+    #[error("The Bitcoin node is on the {chain} chain, but the server is configured for {expected}")]
+    WrongNetwork { expected: Network, chain: String },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RpcConfig {
+    pub url: String,
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error: Option<RpcErrorBody>,
+}
+
+#[derive(Deserialize)]
+struct RpcErrorBody {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct BlockchainInfo {
+    chain: String,
+    blocks: u64,
+}
+
+// The chain name getblockchaininfo reports for each network
+fn chain_name(network: Network) -> &'static str {
+    match network {
+        Network::Bitcoin => "main",
+        Network::Testnet => "test",
+        Network::Signet => "signet",
+        Network::Regtest => "regtest",
+    }
+}
+
+// A minimal bitcoind JSON-RPC client covering the calls the server makes
+#[derive(Clone)]
+pub struct BitcoinRpc {
+    url: String,
+    authorization: Option<String>,
+    agent: ureq::Agent,
+    network: Network,
+}
+
+impl BitcoinRpc {
+    // `network` is the one the server is configured for; the node must be on it too
+    pub fn new(config: &RpcConfig, network: Network) -> Self {
+        let authorization = config.user.as_ref().map(|user| {
+            let credentials = format!("{}:{}", user, config.password.as_deref().unwrap_or_default());
+            format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(credentials))
+        });
+
+        Self {
+            url: config.url.clone(),
+            authorization,
+            agent: ureq::AgentBuilder::new().timeout(Duration::from_secs(30)).build(),
+            network,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn call<T: DeserializeOwned>(&self, method: &str, params: serde_json::Value) -> Result<T, RpcError> {
+        let mut request = self.agent.post(&self.url);
+        if let Some(authorization) = &self.authorization {
+            request = request.set("Authorization", authorization);
+        }
+
+        let body = serde_json::json!({ "jsonrpc": "1.0", "id": "will", "method": method, "params": params });
+        let response = match request.send_json(body) {
+            Ok(response) => response,
+            // bitcoind reports RPC errors with a 4xx/5xx status and a JSON body
+            Err(ureq::Error::Status(_, response)) if response.content_type() == "application/json" => response,
+            Err(e) => return Err(RpcError::Transport(e.to_string())),
+        };
+
+        let response: RpcResponse<T> = response.into_json()
+            .map_err(|e| RpcError::InvalidResponse(e.to_string()))?;
+
+        match (response.result, response.error) {
+            (_, Some(error)) => Err(RpcError::Rejected { method: method.to_string(), code: error.code, message: error.message }),
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(RpcError::InvalidResponse(format!("{} returned no result", method))),
+        }
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    // The node's block height, once it is confirmed to be on the configured network
+    pub fn check_network(&self) -> Result<u64, RpcError> {
+        let info: BlockchainInfo = self.call("getblockchaininfo", serde_json::json!([]))?;
+        if info.chain != chain_name(self.network) {
+            return Err(RpcError::WrongNetwork { expected: self.network, chain: info.chain });
+        }
+        Ok(info.blocks)
+    }
+
+    pub fn send_raw_transaction(&self, tx: &Transaction) -> Result<Txid, RpcError> {
+        let txid: String = self.call("sendrawtransaction", serde_json::json!([serialize_hex(tx)]))?;
+        Txid::from_str(&txid).map_err(|e| RpcError::InvalidResponse(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::spawn_http;
+
+    #[test]
+    fn test_call_sends_credentials_and_reads_result() {
+        let (url, node) = spawn_http("/", "200 OK", r#"{"result":{"chain":"signet","blocks":42},"error":null,"id":"will"}"#);
+        let rpc = BitcoinRpc::new(&RpcConfig { url, user: Some("alice".into()), password: Some("secret".into()) }, Network::Signet);
+
+        assert_eq!(rpc.check_network().unwrap(), 42);
+
+        let (headers, body) = node.join().unwrap();
+        assert!(headers.contains("Basic YWxpY2U6c2VjcmV0"));
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["method"], "getblockchaininfo");
+    }
+
+    #[test]
+    fn test_rejection_carries_node_error() {
+        let (url, node) = spawn_http("/", "500 Internal Server Error", r#"{"result":null,"error":{"code":-26,"message":"bad-txns"},"id":"will"}"#);
+        let rpc = BitcoinRpc::new(&RpcConfig { url, user: None, password: None }, Network::Bitcoin);
+
+        let result = rpc.call::<String>("sendrawtransaction", serde_json::json!(["00"]));
+        assert!(matches!(result, Err(RpcError::Rejected { code: -26, .. })));
+        node.join().unwrap();
+    }
+
+    #[test]
+    fn test_node_on_another_chain_is_refused() {
+        let (url, node) = spawn_http("/", "200 OK", r#"{"result":{"chain":"test","blocks":42},"error":null,"id":"will"}"#);
+        let rpc = BitcoinRpc::new(&RpcConfig { url, user: None, password: None }, Network::Bitcoin);
+
+        let result = rpc.check_network();
+        assert!(matches!(result, Err(RpcError::WrongNetwork { expected: Network::Bitcoin, ref chain }) if chain == "test"));
+        node.join().unwrap();
+    }
+}
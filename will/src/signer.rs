@@ -0,0 +1,275 @@
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::schnorr::{SchnorrSig, TapTweak};
+use bitcoin::secp256k1::{All, KeyPair, Message, Secp256k1, SecretKey, XOnlyPublicKey};
+use bitcoin::util::sighash::{Prevouts, SighashCache};
+use bitcoin::util::taproot::{LeafVersion, TapLeafHash};
+use bitcoin::{EcdsaSig, EcdsaSighashType, PublicKey, SchnorrSighashType, Script, Transaction, TxOut};
+use thiserror::Error;
+use crate::nostr::NostrSecretKey;
+use crate::psbt::Psbt;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SignerError {
+    #[error("Input {0} is missing the output it spends")]
+    MissingUtxo(usize),
+    #[error("Input {0}: {1}")]
+    Sighash(usize, String),
+}
+
+// Signs PSBT inputs in-process, for regtest, automation and hot-wallet use. It signs every input it
+// holds the key for: P2WPKH and P2WSH with BIP-143 sighashes, taproot key and script paths with
+// BIP-341 ones. Each input is signed with the PSBT's sighash type, defaulting to ALL (or DEFAULT
+// for taproot). Finalizing the signed PSBT is left to psbt::finalize or the spending path.
+pub struct LocalSigner {
+    keypair: KeyPair,
+    secp: Secp256k1<All>,
+}
+
+impl LocalSigner {
+    pub fn new(secret: SecretKey) -> Self {
+        let secp = Secp256k1::new();
+        Self { keypair: KeyPair::from_secret_key(&secp, &secret), secp }
+    }
+
+    // Escrows use a Nostr key's even-parity point for segwit v0, so sign with the matching secret
+    pub fn from_nostr(key: &NostrSecretKey) -> Self {
+        Self::new(key.segwit_v0_secret())
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::new(self.keypair.public_key())
+    }
+
+    pub fn x_only_public_key(&self) -> XOnlyPublicKey {
+        self.keypair.x_only_public_key().0
+    }
+
+    // Adds this key's signatures to every input it can sign, returning how many it added
+    pub fn sign_psbt(&self, psbt: &mut Psbt) -> Result<usize, SignerError> {
+        let spent: Vec<Option<TxOut>> = (0..psbt.inputs.len()).map(|i| spent_output(psbt, i)).collect();
+        let mut cache = SighashCache::new(&psbt.unsigned_tx);
+        let mut signed = 0;
+
+        for (index, input) in psbt.inputs.iter_mut().enumerate() {
+            let utxo = spent[index].as_ref().ok_or(SignerError::MissingUtxo(index))?;
+            let script_pubkey = &utxo.script_pubkey;
+            let sighash_error = |e: &dyn ToString| SignerError::Sighash(index, e.to_string());
+
+            if script_pubkey.is_v0_p2wpkh() || script_pubkey.is_v0_p2wsh() {
+                let public_key = self.public_key();
+                let script_code = if script_pubkey.is_v0_p2wpkh() {
+                    let key_hash = public_key.wpubkey_hash().expect("the key is compressed");
+                    if Script::new_v0_p2wpkh(&key_hash) != *script_pubkey {
+                        continue;
+                    }
+                    Script::new_p2pkh(&public_key.pubkey_hash())
+                } else {
+                    match &input.witness_script {
+                        Some(script) if script.to_v0_p2wsh() == *script_pubkey && pushes(script, &public_key.to_bytes()) => script.clone(),
+                        _ => continue,
+                    }
+                };
+
+                let hash_ty = input.ecdsa_hash_ty().map_err(|e| sighash_error(&e))?;
+                let sighash = cache.segwit_signature_hash(index, &script_code, utxo.value, hash_ty)
+                    .map_err(|e| sighash_error(&e))?;
+                input.partial_sigs.insert(public_key, self.sign_ecdsa(&sighash[..], hash_ty));
+                signed += 1;
+                continue;
+            }
+
+            if !script_pubkey.is_v1_p2tr() {
+                continue;
+            }
+            let hash_ty = input.schnorr_hash_ty().map_err(|e| sighash_error(&e))?;
+            let x_only = self.x_only_public_key();
+            // BIP-341 commits to every spent output unless the input signs with ANYONECANPAY
+            let all_spent: Option<Vec<TxOut>> = spent.iter().cloned().collect();
+            let anyone_can_pay = matches!(
+                hash_ty,
+                SchnorrSighashType::AllPlusAnyoneCanPay | SchnorrSighashType::NonePlusAnyoneCanPay | SchnorrSighashType::SinglePlusAnyoneCanPay
+            );
+            let prevouts = match (&all_spent, anyone_can_pay) {
+                (_, true) => Prevouts::One(index, utxo.clone()),
+                (Some(all), false) => Prevouts::All(all),
+                (None, false) => {
+                    let missing = spent.iter().position(Option::is_none).expect("some output is missing");
+                    return Err(SignerError::MissingUtxo(missing));
+                }
+            };
+
+            if input.tap_internal_key == Some(x_only) {
+                let tweaked = self.keypair.tap_tweak(&self.secp, input.tap_merkle_root);
+                if tweaked.to_inner().x_only_public_key().0.serialize() == script_pubkey.as_bytes()[2..] {
+                    let sighash = cache.taproot_key_spend_signature_hash(index, &prevouts, hash_ty)
+                        .map_err(|e| sighash_error(&e))?;
+                    input.tap_key_sig = Some(self.sign_schnorr(&sighash[..], &tweaked.to_inner(), hash_ty));
+                    signed += 1;
+                }
+            }
+
+            let leaves: Vec<TapLeafHash> = input.tap_scripts.values()
+                .filter(|(script, version)| *version == LeafVersion::TapScript && pushes(script, &x_only.serialize()))
+                .map(|(script, version)| TapLeafHash::from_script(script, *version))
+                .collect();
+            for leaf_hash in leaves {
+                let sighash = cache.taproot_script_spend_signature_hash(index, &prevouts, leaf_hash, hash_ty)
+                    .map_err(|e| sighash_error(&e))?;
+                input.tap_script_sigs.insert((x_only, leaf_hash), self.sign_schnorr(&sighash[..], &self.keypair, hash_ty));
+                signed += 1;
+            }
+        }
+
+        Ok(signed)
+    }
+
+    fn sign_ecdsa(&self, sighash: &[u8], hash_ty: EcdsaSighashType) -> EcdsaSig {
+        let message = Message::from_slice(sighash).expect("sighashes are 32 bytes");
+        EcdsaSig { sig: self.secp.sign_ecdsa_low_r(&message, &self.keypair.secret_key()), hash_ty }
+    }
+
+    // BIP-340 nonces stay safe without auxiliary randomness, which only hardens against side channels
+    fn sign_schnorr(&self, sighash: &[u8], keypair: &KeyPair, hash_ty: SchnorrSighashType) -> SchnorrSig {
+        let message = Message::from_slice(sighash).expect("sighashes are 32 bytes");
+        SchnorrSig { sig: self.secp.sign_schnorr_no_aux_rand(&message, keypair), hash_ty }
+    }
+}
+
+fn spent_output(psbt: &Psbt, index: usize) -> Option<TxOut> {
+    let input = &psbt.inputs[index];
+    input.witness_utxo.clone().or_else(|| {
+        let vout = psbt.unsigned_tx.input[index].previous_output.vout as usize;
+        input.non_witness_utxo.as_ref().and_then(|prev| prev.output.get(vout).cloned())
+    })
+}
+
+fn pushes(script: &Script, key: &[u8]) -> bool {
+    script.instructions().any(|instruction| matches!(instruction, Ok(Instruction::PushBytes(bytes)) if bytes == key))
+}
+
+// The transaction a fully signed and finalized PSBT becomes, with the outputs it spends
+pub fn spent_outputs(psbt: &Psbt) -> Option<(Transaction, Vec<TxOut>)> {
+    let spent = (0..psbt.inputs.len()).map(|i| spent_output(psbt, i)).collect::<Option<Vec<_>>>()?;
+    Some((psbt.clone().extract_tx(), spent))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{verify_input, VerifyError};
+    use crate::psbt;
+    use crate::taproot::TaprootTree;
+    use bitcoin::blockdata::opcodes::all::{OP_CHECKMULTISIG, OP_CHECKSIG};
+    use bitcoin::blockdata::script::Builder;
+    use bitcoin::consensus::encode::deserialize;
+    use bitcoin::hashes::hex::{FromHex, ToHex};
+    use bitcoin::psbt::PsbtSighashType;
+    use bitcoin::{OutPoint, PackedLockTime, Sequence, TxIn, Witness};
+
+    fn signer(byte: u8) -> LocalSigner {
+        LocalSigner::new(SecretKey::from_slice(&[byte; 32]).unwrap())
+    }
+
+    // Spends one output of each given script, paying 1000 sats less to a P2WPKH output
+    fn spending(outputs: &[Script]) -> Psbt {
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: (0..outputs.len() as u32)
+                .map(|vout| TxIn { previous_output: OutPoint { vout, ..Default::default() }, script_sig: Script::new(), sequence: Sequence::MAX, witness: Witness::new() })
+                .collect(),
+            output: vec![
+                TxOut { value: 50_000, script_pubkey: Script::new_v0_p2wpkh(&signer(9).public_key().wpubkey_hash().unwrap()) },
+                TxOut { value: 9_000 * outputs.len() as u64, script_pubkey: Script::new_v0_p2wpkh(&signer(8).public_key().wpubkey_hash().unwrap()) },
+            ],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        for (input, script_pubkey) in psbt.inputs.iter_mut().zip(outputs) {
+            input.witness_utxo = Some(TxOut { value: 60_000, script_pubkey: script_pubkey.clone() });
+        }
+        psbt
+    }
+
+    fn verify_all(psbt: &Psbt) -> Result<(), VerifyError> {
+        let (tx, spent) = spent_outputs(psbt).unwrap();
+        (0..tx.input.len()).try_for_each(|i| verify_input(&tx, i, &spent))
+    }
+
+    #[test]
+    fn test_bip143_sighash_vector() {
+        // The native P2WPKH example from BIP-143
+        let tx: Transaction = deserialize(&Vec::<u8>::from_hex(
+            "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000",
+        ).unwrap()).unwrap();
+        let script_code = Script::from_hex("76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac").unwrap();
+        let sighash = SighashCache::new(&tx).segwit_signature_hash(1, &script_code, 600_000_000, EcdsaSighashType::All).unwrap();
+        assert_eq!(sighash[..].to_hex(), "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670");
+    }
+
+    #[test]
+    fn test_segwit_v0_inputs_sign_and_verify() {
+        let keys: Vec<PublicKey> = (1..=3).map(|i| signer(i).public_key()).collect();
+        let multisig = Builder::new().push_int(2)
+            .push_key(&keys[0]).push_key(&keys[1]).push_key(&keys[2])
+            .push_int(3).push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        let mut psbt = spending(&[Script::new_v0_p2wpkh(&keys[0].wpubkey_hash().unwrap()), multisig.to_v0_p2wsh()]);
+        psbt.inputs[1].witness_script = Some(multisig);
+        // The multisig input signs only itself and its own output, so others can add inputs and outputs
+        psbt.inputs[1].sighash_type = Some(PsbtSighashType::from(EcdsaSighashType::SinglePlusAnyoneCanPay));
+
+        assert_eq!(signer(1).sign_psbt(&mut psbt).unwrap(), 2);
+        assert_eq!(signer(3).sign_psbt(&mut psbt).unwrap(), 1);
+        assert_eq!(signer(7).sign_psbt(&mut psbt).unwrap(), 0);
+        assert_eq!(psbt.inputs[1].partial_sigs[&keys[2]].hash_ty, EcdsaSighashType::SinglePlusAnyoneCanPay);
+
+        psbt::finalize(&mut psbt).unwrap();
+        verify_all(&psbt).unwrap();
+
+        // SINGLE|ANYONECANPAY leaves the first output uncommitted; ALL does not
+        let mut changed = psbt.clone();
+        changed.unsigned_tx.output[0].value -= 1;
+        let (tx, spent) = spent_outputs(&changed).unwrap();
+        assert!(matches!(verify_input(&tx, 0, &spent), Err(VerifyError::Failed(0, _))));
+        verify_input(&tx, 1, &spent).unwrap();
+    }
+
+    #[test]
+    fn test_taproot_key_and_script_paths_sign_and_verify() {
+        let (owner, cosigner) = (signer(4), signer(5));
+        let leaf = Builder::new().push_x_only_key(&cosigner.x_only_public_key()).push_opcode(OP_CHECKSIG).into_script();
+        let tree = TaprootTree::weighted(owner.x_only_public_key(), vec![(1, leaf.clone())]).unwrap();
+        let script_pubkey = tree.address(bitcoin::Network::Regtest).script_pubkey();
+
+        let mut psbt = spending(&[script_pubkey.clone(), script_pubkey]);
+        for input in &mut psbt.inputs {
+            input.tap_internal_key = Some(tree.internal_key());
+            input.tap_merkle_root = tree.spend_info.merkle_root();
+            input.tap_scripts.insert(tree.control_block(&leaf).unwrap(), (leaf.clone(), LeafVersion::TapScript));
+        }
+        psbt.inputs[1].sighash_type = Some(PsbtSighashType::from(SchnorrSighashType::NonePlusAnyoneCanPay));
+
+        assert_eq!(owner.sign_psbt(&mut psbt).unwrap(), 2);
+        assert_eq!(cosigner.sign_psbt(&mut psbt).unwrap(), 2);
+
+        // Input 0 through the key path, input 1 through the leaf
+        let leaf_sig = psbt.inputs[1].tap_script_sigs.values().next().unwrap().to_vec();
+        assert_eq!(leaf_sig.len(), 65);
+        let control_block = tree.control_block(&leaf).unwrap().serialize();
+        psbt.inputs[1].final_script_witness = Some(Witness::from_vec(vec![leaf_sig, leaf.to_bytes(), control_block]));
+        psbt::finalize(&mut psbt).unwrap();
+        assert_eq!(psbt.inputs[0].final_script_witness.as_ref().unwrap().len(), 1);
+        verify_all(&psbt).unwrap();
+
+        // DEFAULT commits to every output
+        let mut changed = psbt.clone();
+        changed.unsigned_tx.output[1].value -= 1;
+        let (tx, spent) = spent_outputs(&changed).unwrap();
+        assert!(matches!(verify_input(&tx, 0, &spent), Err(VerifyError::Failed(0, _))));
+        verify_input(&tx, 1, &spent).unwrap();
+
+        let mut unsigned = spending(&[Script::new()]);
+        unsigned.inputs[0].witness_utxo = None;
+        assert_eq!(owner.sign_psbt(&mut unsigned).unwrap_err(), SignerError::MissingUtxo(0));
+    }
+}
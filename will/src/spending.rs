@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use thiserror::Error;
 use std::collections::HashMap;
+use crate::budget::BudgetCaps;
+use crate::engine::ProjectionEngine;
 
 #[derive(Debug, Error)]
 pub enum SpendingError {
@@ -10,9 +13,13 @@ pub enum SpendingError {
     FormulaError(String),
     #[error("Invalid projection years: {0}")]
     InvalidYears(String),
+    #[error("Invalid budget: {0}")]
+    InvalidBudget(String),
+    #[error("Invalid scenario: {0}")]
+    InvalidScenario(String),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ProjectSpend {
     pub project_name: String,
     pub daily_spend: f64,
@@ -20,18 +27,76 @@ pub struct ProjectSpend {
     pub growth_type: GrowthType,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub enum GrowthType {
     Compound,
     Flat,
     Custom(String),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// A named what-if adjustment applied to every project before projecting
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Scenario {
+    pub name: String,
+    #[serde(default)]
+    pub growth_rate_delta: f64,  // added to each project's growth rate
+    #[serde(default = "default_multiplier")]
+    pub spend_multiplier: f64,  // applied to each project's daily spend
+    #[serde(default)]
+    pub inflation_rate: f64,  // used for real-terms projections
+}
+
+fn default_multiplier() -> f64 {
+    1.0
+}
+
+fn default_currency() -> String {
+    "USD".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct UserModel {
     pub user_id: String,
     pub projects: Vec<ProjectSpend>,
     pub projection_years: u32,
+    #[serde(default)]
+    pub budget: BudgetCaps,
+    #[serde(default = "default_currency")]
+    pub currency: String,
+    #[serde(default)]
+    pub scenarios: Vec<Scenario>,
+}
+
+impl Scenario {
+    pub fn validate(&self) -> Result<(), SpendingError> {
+        if self.name.trim().is_empty() {
+            return Err(SpendingError::InvalidScenario("Scenario name cannot be empty".into()));
+        }
+        if !self.spend_multiplier.is_finite() || self.spend_multiplier < 0.0 {
+            return Err(SpendingError::InvalidScenario("Spend multiplier must be a non-negative number".into()));
+        }
+        if !self.growth_rate_delta.is_finite() {
+            return Err(SpendingError::InvalidScenario("Growth rate delta must be a number".into()));
+        }
+        if !self.inflation_rate.is_finite() || self.inflation_rate <= -1.0 {
+            return Err(SpendingError::InvalidScenario("Inflation rate must be greater than -100%".into()));
+        }
+        Ok(())
+    }
+
+    // Returns a copy of the model with this scenario's adjustments applied to every project
+    pub fn apply(&self, model: &UserModel) -> Result<UserModel, SpendingError> {
+        let mut adjusted = model.clone();
+        adjusted.projects = model.projects.iter()
+            .map(|project| ProjectSpend::new(
+                project.project_name.clone(),
+                project.daily_spend * self.spend_multiplier,
+                project.growth_rate + self.growth_rate_delta,
+                project.growth_type.clone(),
+            ))
+            .collect::<Result<_, _>>()?;
+        Ok(adjusted)
+    }
 }
 
 impl ProjectSpend {
@@ -58,19 +123,22 @@ impl ProjectSpend {
             GrowthType::Flat => {
                 Ok(yearly_base * (1.0 + (self.growth_rate * year as f64)))
             }
-            GrowthType::Custom(formula) => {
-                let mut engine = rhai::Engine::new();
-                let mut scope = rhai::Scope::new();
-                
-                scope.push("base", yearly_base);
-                scope.push("rate", self.growth_rate);
-                scope.push("year", year as i64);
-                
-                engine.eval_with_scope::<f64>(&mut scope, formula)
-                    .map_err(|e| SpendingError::FormulaError(e.to_string()))
+            GrowthType::Custom(_) => {
+                ProjectionEngine::shared().yearly_spend(self, year)
             }
         }
     }
+
+    // Variables available to custom growth formulas
+    pub(crate) fn formula_scope(&self, year: u32) -> rhai::Scope<'static> {
+        let mut scope = rhai::Scope::new();
+        
+        scope.push("base", self.daily_spend * 365.0);
+        scope.push("rate", self.growth_rate);
+        scope.push("year", year as i64);
+        
+        scope
+    }
 }
 
 impl UserModel {
@@ -83,26 +151,61 @@ impl UserModel {
             user_id,
             projects: Vec::new(),
             projection_years,
+            budget: BudgetCaps::default(),
+            currency: default_currency(),
+            scenarios: Vec::new(),
         })
     }
 
+    // Checks a model that arrived whole, e.g. deserialized from a request, rather than via new()
+    pub fn validate(&self) -> Result<(), SpendingError> {
+        if self.projection_years == 0 {
+            return Err(SpendingError::InvalidYears("Projection years must be greater than 0".into()));
+        }
+        for project in &self.projects {
+            ProjectSpend::new(
+                project.project_name.clone(),
+                project.daily_spend,
+                project.growth_rate,
+                project.growth_type.clone(),
+            )?;
+        }
+        for scenario in &self.scenarios {
+            scenario.validate()?;
+        }
+        self.budget.validate()
+    }
+
+    pub fn scenario(&self, name: &str) -> Option<&Scenario> {
+        self.scenarios.iter().find(|scenario| scenario.name == name)
+    }
+
+    // Adds the scenario, replacing any existing one with the same name
+    pub fn set_scenario(&mut self, scenario: Scenario) -> Result<(), SpendingError> {
+        scenario.validate()?;
+        match self.scenarios.iter_mut().find(|s| s.name == scenario.name) {
+            Some(existing) => *existing = scenario,
+            None => self.scenarios.push(scenario),
+        }
+        Ok(())
+    }
+
+    pub fn remove_scenario(&mut self, name: &str) -> Option<Scenario> {
+        let index = self.scenarios.iter().position(|scenario| scenario.name == name)?;
+        Some(self.scenarios.remove(index))
+    }
+
     pub fn add_project(&mut self, project: ProjectSpend) {
         self.projects.push(project);
     }
 
+    pub fn set_budget(&mut self, budget: BudgetCaps) -> Result<(), SpendingError> {
+        budget.validate()?;
+        self.budget = budget;
+        Ok(())
+    }
+
     pub fn calculate_total_spend(&self) -> Result<HashMap<u32, f64>, SpendingError> {
-        let mut yearly_totals = HashMap::new();
-        
-        for year in 0..self.projection_years {
-            let mut year_total = 0.0;
-            
-            for project in &self.projects {
-                year_total += project.calculate_yearly_spend(year)?;
-            }
-            
-            yearly_totals.insert(year, year_total);
-        }
-        
-        Ok(yearly_totals)
+        ProjectionEngine::shared().total_spend(self)
     }
 } 
\ No newline at end of file
@@ -0,0 +1,90 @@
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use crate::history::{ModelChange, ModelEvent};
+use crate::spending::{SpendingError, UserModel};
+
+// A user's current model together with the changes that produced it
+struct UserRecord {
+    current: UserModel,
+    events: Vec<ModelEvent>,
+}
+
+impl UserRecord {
+    fn record(&mut self, change: ModelChange) -> u64 {
+        let version = self.events.len() as u64 + 1;
+        self.events.push(ModelEvent { version, timestamp: Utc::now(), change });
+        version
+    }
+}
+
+// The model before and after a change, and the version the change created
+pub struct Revision {
+    pub version: u64,
+    pub before: UserModel,
+    pub after: UserModel,
+}
+
+// In-memory user storage shared by the web handlers. Every change is kept as an event,
+// so earlier versions of a model can be rebuilt.
+#[derive(Default)]
+pub struct UserStore {
+    users: RwLock<HashMap<String, UserRecord>>,
+}
+
+impl UserStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Stores a new user; false, storing nothing, when the user_id is already taken
+    pub fn insert(&self, user: UserModel) -> bool {
+        let mut users = self.users.write().unwrap();
+        if users.contains_key(&user.user_id) {
+            return false;
+        }
+
+        let mut record = UserRecord { current: user, events: Vec::new() };
+        record.record(ModelChange::Created { model: record.current.clone() });
+        users.insert(record.current.user_id.clone(), record);
+        true
+    }
+
+    pub fn get(&self, user_id: &str) -> Option<UserModel> {
+        self.users.read().unwrap().get(user_id).map(|record| record.current.clone())
+    }
+
+    // False once a writer has panicked while holding the lock
+    pub fn is_available(&self) -> bool {
+        !self.users.is_poisoned()
+    }
+
+    // Applies `change` to a copy of the user and stores the result once `check` accepts the
+    // before and after models. Returns None when the user does not exist.
+    pub fn apply<E: From<SpendingError>>(
+        &self,
+        user_id: &str,
+        change: ModelChange,
+        check: impl FnOnce(&UserModel, &UserModel) -> Result<(), E>,
+    ) -> Option<Result<Revision, E>> {
+        let mut users = self.users.write().unwrap();
+        let record = users.get_mut(user_id)?;
+
+        let mut updated = record.current.clone();
+        if let Err(e) = change.apply(&mut updated) {
+            return Some(Err(e.into()));
+        }
+        if let Err(e) = check(&record.current, &updated) {
+            return Some(Err(e));
+        }
+
+        let version = record.record(change);
+        let before = std::mem::replace(&mut record.current, updated.clone());
+        Some(Ok(Revision { version, before, after: updated }))
+    }
+
+    // Runs `f` over the user's changes, oldest first
+    pub fn with_history<T>(&self, user_id: &str, f: impl FnOnce(&[ModelEvent]) -> T) -> Option<T> {
+        self.users.read().unwrap().get(user_id).map(|record| f(&record.events))
+    }
+}
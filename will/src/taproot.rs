@@ -0,0 +1,275 @@
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::constants::CURVE_ORDER;
+use bitcoin::secp256k1::{PublicKey, Scalar, Secp256k1, XOnlyPublicKey};
+use bitcoin::psbt::TapTree;
+use bitcoin::util::taproot::{ControlBlock, LeafVersion, TapLeafHash, TaprootBuilder, TaprootBuilderError, TaprootSpendInfo};
+use bitcoin::{Address, Network, Script};
+use serde::Serialize;
+use std::str::FromStr;
+use thiserror::Error;
+use utoipa::ToSchema;
+
+// The point H suggested by BIP-341: lift_x(sha256(G)), using the uncompressed generator. Nobody
+// knows its discrete log, so an output with it as internal key can only be spent by its scripts.
+pub const NUMS_KEY: &str = "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
+
+pub fn nums_key() -> XOnlyPublicKey {
+    XOnlyPublicKey::from_str(NUMS_KEY).expect("the NUMS point is a valid x-only key")
+}
+
+fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag);
+    engine.input(&tag);
+    for data in data {
+        engine.input(data);
+    }
+    sha256::Hash::from_engine(engine).into_inner()
+}
+
+// BIP-327 KeyAgg coefficients, in the order of `keys`. The second distinct key gets 1.
+pub fn musig_coefficients(keys: &[PublicKey]) -> Vec<Scalar> {
+    let list: Vec<u8> = keys.iter().flat_map(PublicKey::serialize).collect();
+    let list_hash = tagged_hash("KeyAgg list", &[&list]);
+    let second = keys.iter().find(|key| **key != keys[0]);
+    keys.iter()
+        .map(|key| match Some(key) == second {
+            true => Scalar::ONE,
+            false => scalar_mod_n(tagged_hash("KeyAgg coefficient", &[&list_hash, &key.serialize()])),
+        })
+        .collect()
+}
+
+// A 256-bit hash as a scalar, reduced mod the curve order n. As 2^256 < 2n, subtracting n once is enough.
+fn scalar_mod_n(hash: [u8; 32]) -> Scalar {
+    if let Ok(scalar) = Scalar::from_be_bytes(hash) {
+        return scalar;
+    }
+    let mut reduced = hash;
+    let mut borrow = 0;
+    for (byte, n) in reduced.iter_mut().zip(CURVE_ORDER).rev() {
+        let difference = i16::from(*byte) - i16::from(n) - borrow;
+        borrow = i16::from(difference < 0);
+        *byte = difference.rem_euclid(256) as u8;
+    }
+    Scalar::from_be_bytes(reduced).expect("2^256 - n is below n")
+}
+
+// BIP-327 KeyAgg: the MuSig2 aggregate of `keys` in the order given. Its holders can only spend
+// through it together, so it is safe as the internal key of a cooperative key path.
+pub fn musig_key_agg(keys: &[PublicKey]) -> XOnlyPublicKey {
+    let secp = Secp256k1::verification_only();
+    let terms: Vec<PublicKey> = keys.iter().zip(musig_coefficients(keys))
+        .map(|(key, coefficient)| key.mul_tweak(&secp, &coefficient).expect("coefficients are non-zero"))
+        .collect();
+    let aggregate = PublicKey::combine_keys(&terms.iter().collect::<Vec<_>>())
+        .expect("distinct keys with hashed coefficients do not cancel out");
+    aggregate.x_only_public_key().0
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum TaprootError {
+    #[error("Invalid taproot tree: {0}")]
+    InvalidTree(String),
+}
+
+// What a script-path spend needs besides the satisfaction: the script and its control block
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct TapLeaf {
+    // Hex
+    pub script: String,
+    pub leaf_version: u8,
+    pub leaf_hash: String,
+    // Hex; the last witness element, after the script
+    pub control_block: String,
+    // Distance from the root; each level adds 32 bytes to the control block
+    pub depth: usize,
+}
+
+// Taproot commitments as the API reports them
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct TaprootSummary {
+    pub internal_key: String,
+    // False for the NUMS key, whose key path nobody can use
+    pub key_path_spendable: bool,
+    // The tweaked key in the output
+    pub output_key: String,
+    pub merkle_root: Option<String>,
+    pub leaves: Vec<TapLeaf>,
+}
+
+// An internal key with an optional tree of tapscript leaves
+#[derive(Debug, Clone)]
+pub struct TaprootTree {
+    pub spend_info: TaprootSpendInfo,
+    // In the order they were given, which is not necessarily their order in the tree
+    pub scripts: Vec<Script>,
+}
+
+impl TaprootTree {
+    // Leaves at explicit depths, in depth-first order, as descriptors write them
+    pub fn with_depths(internal_key: XOnlyPublicKey, leaves: Vec<(u8, Script)>) -> Result<Self, TaprootError> {
+        let secp = Secp256k1::verification_only();
+        if leaves.is_empty() {
+            return Ok(Self { spend_info: TaprootSpendInfo::new_key_spend(&secp, internal_key, None), scripts: Vec::new() });
+        }
+
+        let mut builder = TaprootBuilder::new();
+        for (depth, script) in &leaves {
+            builder = builder.add_leaf(*depth, script.clone())
+                .map_err(|e| TaprootError::InvalidTree(e.to_string()))?;
+        }
+        let spend_info = builder.finalize(&secp, internal_key)
+            .map_err(|_| TaprootError::InvalidTree("the leaves do not form a complete tree".into()))?;
+        Ok(Self { spend_info, scripts: leaves.into_iter().map(|(_, script)| script).collect() })
+    }
+
+    // Leaves weighted by how often they are expected to be used. Likelier leaves sit nearer the
+    // root, so spending through them needs a shorter control block.
+    pub fn weighted(internal_key: XOnlyPublicKey, leaves: Vec<(u32, Script)>) -> Result<Self, TaprootError> {
+        let secp = Secp256k1::verification_only();
+        if leaves.is_empty() {
+            return Ok(Self { spend_info: TaprootSpendInfo::new_key_spend(&secp, internal_key, None), scripts: Vec::new() });
+        }
+
+        let scripts = leaves.iter().map(|(_, script)| script.clone()).collect();
+        let spend_info = TaprootSpendInfo::with_huffman_tree(&secp, internal_key, leaves)
+            .map_err(|e| TaprootError::InvalidTree(e.to_string()))?;
+        Ok(Self { spend_info, scripts })
+    }
+
+    // The tree as a PSBT output's tap_tree (BIP-371), from which signers rebuild the merkle root
+    // and recognise the output; None without leaves
+    pub fn psbt_tap_tree(&self) -> Option<TapTree> {
+        let leaves: Vec<(&Script, &[sha256::Hash])> = self.spend_info.as_script_map().iter()
+            .flat_map(|((script, _), branches)| branches.iter().map(move |branch| (script, branch.as_inner())))
+            .collect();
+        if leaves.is_empty() {
+            return None;
+        }
+        let builder = add_subtree(TaprootBuilder::new(), leaves, 0).expect("the leaves come from a complete tree");
+        Some(TapTree::try_from(builder).expect("the leaves come from a complete tree"))
+    }
+
+    pub fn internal_key(&self) -> XOnlyPublicKey {
+        self.spend_info.internal_key()
+    }
+
+    pub fn key_path_spendable(&self) -> bool {
+        self.internal_key() != nums_key()
+    }
+
+    pub fn address(&self, network: Network) -> Address {
+        Address::p2tr_tweaked(self.spend_info.output_key(), network)
+    }
+
+    pub fn control_block(&self, script: &Script) -> Option<ControlBlock> {
+        self.spend_info.control_block(&(script.clone(), LeafVersion::TapScript))
+    }
+
+    pub fn leaf(&self, index: usize) -> TapLeaf {
+        let script = &self.scripts[index];
+        let control_block = self.control_block(script).expect("every script was added to the tree");
+        TapLeaf {
+            script: script.to_hex(),
+            leaf_version: LeafVersion::TapScript.to_consensus(),
+            leaf_hash: TapLeafHash::from_script(script, LeafVersion::TapScript).to_hex(),
+            depth: control_block.merkle_branch.as_inner().len(),
+            control_block: control_block.serialize().to_hex(),
+        }
+    }
+
+    pub fn summary(&self) -> TaprootSummary {
+        TaprootSummary {
+            internal_key: self.internal_key().to_string(),
+            key_path_spendable: self.key_path_spendable(),
+            output_key: self.spend_info.output_key().to_string(),
+            merkle_root: self.spend_info.merkle_root().map(|root| root.to_hex()),
+            leaves: (0..self.scripts.len()).map(|i| self.leaf(i)).collect(),
+        }
+    }
+}
+
+// Adds the leaves below one node, depth first. A merkle branch lists a leaf's siblings from the
+// leaf up, so the leaves on one side of this node share the hash of the other side.
+fn add_subtree(
+    builder: TaprootBuilder,
+    mut leaves: Vec<(&Script, &[sha256::Hash])>,
+    depth: usize,
+) -> Result<TaprootBuilder, TaprootBuilderError> {
+    match leaves.as_slice() {
+        [] => return Err(TaprootBuilderError::EmptyTree),
+        [(script, branch)] if branch.len() == depth => return builder.add_leaf(depth as u8, (*script).clone()),
+        _ => {}
+    }
+    let other_side = |(_, branch): &(&Script, &[sha256::Hash])| branch.get(branch.len().wrapping_sub(depth + 1)).copied();
+    leaves.sort_by_key(other_side);
+    // Two identical subtrees have the same hash and as many leaves each
+    let split = leaves.iter().position(|leaf| other_side(leaf) != other_side(&leaves[0])).unwrap_or(leaves.len() / 2);
+    let right = leaves.split_off(split);
+    add_subtree(add_subtree(builder, leaves, depth + 1)?, right, depth + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::blockdata::opcodes::all::OP_CHECKSIG;
+    use bitcoin::blockdata::script::Builder;
+
+    #[test]
+    fn test_musig_key_agg_vector() {
+        // From the BIP-327 KeyAgg test vectors
+        let keys: Vec<PublicKey> = [
+            "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9",
+            "03dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+            "023590a94e768f8e1815c2f24b4d80a8e3149316c3518ce7b7ad338368d038ca66",
+        ]
+        .iter()
+        .map(|key| PublicKey::from_str(key).unwrap())
+        .collect();
+        assert_eq!(musig_key_agg(&keys).to_hex(), "90539eede565f5d054f32cc0c220126889ed1e5d193baf15aef344fe59d4610c");
+        assert_eq!(musig_key_agg(&[keys[2], keys[1], keys[0]]).to_hex(), "6204de8b083426dc6eaf9502d27024d53fc826bf7d2012148a0575435df54b2b");
+    }
+
+    #[test]
+    fn test_control_blocks_commit_to_every_leaf() {
+        let keys = [
+            "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            "c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5",
+            "f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9",
+        ];
+        let leaves: Vec<(u32, Script)> = keys.iter().zip([4, 2, 1])
+            .map(|(key, weight)| {
+                let key = XOnlyPublicKey::from_str(key).unwrap();
+                (weight, Builder::new().push_x_only_key(&key).push_opcode(OP_CHECKSIG).into_script())
+            })
+            .collect();
+        let tree = TaprootTree::weighted(nums_key(), leaves).unwrap();
+        assert!(!tree.key_path_spendable());
+
+        let secp = Secp256k1::verification_only();
+        let output_key = tree.spend_info.output_key().to_inner();
+        for (i, script) in tree.scripts.iter().enumerate() {
+            assert!(tree.control_block(script).unwrap().verify_taproot_commitment(&secp, output_key, script));
+            // The likeliest leaf sits right below the root
+            assert_eq!(tree.leaf(i).depth, if i == 0 { 1 } else { 2 });
+        }
+
+        // The PSBT tap_tree rebuilds the same output key
+        let rebuilt = tree.psbt_tap_tree().unwrap().into_builder().finalize(&secp, nums_key()).unwrap();
+        assert_eq!(rebuilt.output_key(), tree.spend_info.output_key());
+
+        let key_only = TaprootTree::with_depths(XOnlyPublicKey::from_str(keys[0]).unwrap(), Vec::new()).unwrap();
+        assert!(key_only.key_path_spendable() && key_only.summary().leaves.is_empty());
+        assert!(key_only.psbt_tap_tree().is_none());
+    }
+
+    #[test]
+    fn test_coefficient_hashes_reduce_mod_n() {
+        assert_eq!(scalar_mod_n([0xff; 32]).to_be_bytes().to_hex(), format!("{}14551231950b75fc4402da1732fc9bebe", "0".repeat(31)));
+        assert_eq!(scalar_mod_n(CURVE_ORDER).to_be_bytes(), [0; 32]);
+        assert_eq!(scalar_mod_n([0x01; 32]).to_be_bytes(), [0x01; 32]);
+    }
+}
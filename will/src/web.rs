@@ -1,10 +1,40 @@
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
+use actix_web::http::{header, StatusCode};
+use actix_web::middleware::{from_fn, Logger};
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use bitcoin::Network;
 use serde::{Deserialize, Serialize};
-use crate::spending::{UserModel, ProjectSpend, GrowthType};
-use std::env;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
+use utoipa_scalar::{Scalar, Servable};
+use crate::spending::{UserModel, ProjectSpend, GrowthType, Scenario};
+use crate::budget::{BudgetBreach, BudgetCaps};
+use crate::engine::ProjectionEngine;
+use crate::projection::{self, Basis, Granularity, OutputFormat, Period, ProjectionOptions};
+use crate::chart::{self, ChartData, ChartKind};
+use crate::jobs::{Job, JobEvent, JobSnapshot, JobStore};
+use futures_util::stream::{self, Stream};
+use std::sync::Arc;
+use crate::alerts::WebhookNotifier;
+use crate::import::{self, Categorizer, CsvFormat, RuleDefinition, SpendEstimate};
+use crate::store::UserStore;
+use crate::history::{self, ModelChange, ModelEvent};
+use crate::audit::{self, AuditEntry, AuditAction, AuditLog, AuditVerification, Change, FieldChange, ResourceKind};
+use crate::error::{self, ApiError, ErrorBody};
+use crate::auth::{self, Nip98Auth};
+use crate::config::ServerConfig;
+use crate::escrow::{
+    self, BroadcastEscrowTxInput, BroadcastEscrowTxOutput, CreateEscrowTxInput, CreateEscrowTxOutput, FundingUtxo,
+    ReleaseEscrowTxInput, ReleaseEscrowTxOutput,
+};
+use crate::miniscript::{CompileRequest, CompiledDescriptor};
+use crate::rpc::BitcoinRpc;
+use crate::ratelimit::{self, RateLimits};
+use crate::metrics::{self, metrics};
 use actix_cors::Cors;
+use actix_files::Files;
+use std::path::Path;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct CreateProjectRequest {
     project_name: String,
     daily_spend: f64,
@@ -12,129 +42,1924 @@ pub struct CreateProjectRequest {
     growth_type: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct CreateUserRequest {
     user_id: String,
     projection_years: u32,
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize, ToSchema)]
+pub struct ImportRequest {
+    // "bank", "exchange" or "ledger"; ignored when csv_format is given
+    #[serde(default = "default_import_format")]
+    format: String,
+    csv_format: Option<CsvFormat>,
+    csv: String,
+    #[serde(default)]
+    rules: Vec<RuleDefinition>,
+    fallback_project: Option<String>,
+    #[serde(default = "default_window_days")]
+    window_days: u32,
+}
+
+fn default_import_format() -> String {
+    "bank".to_string()
+}
+
+fn default_window_days() -> u32 {
+    90
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ImportResponse {
+    transactions: usize,
+    estimates: Vec<SpendEstimate>,
+    user: UserModel,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectionQuery {
+    // Years to project; defaults to the model's projection_years
+    horizon: Option<u32>,
+    #[serde(default)]
+    granularity: Granularity,
+    // ISO 4217 code; defaults to the model's currency
+    currency: Option<String>,
+    // Units of `currency` per unit of the model's currency; required when converting
+    fx_rate: Option<f64>,
+    // Name of one of the model's scenarios to apply
+    scenario: Option<String>,
+    #[serde(default)]
+    basis: Basis,
+    // Yearly inflation used for real figures; defaults to the scenario's rate, else 0
+    inflation: Option<f64>,
+    #[serde(default)]
+    format: OutputFormat,
+    // Project an earlier version of the stored model
+    version: Option<u64>,
+    // Project the stored model as it stood at this RFC 3339 time, or the end of this YYYY-MM-DD day
+    as_of: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
 pub struct ProjectionResponse {
+    currency: String,
+    granularity: Granularity,
+    basis: Basis,
+    scenario: Option<String>,
     yearly_totals: Vec<YearlyTotal>,
+    periods: Vec<Period>,
+    // Checked against nominal spend in the model's own currency
+    breaches: Vec<BudgetBreach>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct YearlyTotal {
     year: u32,
     total: f64,
 }
 
-async fn create_user(req: web::Json<CreateUserRequest>) -> impl Responder {
-    match UserModel::new(req.user_id.clone(), req.projection_years) {
-        Ok(user) => {
-            // In a real application, you'd store this in a database
-            HttpResponse::Ok().json(user)
+fn invalid_query(field: &str, message: impl Into<String>) -> ApiError {
+    ApiError::bad_request("invalid_query", message).with_field(field)
+}
+
+impl ProjectionQuery {
+    fn is_historical(&self) -> bool {
+        self.version.is_some() || self.as_of.is_some()
+    }
+
+    // The stored model to project: the current one, or an earlier version when asked for
+    fn stored_model(&self, store: &UserStore, user_id: &str) -> Result<UserModel, ApiError> {
+        let missing_user = || ApiError::not_found("User", user_id);
+        let as_of = match &self.as_of {
+            Some(value) => Some(history::parse_as_of(value)
+                .ok_or_else(|| invalid_query("as_of", format!("{} is not an RFC 3339 timestamp or a YYYY-MM-DD date", value)))?),
+            None => None,
+        };
+
+        match (self.version, as_of) {
+            (None, None) => store.get(user_id).ok_or_else(missing_user),
+            (Some(_), Some(_)) => Err(invalid_query("as_of", "Give either version or as_of, not both")),
+            (Some(version), None) => store.with_history(user_id, |events| history::at_version(events, version))
+                .ok_or_else(missing_user)?
+                .ok_or_else(|| ApiError::not_found("Version", &version.to_string()).with_field("version")),
+            (None, Some(at)) => store.with_history(user_id, |events| history::as_of(events, at))
+                .ok_or_else(missing_user)?
+                .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "not_found", format!("User {} did not exist at {}", user_id, at))
+                    .with_field("as_of")),
         }
-        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
     }
+
+    // Resolves the query against a model, returning the model to project (with any scenario applied)
+    fn resolve(&self, model: &UserModel, limits: &RateLimits) -> Result<(UserModel, ProjectionOptions), ApiError> {
+        let horizon = self.horizon.unwrap_or(model.projection_years);
+        if horizon == 0 {
+            return Err(invalid_query("horizon", "Horizon must be greater than 0"));
+        }
+        limits.config.check_projection_years(horizon).map_err(|e| e.with_field("horizon"))?;
+
+        let scenario = match &self.scenario {
+            Some(name) => Some(model.scenario(name)
+                .ok_or_else(|| ApiError::not_found("Scenario", name).with_field("scenario"))?),
+            None => None,
+        };
+
+        let currency = match &self.currency {
+            Some(code) if code.len() == 3 && code.chars().all(|c| c.is_ascii_alphabetic()) => code.to_ascii_uppercase(),
+            Some(code) => return Err(invalid_query("currency", format!("{} is not a three-letter currency code", code))),
+            None => model.currency.clone(),
+        };
+
+        let fx_rate = match self.fx_rate {
+            Some(rate) if !rate.is_finite() || rate <= 0.0 => {
+                return Err(invalid_query("fx_rate", "fx_rate must be greater than 0"));
+            }
+            Some(_) if currency == model.currency => {
+                return Err(invalid_query("fx_rate", format!("fx_rate only applies when converting from {}", model.currency)));
+            }
+            Some(rate) => rate,
+            None if currency != model.currency => {
+                return Err(invalid_query("fx_rate", format!("fx_rate is required to convert {} to {}", model.currency, currency)));
+            }
+            None => 1.0,
+        };
+
+        let inflation_rate = match self.inflation {
+            Some(rate) if !rate.is_finite() || rate <= -1.0 => {
+                return Err(invalid_query("inflation", "Inflation must be greater than -100%"));
+            }
+            Some(rate) => rate,
+            None => scenario.map_or(0.0, |s| s.inflation_rate),
+        };
+
+        let mut model = match scenario {
+            Some(scenario) => scenario.apply(model)?,
+            None => model.clone(),
+        };
+        model.projection_years = horizon;
+
+        let options = ProjectionOptions {
+            horizon,
+            granularity: self.granularity,
+            basis: self.basis,
+            inflation_rate,
+            currency,
+            fx_rate,
+        };
+        Ok((model, options))
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[serde(deny_unknown_fields)]
+pub struct ChartQuery {
+    horizon: Option<u32>,
+    #[serde(default)]
+    granularity: Granularity,
+    currency: Option<String>,
+    fx_rate: Option<f64>,
+    scenario: Option<String>,
+    #[serde(default)]
+    basis: Basis,
+    inflation: Option<f64>,
+    version: Option<u64>,
+    as_of: Option<String>,
+    #[serde(default)]
+    kind: ChartKind,
+    // Pixels; 800x480 by default
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+impl ChartQuery {
+    fn projection_query(&self) -> ProjectionQuery {
+        ProjectionQuery {
+            horizon: self.horizon,
+            granularity: self.granularity,
+            currency: self.currency.clone(),
+            fx_rate: self.fx_rate,
+            scenario: self.scenario.clone(),
+            basis: self.basis,
+            inflation: self.inflation,
+            format: OutputFormat::Json,
+            version: self.version,
+            as_of: self.as_of.clone(),
+        }
+    }
+
+    fn size(&self) -> Result<(u32, u32), ApiError> {
+        let check = |field: &str, value: u32| {
+            if (chart::MIN_SIZE..=chart::MAX_SIZE).contains(&value) {
+                Ok(value)
+            } else {
+                Err(invalid_query(field, format!("{} must be within {}..={} pixels", field, chart::MIN_SIZE, chart::MAX_SIZE)))
+            }
+        };
+        Ok((check("width", self.width.unwrap_or(800))?, check("height", self.height.unwrap_or(480))?))
+    }
+}
+
+// Projects the stored model and lays it out as a chart
+fn projection_chart(store: &UserStore, limits: &RateLimits, user_id: &str, query: &ChartQuery) -> Result<ChartData, ApiError> {
+    let projection_query = query.projection_query();
+    let user = projection_query.stored_model(store, user_id)?;
+
+    let (model, options) = projection_query.resolve(&user, limits)?;
+    let projection = projection::project(&model, &options, ProjectionEngine::shared())?;
+
+    let mut title = format!("{} {}", user.user_id, projection.currency);
+    if projection.basis == Basis::Real {
+        title.push_str(" real");
+    }
+    if let Some(scenario) = &query.scenario {
+        title = format!("{} {}", title, scenario);
+    }
+    if let Some(version) = query.version {
+        title = format!("{} v{}", title, version);
+    }
+    if let Some(as_of) = &query.as_of {
+        title = format!("{} as of {}", title, as_of);
+    }
+    Ok(ChartData::from_projection(title, &projection))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/projection.png",
+    params(("user_id" = String, Path, description = "User identifier"), ChartQuery),
+    responses(
+        (status = 200, description = "The projection drawn as a PNG chart", content_type = "image/png", body = Vec<u8>),
+        (status = 400, description = "Invalid query parameters", body = ErrorBody),
+        (status = 401, description = "Missing or invalid NIP-98 authorization", body = ErrorBody),
+        (status = 403, description = "Authorized as a different user", body = ErrorBody),
+        (status = 404, description = "Unknown user, scenario or version", body = ErrorBody),
+        (status = 422, description = "A custom formula failed or the horizon is above the server limit", body = ErrorBody),
+    )
+)]
+async fn projection_png(
+    store: web::Data<UserStore>,
+    limits: web::Data<RateLimits>,
+    auth: Nip98Auth,
+    user_id: web::Path<String>,
+    query: web::Query<ChartQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = auth.authorize(&user_id)?;
+    let (width, height) = query.size()?;
+    let data = projection_chart(&store, &limits, &user_id, &query)?;
+
+    let png = chart::render_png(&data, query.kind, width, height)
+        .map_err(|e| ApiError::internal(e.to_string()))?;
+    Ok(HttpResponse::Ok().content_type("image/png").body(png))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/projection.svg",
+    params(("user_id" = String, Path, description = "User identifier"), ChartQuery),
+    responses(
+        (status = 200, description = "The projection drawn as an SVG chart", content_type = "image/svg+xml", body = String),
+        (status = 400, description = "Invalid query parameters", body = ErrorBody),
+        (status = 401, description = "Missing or invalid NIP-98 authorization", body = ErrorBody),
+        (status = 403, description = "Authorized as a different user", body = ErrorBody),
+        (status = 404, description = "Unknown user, scenario or version", body = ErrorBody),
+        (status = 422, description = "A custom formula failed or the horizon is above the server limit", body = ErrorBody),
+    )
+)]
+async fn projection_svg(
+    store: web::Data<UserStore>,
+    limits: web::Data<RateLimits>,
+    auth: Nip98Auth,
+    user_id: web::Path<String>,
+    query: web::Query<ChartQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = auth.authorize(&user_id)?;
+    let (width, height) = query.size()?;
+    let data = projection_chart(&store, &limits, &user_id, &query)?;
+
+    let svg = chart::render_svg(&data, query.kind, width, height);
+    Ok(HttpResponse::Ok().content_type("image/svg+xml").body(svg))
+}
+
+// Runs a resolved projection and renders it in the requested format
+fn render_projection(
+    model: &UserModel,
+    options: &ProjectionOptions,
+    query: &ProjectionQuery,
+    breaches: Vec<BudgetBreach>,
+) -> Result<HttpResponse, ApiError> {
+    let projection = projection::project(model, options, ProjectionEngine::shared())?;
+
+    if query.format == OutputFormat::Csv {
+        let csv = projection.to_csv().map_err(|e| ApiError::internal(e.to_string()))?;
+        return Ok(HttpResponse::Ok().content_type("text/csv; charset=utf-8").body(csv));
+    }
+
+    let yearly_totals = projection.yearly_totals()
+        .into_iter()
+        .map(|(year, total)| YearlyTotal { year, total })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(ProjectionResponse {
+        currency: projection.currency,
+        granularity: projection.granularity,
+        basis: projection.basis,
+        scenario: query.scenario.clone(),
+        yearly_totals,
+        periods: projection.periods,
+        breaches,
+    }))
 }
 
+#[utoipa::path(
+    post,
+    path = "/users",
+    request_body = CreateUserRequest,
+    responses(
+        (status = 200, description = "User created", body = UserModel),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 422, description = "projection_years is above the server limit", body = ErrorBody),
+        (status = 401, description = "Missing or invalid NIP-98 authorization", body = ErrorBody),
+        (status = 403, description = "user_id is not the authorized pubkey", body = ErrorBody),
+        (status = 409, description = "A user with this user_id already exists", body = ErrorBody),
+    )
+)]
+async fn create_user(
+    store: web::Data<UserStore>,
+    audit: web::Data<AuditLog>,
+    limits: web::Data<RateLimits>,
+    auth: Nip98Auth,
+    req: web::Json<CreateUserRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = auth.authorize(&req.user_id)?;
+    limits.config.check_projection_years(req.projection_years)?;
+    let user = UserModel::new(user_id, req.projection_years)?;
+    if !store.insert(user.clone()) {
+        return Err(ApiError::new(StatusCode::CONFLICT, "user_exists", format!("User {} already exists", user.user_id))
+            .with_field("user_id"));
+    }
+
+    audit.record(auth.pubkey(), Change::new(ResourceKind::User, &user.user_id, None, Some(&user))
+        .subject(&user.user_id));
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/projects",
+    params(("user_id" = String, Path, description = "User identifier")),
+    request_body = CreateProjectRequest,
+    responses(
+        (status = 200, description = "Project added", body = ProjectSpend),
+        (status = 400, description = "Invalid project", body = ErrorBody),
+        (status = 422, description = "The model already has the maximum number of projects", body = ErrorBody),
+        (status = 401, description = "Missing or invalid NIP-98 authorization", body = ErrorBody),
+        (status = 403, description = "Authorized as a different user", body = ErrorBody),
+        (status = 404, description = "Unknown user", body = ErrorBody),
+    )
+)]
 async fn add_project(
+    store: web::Data<UserStore>,
+    audit: web::Data<AuditLog>,
+    limits: web::Data<RateLimits>,
+    auth: Nip98Auth,
     user_id: web::Path<String>,
     req: web::Json<CreateProjectRequest>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
+    let user_id = auth.authorize(&user_id)?;
+
     let growth_type = match req.growth_type.as_str() {
         "compound" => GrowthType::Compound,
         "flat" => GrowthType::Flat,
         custom => GrowthType::Custom(custom.to_string()),
     };
 
-    match ProjectSpend::new(
+    let project = ProjectSpend::new(
         req.project_name.clone(),
         req.daily_spend,
         req.growth_rate,
         growth_type,
-    ) {
-        Ok(project) => {
-            // In a real application, you'd add this to the user's projects in a database
-            HttpResponse::Ok().json(project)
+    )?;
+
+    store.apply(&user_id, ModelChange::ProjectAdded { project: project.clone() }, |_, after| {
+        limits.config.check_projects(after.projects.len())
+    })
+    .ok_or_else(|| ApiError::not_found("User", &user_id))??;
+
+    audit.record(auth.pubkey(), Change::new(ResourceKind::Project, &project.project_name, None, Some(&project))
+        .subject(user_id.as_str()));
+    Ok(HttpResponse::Ok().json(project))
+}
+
+#[utoipa::path(
+    put,
+    path = "/users/{user_id}/budget",
+    params(("user_id" = String, Path, description = "User identifier")),
+    request_body = BudgetCaps,
+    responses(
+        (status = 200, description = "Budget caps replaced", body = BudgetCaps),
+        (status = 400, description = "Invalid caps or thresholds", body = ErrorBody),
+        (status = 401, description = "Missing or invalid NIP-98 authorization", body = ErrorBody),
+        (status = 403, description = "Authorized as a different user", body = ErrorBody),
+        (status = 404, description = "Unknown user", body = ErrorBody),
+    )
+)]
+async fn set_budget(
+    store: web::Data<UserStore>,
+    audit: web::Data<AuditLog>,
+    auth: Nip98Auth,
+    user_id: web::Path<String>,
+    req: web::Json<BudgetCaps>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = auth.authorize(&user_id)?;
+
+    let revision = store.apply(&user_id, ModelChange::BudgetSet { budget: req.into_inner() }, |_, _| Ok::<_, ApiError>(()))
+        .ok_or_else(|| ApiError::not_found("User", &user_id))??;
+
+    let (before, after) = (&revision.before.budget, &revision.after.budget);
+    audit.record(auth.pubkey(), Change::new(ResourceKind::Budget, &user_id, Some(before), Some(after))
+        .subject(user_id.as_str()));
+    Ok(HttpResponse::Ok().json(after))
+}
+
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/import",
+    params(("user_id" = String, Path, description = "User identifier")),
+    request_body = ImportRequest,
+    responses(
+        (status = 200, description = "Spend estimates applied to the user", body = ImportResponse),
+        (status = 400, description = "Unreadable export or rules", body = ErrorBody),
+        (status = 422, description = "The import would exceed the project limit", body = ErrorBody),
+        (status = 401, description = "Missing or invalid NIP-98 authorization", body = ErrorBody),
+        (status = 403, description = "Authorized as a different user", body = ErrorBody),
+        (status = 404, description = "Unknown user", body = ErrorBody),
+    )
+)]
+async fn import_transactions(
+    store: web::Data<UserStore>,
+    audit: web::Data<AuditLog>,
+    limits: web::Data<RateLimits>,
+    auth: Nip98Auth,
+    user_id: web::Path<String>,
+    req: web::Json<ImportRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = auth.authorize(&user_id)?;
+
+    let req = req.into_inner();
+    let format = match (req.csv_format, req.format.as_str()) {
+        (Some(format), _) => format,
+        (None, "bank") => CsvFormat::bank(),
+        (None, "exchange") => CsvFormat::exchange(),
+        (None, "ledger") => CsvFormat::ledger(),
+        (None, other) => {
+            return Err(ApiError::bad_request("unknown_format", format!("Unknown import format: {}", other))
+                .with_field("format"));
+        }
+    };
+
+    let transactions = import::read_transactions(req.csv.as_bytes(), &format)?;
+    let categorizer = Categorizer::new(&req.rules, req.fallback_project)?;
+    let estimates = import::estimate_spend(&transactions, &categorizer, req.window_days);
+
+    let revision = store.apply(&user_id, ModelChange::EstimatesApplied { estimates: estimates.clone() }, |_, after| {
+        limits.config.check_projects(after.projects.len())
+    })
+    .ok_or_else(|| ApiError::not_found("User", &user_id))??;
+
+    audit.record(auth.pubkey(), Change::new(ResourceKind::User, &user_id, Some(&revision.before), Some(&revision.after))
+        .subject(user_id.as_str()));
+
+    Ok(HttpResponse::Ok().json(ImportResponse {
+        transactions: transactions.len(),
+        estimates,
+        user: revision.after,
+    }))
+}
+
+#[utoipa::path(
+    put,
+    path = "/users/{user_id}/scenarios",
+    params(("user_id" = String, Path, description = "User identifier")),
+    request_body = Scenario,
+    responses(
+        (status = 200, description = "Scenario added or replaced", body = Scenario),
+        (status = 400, description = "Invalid scenario", body = ErrorBody),
+        (status = 401, description = "Missing or invalid NIP-98 authorization", body = ErrorBody),
+        (status = 403, description = "Authorized as a different user", body = ErrorBody),
+        (status = 404, description = "Unknown user", body = ErrorBody),
+    )
+)]
+async fn set_scenario(
+    store: web::Data<UserStore>,
+    audit: web::Data<AuditLog>,
+    auth: Nip98Auth,
+    user_id: web::Path<String>,
+    req: web::Json<Scenario>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = auth.authorize(&user_id)?;
+
+    let scenario = req.into_inner();
+    let revision = store.apply(&user_id, ModelChange::ScenarioSet { scenario: scenario.clone() }, |_, _| Ok::<_, ApiError>(()))
+        .ok_or_else(|| ApiError::not_found("User", &user_id))??;
+
+    let previous = revision.before.scenario(&scenario.name);
+    audit.record(auth.pubkey(), Change::new(ResourceKind::Scenario, &scenario.name, previous, Some(&scenario))
+        .subject(user_id.as_str()));
+    Ok(HttpResponse::Ok().json(scenario))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/users/{user_id}/scenarios/{name}",
+    params(
+        ("user_id" = String, Path, description = "User identifier"),
+        ("name" = String, Path, description = "Scenario name"),
+    ),
+    responses(
+        (status = 200, description = "Scenario removed", body = Scenario),
+        (status = 401, description = "Missing or invalid NIP-98 authorization", body = ErrorBody),
+        (status = 403, description = "Authorized as a different user", body = ErrorBody),
+        (status = 404, description = "Unknown user or scenario", body = ErrorBody),
+    )
+)]
+async fn delete_scenario(
+    store: web::Data<UserStore>,
+    audit: web::Data<AuditLog>,
+    auth: Nip98Auth,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (user_id, name) = path.into_inner();
+    let user_id = auth.authorize(&user_id)?;
+
+    let revision = store.apply(&user_id, ModelChange::ScenarioRemoved { name: name.clone() }, |before, _| {
+        before.scenario(&name).map(|_| ()).ok_or_else(|| ApiError::not_found("Scenario", &name))
+    })
+    .ok_or_else(|| ApiError::not_found("User", &user_id))??;
+
+    let removed = revision.before.scenario(&name);
+    audit.record(auth.pubkey(), Change::new(ResourceKind::Scenario, &name, removed, None)
+        .subject(&user_id));
+    Ok(HttpResponse::Ok().json(removed))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/history",
+    params(("user_id" = String, Path, description = "User identifier")),
+    responses(
+        (status = 200, description = "Every change to the user's model, oldest first", body = Vec<ModelEvent>),
+        (status = 401, description = "Missing or invalid NIP-98 authorization", body = ErrorBody),
+        (status = 403, description = "Authorized as a different user", body = ErrorBody),
+        (status = 404, description = "Unknown user", body = ErrorBody),
+    )
+)]
+async fn model_history(
+    store: web::Data<UserStore>,
+    auth: Nip98Auth,
+    user_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = auth.authorize(&user_id)?;
+
+    let events = store.with_history(&user_id, <[ModelEvent]>::to_vec)
+        .ok_or_else(|| ApiError::not_found("User", &user_id))?;
+    Ok(HttpResponse::Ok().json(events))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[serde(deny_unknown_fields)]
+pub struct DiffQuery {
+    from: u64,
+    // Defaults to the latest version
+    to: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct VersionDiff {
+    from: u64,
+    to: u64,
+    changes: Vec<FieldChange>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/diff",
+    params(("user_id" = String, Path, description = "User identifier"), DiffQuery),
+    responses(
+        (status = 200, description = "Field-level differences between two versions of the model", body = VersionDiff),
+        (status = 400, description = "Invalid query parameters", body = ErrorBody),
+        (status = 401, description = "Missing or invalid NIP-98 authorization", body = ErrorBody),
+        (status = 403, description = "Authorized as a different user", body = ErrorBody),
+        (status = 404, description = "Unknown user or version", body = ErrorBody),
+    )
+)]
+async fn diff_versions(
+    store: web::Data<UserStore>,
+    auth: Nip98Auth,
+    user_id: web::Path<String>,
+    query: web::Query<DiffQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = auth.authorize(&user_id)?;
+
+    let diff = store.with_history(&user_id, |events| {
+        let to = query.to.unwrap_or(events.len() as u64);
+        let model = |field: &str, version: u64| history::at_version(events, version)
+            .map(|model| serde_json::to_value(model).expect("models serialize to JSON"))
+            .ok_or_else(|| ApiError::not_found("Version", &version.to_string()).with_field(field));
+
+        let changes = audit::diff(&model("from", query.from)?, &model("to", to)?);
+        Ok::<_, ApiError>(VersionDiff { from: query.from, to, changes })
+    })
+    .ok_or_else(|| ApiError::not_found("User", &user_id))??;
+
+    Ok(HttpResponse::Ok().json(diff))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/projection",
+    params(("user_id" = String, Path, description = "User identifier"), ProjectionQuery),
+    responses(
+        (status = 200, description = "Projected periods and budget breaches, or CSV when format=csv", body = ProjectionResponse),
+        (status = 400, description = "Invalid query parameters", body = ErrorBody),
+        (status = 401, description = "Missing or invalid NIP-98 authorization", body = ErrorBody),
+        (status = 403, description = "Authorized as a different user", body = ErrorBody),
+        (status = 404, description = "Unknown user, scenario or version", body = ErrorBody),
+        (status = 422, description = "A custom formula failed or the horizon is above the server limit", body = ErrorBody),
+    )
+)]
+async fn calculate_projection(
+    store: web::Data<UserStore>,
+    limits: web::Data<RateLimits>,
+    notifier: web::Data<Option<WebhookNotifier>>,
+    auth: Nip98Auth,
+    user_id: web::Path<String>,
+    query: web::Query<ProjectionQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = auth.authorize(&user_id)?;
+
+    let user = query.stored_model(&store, &user_id)?;
+    let (model, options) = query.resolve(&user, &limits)?;
+    let breaches = model.check_budget()?;
+
+    // Only the current model as stored raises alerts; what-if scenarios and past versions never do
+    let alerting = query.scenario.is_none() && !query.is_historical();
+    if let Some(notifier) = notifier.get_ref().clone().filter(|_| alerting) {
+        if notifier.should_notify(&user.user_id, &breaches) {
+            let breaches = breaches.clone();
+            // Fire and forget so a slow webhook never delays the projection
+            actix_web::rt::spawn(async move {
+                match web::block(move || notifier.notify(&user.user_id, &breaches)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => log::warn!("Budget alert for {} failed: {}", user_id, e),
+                    Err(e) => log::warn!("Budget alert task for {} failed: {}", user_id, e),
+                }
+            });
         }
-        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
     }
+
+    render_projection(&model, &options, &query, breaches)
 }
 
-async fn calculate_projection(user_id: web::Path<String>) -> impl Responder {
-    // Extract the String from Path
-    let user_id = user_id.into_inner();
-    
-    // In a real application, you'd fetch the user from a database
-    let mut user = match UserModel::new(user_id, 5) {
-        Ok(u) => u,
-        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
-    };
+#[utoipa::path(
+    post,
+    path = "/projection",
+    params(ProjectionQuery),
+    request_body = UserModel,
+    responses(
+        (status = 200, description = "Projection of the inline model, or CSV when format=csv", body = ProjectionResponse),
+        (status = 400, description = "Invalid model or query parameters", body = ErrorBody),
+        (status = 404, description = "Unknown scenario", body = ErrorBody),
+        (status = 422, description = "A custom formula failed or the model is above the server limits", body = ErrorBody),
+    ),
+    security(())
+)]
+// Stateless what-if projection: nothing is stored and no alerts are sent
+async fn project_inline(
+    limits: web::Data<RateLimits>,
+    query: web::Query<ProjectionQuery>,
+    req: web::Json<UserModel>,
+) -> Result<HttpResponse, ApiError> {
+    if query.is_historical() {
+        let field = if query.version.is_some() { "version" } else { "as_of" };
+        return Err(invalid_query(field, "An inline model has no history"));
+    }
+
+    let user = req.into_inner();
+    user.validate()?;
+    limits.config.check_projects(user.projects.len())?;
+    limits.config.check_projection_years(user.projection_years)?;
+
+    let (model, options) = query.resolve(&user, &limits)?;
+    let breaches = model.check_budget()?;
+
+    render_projection(&model, &options, &query, breaches)
+}
+
+#[utoipa::path(
+    post,
+    path = "/miniscript/compile",
+    request_body = CompileRequest,
+    responses(
+        (status = 200, description = "Address, scripts, analysis and plain-language spending paths", body = CompiledDescriptor),
+        (status = 400, description = "Invalid policy, Miniscript or descriptor", body = ErrorBody),
+        (status = 422, description = "No safe script implements the policy, or too many spending paths to describe", body = ErrorBody),
+    ),
+    security(())
+)]
+// Compiles a policy, Miniscript or descriptor; nothing is stored
+async fn compile_miniscript(network: web::Data<Network>, req: web::Json<CompileRequest>) -> Result<HttpResponse, ApiError> {
+    let compiled = req.descriptor()
+        .and_then(|descriptor| descriptor.compile(**network))
+        .map_err(|e| ApiError::from(e).with_field(req.field()))?;
+    Ok(HttpResponse::Ok().json(compiled))
+}
 
-    // Example projects - in a real app, these would come from a database
-    let project1 = ProjectSpend::new(
-        "Project A".into(),
-        100.0,
-        0.05,
-        GrowthType::Compound,
-    ).unwrap();
-    
-    let project2 = ProjectSpend::new(
-        "Project B".into(),
-        50.0,
-        0.03,
-        GrowthType::Flat,
-    ).unwrap();
-
-    user.add_project(project1);
-    user.add_project(project2);
-
-    match user.calculate_total_spend() {
-        Ok(totals) => {
-            let yearly_totals: Vec<YearlyTotal> = totals
-                .into_iter()
-                .map(|(year, total)| YearlyTotal { year, total })
-                .collect();
-            
-            HttpResponse::Ok().json(ProjectionResponse { yearly_totals })
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/jobs",
+    params(("user_id" = String, Path, description = "User identifier"), ProjectionQuery),
+    responses(
+        (status = 202, description = "Projection job started; follow it at its events URL", body = JobSnapshot),
+        (status = 400, description = "Invalid query parameters", body = ErrorBody),
+        (status = 401, description = "Missing or invalid NIP-98 authorization", body = ErrorBody),
+        (status = 403, description = "Authorized as a different user", body = ErrorBody),
+        (status = 404, description = "Unknown user, scenario or version", body = ErrorBody),
+        (status = 422, description = "Too many running jobs or the horizon is above the server limit", body = ErrorBody),
+    )
+)]
+async fn submit_job(
+    store: web::Data<UserStore>,
+    jobs: web::Data<JobStore>,
+    limits: web::Data<RateLimits>,
+    auth: Nip98Auth,
+    user_id: web::Path<String>,
+    query: web::Query<ProjectionQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = auth.authorize(&user_id)?;
+
+    if query.format != OutputFormat::Json {
+        return Err(invalid_query("format", "Jobs stream JSON events; format cannot be changed"));
+    }
+
+    let user = query.stored_model(&store, &user_id)?;
+    let (model, options) = query.resolve(&user, &limits)?;
+
+    let job = jobs.create(&user_id, options.horizon, |running| limits.config.check_running_jobs(running))?;
+
+    let runner = job.clone();
+    actix_web::rt::spawn(async move {
+        let worker = runner.clone();
+        if let Err(e) = web::block(move || worker.run(&model, &options, ProjectionEngine::shared())).await {
+            log::warn!("Projection job {} failed: {}", runner.id(), e);
+            runner.fail(ApiError::internal("The projection job stopped unexpectedly"));
+        }
+    });
+
+    Ok(HttpResponse::Accepted()
+        .insert_header((header::LOCATION, format!("/users/{}/jobs/{}/events", user_id, job.id())))
+        .json(job.snapshot()))
+}
+
+// Jobs are only visible under the user that submitted them
+fn owned_job(jobs: &JobStore, user_id: &str, job_id: &str) -> Result<Arc<Job>, ApiError> {
+    jobs.get(job_id)
+        .filter(|job| job.user_id() == user_id)
+        .ok_or_else(|| ApiError::not_found("Job", job_id))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/jobs/{job_id}",
+    params(
+        ("user_id" = String, Path, description = "User identifier"),
+        ("job_id" = String, Path, description = "Job identifier"),
+    ),
+    responses(
+        (status = 200, description = "Current job status", body = JobSnapshot),
+        (status = 401, description = "Missing or invalid NIP-98 authorization", body = ErrorBody),
+        (status = 403, description = "Authorized as a different user", body = ErrorBody),
+        (status = 404, description = "Unknown job", body = ErrorBody),
+    )
+)]
+async fn job_status(
+    jobs: web::Data<JobStore>,
+    auth: Nip98Auth,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (user_id, job_id) = path.into_inner();
+    let user_id = auth.authorize(&user_id)?;
+
+    let job = owned_job(&jobs, &user_id, &job_id)?;
+    Ok(HttpResponse::Ok().json(job.snapshot()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/jobs/{job_id}/events",
+    params(
+        ("user_id" = String, Path, description = "User identifier"),
+        ("job_id" = String, Path, description = "Job identifier"),
+        ("Last-Event-ID" = Option<usize>, Header, description = "Resume after this event"),
+    ),
+    responses(
+        (status = 200, description = "Server-Sent Events: every event so far, then new ones until the job finishes", body = JobEvent, content_type = "text/event-stream"),
+        (status = 401, description = "Missing or invalid NIP-98 authorization", body = ErrorBody),
+        (status = 403, description = "Authorized as a different user", body = ErrorBody),
+        (status = 404, description = "Unknown job", body = ErrorBody),
+    )
+)]
+async fn job_events(
+    req: HttpRequest,
+    jobs: web::Data<JobStore>,
+    auth: Nip98Auth,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (user_id, job_id) = path.into_inner();
+    let user_id = auth.authorize(&user_id)?;
+
+    let job = owned_job(&jobs, &user_id, &job_id)?;
+    let cursor = req.headers().get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok())
+        .map_or(0, |last| last + 1);
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(event_stream(job, cursor)))
+}
+
+// Replays the job's events from `cursor`, then waits for new ones until the job finishes
+fn event_stream(job: Arc<Job>, cursor: usize) -> impl Stream<Item = Result<web::Bytes, actix_web::Error>> {
+    let updates = job.subscribe();
+    stream::unfold((job, updates, cursor), |(job, mut updates, cursor)| async move {
+        loop {
+            let (events, finished) = job.events_since(cursor);
+            if !events.is_empty() {
+                let frames: String = events.iter()
+                    .enumerate()
+                    .map(|(i, event)| sse_frame(cursor + i, event))
+                    .collect();
+                return Some((Ok(web::Bytes::from(frames)), (job, updates, cursor + events.len())));
+            }
+            if finished || updates.changed().await.is_err() {
+                return None;
+            }
         }
-        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
-    }
-}
-
-async fn index() -> impl Responder {
-    HttpResponse::Ok().body("Welcome to the Projection API! Use /users to create a new user.")
-}
-
-pub async fn run_server() -> std::io::Result<()> {
-    // Get port from environment variable or use default
-    let port = env::var("PORT")
-        .unwrap_or_else(|_| "8080".to_string())
-        .parse::<u16>()
-        .unwrap_or(8080);
-    
-    let host = env::var("HOST")
-        .unwrap_or_else(|_| "0.0.0.0".to_string());
-    
-    println!("Starting web server at http://{}:{}", host, port);
-    
-    HttpServer::new(|| {
-        let cors = Cors::default()
-            .allow_any_origin()
-            .allow_any_method()
-            .allow_any_header()
+    })
+}
+
+fn sse_frame(id: usize, event: &JobEvent) -> String {
+    let data = serde_json::to_string(event).expect("job events serialize to JSON");
+    format!("id: {}\nevent: {}\ndata: {}\n\n", id, event.name(), data)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/users/{user_id}/jobs/{job_id}",
+    params(
+        ("user_id" = String, Path, description = "User identifier"),
+        ("job_id" = String, Path, description = "Job identifier"),
+    ),
+    responses(
+        (status = 200, description = "Job cancelled, or its final status if it had already finished", body = JobSnapshot),
+        (status = 401, description = "Missing or invalid NIP-98 authorization", body = ErrorBody),
+        (status = 403, description = "Authorized as a different user", body = ErrorBody),
+        (status = 404, description = "Unknown job", body = ErrorBody),
+    )
+)]
+async fn cancel_job(
+    jobs: web::Data<JobStore>,
+    auth: Nip98Auth,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (user_id, job_id) = path.into_inner();
+    let user_id = auth.authorize(&user_id)?;
+
+    let job = owned_job(&jobs, &user_id, &job_id)?;
+    job.cancel();
+    Ok(HttpResponse::Ok().json(job.snapshot()))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[serde(deny_unknown_fields)]
+pub struct AuditQuery {
+    resource: Option<ResourceKind>,
+    action: Option<AuditAction>,
+    // RFC 3339 bounds on the entry timestamp, both inclusive
+    since: Option<chrono::DateTime<chrono::Utc>>,
+    until: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl AuditQuery {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.resource.is_none_or(|resource| entry.resource == resource)
+            && self.action.is_none_or(|action| entry.action == action)
+            && self.since.is_none_or(|since| entry.timestamp >= since)
+            && self.until.is_none_or(|until| entry.timestamp <= until)
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/audit",
+    params(("user_id" = String, Path, description = "User identifier"), AuditQuery),
+    responses(
+        (status = 200, description = "Audit entries touching the user's model or escrows, oldest first", body = Vec<AuditEntry>),
+        (status = 400, description = "Invalid query parameters", body = ErrorBody),
+        (status = 401, description = "Missing or invalid NIP-98 authorization", body = ErrorBody),
+        (status = 403, description = "Authorized as a different user", body = ErrorBody),
+    )
+)]
+async fn audit_entries(
+    audit: web::Data<AuditLog>,
+    auth: Nip98Auth,
+    user_id: web::Path<String>,
+    query: web::Query<AuditQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = auth.authorize(&user_id)?;
+
+    let entries = audit.query(|entry| entry.subjects.contains(&user_id) && query.matches(entry));
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+#[utoipa::path(
+    get,
+    path = "/audit/verify",
+    responses((status = 200, description = "Whether the whole audit chain is intact, and its head hash", body = AuditVerification)),
+    security(())
+)]
+// Reveals only the chain's length and head, so anyone can check it against a copy they hold
+async fn verify_audit(audit: web::Data<AuditLog>) -> HttpResponse {
+    HttpResponse::Ok().json(audit.verify())
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct HealthResponse {
+    status: &'static str,
+    // The Bitcoin network escrows are created on
+    network: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReadinessCheck {
+    ok: bool,
+    detail: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReadinessResponse {
+    status: &'static str,
+    storage: ReadinessCheck,
+    bitcoin_rpc: ReadinessCheck,
+}
+
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    responses((status = 200, description = "The process is up", body = HealthResponse)),
+    security(())
+)]
+async fn healthz(network: web::Data<Network>) -> HttpResponse {
+    HttpResponse::Ok().json(HealthResponse { status: "ok", network: network.to_string() })
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    responses(
+        (status = 200, description = "Storage and the Bitcoin node are reachable", body = ReadinessResponse),
+        (status = 503, description = "A dependency is unavailable", body = ReadinessResponse),
+    ),
+    security(())
+)]
+async fn readyz(store: web::Data<UserStore>, rpc: web::Data<Option<BitcoinRpc>>) -> HttpResponse {
+    let storage = match store.is_available() {
+        true => ReadinessCheck { ok: true, detail: "available".into() },
+        false => ReadinessCheck { ok: false, detail: "user store lock is poisoned".into() },
+    };
+
+    // Without a configured node there is nothing to wait for, so it does not block readiness
+    let bitcoin_rpc = match rpc.get_ref().clone() {
+        None => ReadinessCheck { ok: true, detail: "not configured".into() },
+        Some(rpc) => match web::block(move || rpc.check_network().map(|height| (height, rpc.network()))).await {
+            Ok(Ok((height, network))) => ReadinessCheck { ok: true, detail: format!("{} block height {}", network, height) },
+            Ok(Err(e)) => ReadinessCheck { ok: false, detail: e.to_string() },
+            Err(e) => ReadinessCheck { ok: false, detail: e.to_string() },
+        },
+    };
+
+    let ready = storage.ok && bitcoin_rpc.ok;
+    let mut response = match ready {
+        true => HttpResponse::Ok(),
+        false => HttpResponse::ServiceUnavailable(),
+    };
+    response.json(ReadinessResponse {
+        status: if ready { "ready" } else { "not_ready" },
+        storage,
+        bitcoin_rpc,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses((status = 200, description = "Prometheus text exposition format", body = String, content_type = "text/plain")),
+    security(())
+)]
+async fn metrics_text() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics().render())
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Projection API", description = "Spending projections for will planning"),
+    paths(
+        create_user, add_project, set_budget, import_transactions,
+        set_scenario, delete_scenario, model_history, diff_versions, calculate_projection, project_inline, projection_png, projection_svg,
+        submit_job, job_status, job_events, cancel_job, audit_entries, verify_audit,
+        escrow::create_escrow_tx, escrow::release_escrow_tx, escrow::broadcast_escrow_tx, compile_miniscript, healthz, readyz, metrics_text
+    ),
+    components(schemas(
+        ErrorBody, CreateEscrowTxInput, CreateEscrowTxOutput, FundingUtxo, ReleaseEscrowTxInput, ReleaseEscrowTxOutput,
+        BroadcastEscrowTxInput, BroadcastEscrowTxOutput,
+    )),
+    modifiers(&Nip98Security),
+    security(("nip98" = []))
+)]
+// Every route may also answer 413 for oversized bodies and 429 when rate limited
+pub struct ApiDoc;
+
+struct Nip98Security;
+
+impl Modify for Nip98Security {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        let mut header = ApiKeyValue::new("Authorization");
+        header.description = Some("Nostr <base64 NIP-98 event>".into());
+        components.add_security_scheme("nip98", SecurityScheme::ApiKey(ApiKey::Header(header)));
+    }
+}
+
+async fn openapi_json() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+async fn not_found(req: HttpRequest) -> Result<HttpResponse, ApiError> {
+    Err(ApiError::new(StatusCode::NOT_FOUND, "route_not_found", format!("No route for {} {}", req.method(), req.path())))
+}
+
+fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default().error_handler(error::json_error_handler)
+}
+
+// Registers the API routes along with extractor configs that report errors as JSON
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(json_config())
+        .app_data(web::PathConfig::default().error_handler(error::path_error_handler))
+        .app_data(web::QueryConfig::default().error_handler(error::query_error_handler))
+        .route("/openapi.json", web::get().to(openapi_json))
+        .route("/healthz", web::get().to(healthz))
+        .route("/readyz", web::get().to(readyz))
+        .route("/metrics", web::get().to(metrics_text))
+        .route("/audit/verify", web::get().to(verify_audit))
+        // Escrows build and broadcast transactions for their parties, so they need a signer like /users
+        .service(
+            web::resource("/create_escrow_tx")
+                .wrap(from_fn(ratelimit::limit_by_pubkey))
+                .wrap(from_fn(auth::verify_nip98))
+                .route(web::post().to(escrow::create_escrow_tx)),
+        )
+        .service(
+            web::resource("/release_escrow_tx")
+                .wrap(from_fn(ratelimit::limit_by_pubkey))
+                .wrap(from_fn(auth::verify_nip98))
+                .route(web::post().to(escrow::release_escrow_tx)),
+        )
+        .service(
+            web::resource("/broadcast_escrow_tx")
+                .wrap(from_fn(ratelimit::limit_by_pubkey))
+                .wrap(from_fn(auth::verify_nip98))
+                .route(web::post().to(escrow::broadcast_escrow_tx)),
+        )
+        .route("/projection", web::post().to(project_inline))
+        .route("/miniscript/compile", web::post().to(compile_miniscript))
+        .service(Scalar::with_url("/docs", ApiDoc::openapi()))
+        .service(
+            web::scope("/users")
+                // Wraps run outermost-last: authenticate first, then throttle by signer
+                .wrap(from_fn(ratelimit::limit_by_pubkey))
+                .wrap(from_fn(auth::verify_nip98))
+                .route("", web::post().to(create_user))
+                .route("/{user_id}/projects", web::post().to(add_project))
+                .route("/{user_id}/budget", web::put().to(set_budget))
+                .route("/{user_id}/import", web::post().to(import_transactions))
+                .route("/{user_id}/scenarios", web::put().to(set_scenario))
+                .route("/{user_id}/scenarios/{name}", web::delete().to(delete_scenario))
+                .route("/{user_id}/history", web::get().to(model_history))
+                .route("/{user_id}/diff", web::get().to(diff_versions))
+                .route("/{user_id}/projection", web::get().to(calculate_projection))
+                .route("/{user_id}/projection.png", web::get().to(projection_png))
+                .route("/{user_id}/projection.svg", web::get().to(projection_svg))
+                .route("/{user_id}/jobs", web::post().to(submit_job))
+                .route("/{user_id}/jobs/{job_id}", web::get().to(job_status))
+                .route("/{user_id}/jobs/{job_id}", web::delete().to(cancel_job))
+                .route("/{user_id}/jobs/{job_id}/events", web::get().to(job_events))
+                .route("/{user_id}/audit", web::get().to(audit_entries)),
+        )
+        .default_service(web::to(not_found));
+}
+
+// Serves the front end; registered after configure so API routes take precedence
+pub fn static_files(dir: &Path) -> Files {
+    Files::new("/", dir)
+        .index_file("index.html")
+        .default_handler(web::to(not_found))
+}
+
+pub async fn run_server(config: ServerConfig) -> std::io::Result<()> {
+    let _ = env_logger::Builder::new()
+        .filter_level(config.log_filter().unwrap_or(log::LevelFilter::Info))
+        .try_init();
+
+    let tls = config.rustls_config()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+    // Budget breaches are posted here when set
+    let notifier = config.budget_webhook_url.clone().map(WebhookNotifier::new);
+    if let Some(notifier) = &notifier {
+        log::info!("Sending budget alerts to {}", notifier.url());
+    }
+
+    if !config.auth.enabled {
+        log::warn!("NIP-98 authentication is disabled");
+    }
+
+    log::info!("Escrows are created on {}", config.network);
+    let rpc = config.bitcoin_rpc.as_ref().map(|rpc| BitcoinRpc::new(rpc, config.network));
+    match &rpc {
+        Some(rpc) => log::info!("Broadcasting escrow transactions through {}", rpc.url()),
+        None => log::warn!("No Bitcoin node configured; escrow broadcasts will fail"),
+    }
+
+    let store = web::Data::new(UserStore::new());
+    let audit = web::Data::new(AuditLog::new());
+    let rpc = web::Data::new(rpc);
+    let network = web::Data::new(config.network);
+    let static_dir = config.static_dir.clone();
+    let jobs = web::Data::new(JobStore::new());
+    let notifier = web::Data::new(notifier);
+    let auth_config = web::Data::new(config.auth.clone());
+    let limits = web::Data::new(RateLimits::new(config.limits.clone(), config.payload_limit));
+    let allowed_origins = config.allowed_origins.clone();
+    let payload_limit = config.payload_limit;
+
+    let mut server = HttpServer::new(move || {
+        let cors = allowed_origins.iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+            .allowed_methods(["GET", "POST", "PUT", "DELETE"])
+            .allowed_headers([header::AUTHORIZATION, header::CONTENT_TYPE])
             .max_age(3600);
 
         App::new()
+            .wrap(from_fn(ratelimit::limit_by_ip))
             .wrap(cors)
-            .route("/", web::get().to(index))
-            .route("/users", web::post().to(create_user))
-            .route("/users/{user_id}/projects", web::post().to(add_project))
-            .route("/users/{user_id}/projection", web::get().to(calculate_projection))
-    })
-    .bind(format!("{}:{}", host, port))?
-    .run()
-    .await
-} 
\ No newline at end of file
+            .wrap(Logger::default())
+            .wrap(from_fn(metrics::track_requests))
+            .app_data(store.clone())
+            .app_data(audit.clone())
+            .app_data(jobs.clone())
+            .app_data(limits.clone())
+            .app_data(notifier.clone())
+            .app_data(auth_config.clone())
+            .app_data(rpc.clone())
+            .app_data(network.clone())
+            .configure(configure)
+            .service(static_files(&static_dir))
+            // Registered after configure so the configured limits replace its defaults
+            .app_data(json_config().limit(payload_limit))
+            .app_data(web::PayloadConfig::new(payload_limit))
+    });
+
+    if let Some(workers) = config.workers {
+        server = server.workers(workers);
+    }
+
+    for addr in &config.bind {
+        server = match &tls {
+            Some(tls) => {
+                log::info!("Starting web server at https://{}", addr);
+                server.bind_rustls_0_23(addr, tls.clone())?
+            }
+            None => {
+                log::info!("Starting web server at http://{}", addr);
+                server.bind(addr)?
+            }
+        };
+    }
+
+    server.run().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::AuthConfig;
+    use actix_web::test;
+
+    macro_rules! test_app {
+        () => {
+            test::init_service(
+                App::new()
+                    .app_data(web::Data::new(UserStore::new()))
+                    .app_data(web::Data::new(AuditLog::new()))
+                    .app_data(web::Data::new(JobStore::new()))
+                    .app_data(web::Data::new(RateLimits::new(Default::default(), 1024)))
+                    .app_data(web::Data::new(None::<WebhookNotifier>))
+                    .app_data(web::Data::new(AuthConfig::disabled()))
+                    .app_data(web::Data::new(None::<BitcoinRpc>))
+                    .app_data(web::Data::new(Network::Bitcoin))
+                    .configure(configure)
+                    .service(static_files(Path::new("static"))),
+            )
+            .await
+        };
+    }
+
+    #[actix_web::test]
+    async fn test_missing_field_reports_envelope() {
+        let app = test_app!();
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .set_json(serde_json::json!({ "user_id": "alice" }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["code"], "invalid_json");
+        assert_eq!(body["field"], "projection_years");
+    }
+
+    #[actix_web::test]
+    async fn test_spending_error_maps_to_field() {
+        let app = test_app!();
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .set_json(serde_json::json!({ "user_id": "alice", "projection_years": 0 }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["code"], "invalid_projection_years");
+        assert_eq!(body["field"], "projection_years");
+    }
+
+    #[actix_web::test]
+    async fn test_existing_user_is_not_replaced() {
+        let app = test_app!();
+        for (years, status) in [(4, StatusCode::OK), (2, StatusCode::CONFLICT)] {
+            let req = test::TestRequest::post()
+                .uri("/users")
+                .set_json(serde_json::json!({ "user_id": "alice", "projection_years": years }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), status);
+            if status == StatusCode::CONFLICT {
+                let body: serde_json::Value = test::read_body_json(resp).await;
+                assert_eq!(body["code"], "user_exists");
+                assert_eq!(body["field"], "user_id");
+            }
+        }
+
+        let req = test::TestRequest::get().uri("/users/alice/history").to_request();
+        let history: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(history.as_array().unwrap().len(), 1);
+        assert_eq!(history[0]["change"]["type"], "created");
+        assert_eq!(history[0]["change"]["model"]["projection_years"], 4);
+    }
+
+    #[actix_web::test]
+    async fn test_unknown_user_is_not_found() {
+        let app = test_app!();
+        let req = test::TestRequest::get().uri("/users/nobody/projection").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["code"], "not_found");
+        assert_eq!(body["details"]["id"], "nobody");
+    }
+
+    #[actix_web::test]
+    async fn test_openapi_paths_are_routed() {
+        let app = test_app!();
+        let req = test::TestRequest::get().uri("/openapi.json").to_request();
+        let spec: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+        let paths = spec["paths"].as_object().unwrap();
+        assert!(paths.contains_key("/users/{user_id}/projection"));
+
+        for (path, operations) in paths {
+            for method in operations.as_object().unwrap().keys() {
+                let uri = path.replace("{user_id}", "alice");
+                let req = test::TestRequest::default()
+                    .method(method.to_uppercase().parse().unwrap())
+                    .uri(&uri)
+                    .to_request();
+                let body = test::call_and_read_body(&app, req).await;
+                // Not every route answers JSON, e.g. /metrics
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+                assert_ne!(body["code"], "route_not_found", "{} {} is documented but not routed", method, path);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_nip98_binds_user_to_pubkey() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(UserStore::new()))
+                .app_data(web::Data::new(AuditLog::new()))
+                .app_data(web::Data::new(RateLimits::new(Default::default(), 1024)))
+                .app_data(web::Data::new(None::<WebhookNotifier>))
+                .app_data(web::Data::new(AuthConfig::default()))
+                .configure(configure),
+        )
+        .await;
+
+        let alice = auth::tests::keypair(1);
+        let alice_id = alice.x_only_public_key().0.to_string();
+        let mallory = auth::tests::keypair(2);
+
+        // No header at all
+        let req = test::TestRequest::get().uri(&format!("/users/{}/projection", alice_id)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+
+        // Alice creates her own model
+        let body = serde_json::to_vec(&serde_json::json!({ "user_id": alice_id, "projection_years": 3 })).unwrap();
+        let header = auth::tests::auth_header(&alice, "POST", "http://localhost:8080/users", Some(&body));
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .insert_header(("Authorization", header))
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload(body)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        // Mallory signs correctly but for someone else's model
+        let url = format!("http://localhost:8080/users/{}/projection", alice_id);
+        let header = auth::tests::auth_header(&mallory, "GET", &url, None);
+        let req = test::TestRequest::get()
+            .uri(&format!("/users/{}/projection", alice_id))
+            .insert_header(("Authorization", header))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+
+        // A header signed for another method is rejected
+        let header = auth::tests::auth_header(&alice, "POST", &url, None);
+        let req = test::TestRequest::get()
+            .uri(&format!("/users/{}/projection", alice_id))
+            .insert_header(("Authorization", header))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+
+        let header = auth::tests::auth_header(&alice, "GET", &url, None);
+        let req = test::TestRequest::get()
+            .uri(&format!("/users/{}/projection", alice_id))
+            .insert_header(("Authorization", header))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        // Her npub names the same model as her hex key
+        let npub = alice_id.parse::<crate::nostr::NostrPublicKey>().unwrap().to_npub();
+        let url = format!("http://localhost:8080/users/{}/projection", npub);
+        let header = auth::tests::auth_header(&alice, "GET", &url, None);
+        let req = test::TestRequest::get()
+            .uri(&format!("/users/{}/projection", npub))
+            .insert_header(("Authorization", header))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        // Escrow routes need a signer too
+        for uri in ["/create_escrow_tx", "/release_escrow_tx", "/broadcast_escrow_tx"] {
+            let req = test::TestRequest::post().uri(uri).set_json(serde_json::json!({})).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED, "{}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_escrow_is_created_by_a_party_and_audited_as_them() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(AuditLog::new()))
+                .app_data(web::Data::new(RateLimits::new(Default::default(), 1024)))
+                .app_data(web::Data::new(AuthConfig::default()))
+                .app_data(web::Data::new(None::<BitcoinRpc>))
+                .app_data(web::Data::new(Network::Bitcoin))
+                .configure(configure),
+        )
+        .await;
+
+        let alice = auth::tests::keypair(1);
+        let alice_id = alice.x_only_public_key().0.to_string();
+        let mallory = auth::tests::keypair(3);
+        let body = serde_json::to_vec(&serde_json::json!({
+            "escrow_input": {
+                "npub_1": alice_id,
+                "npub_2": auth::tests::keypair(2).x_only_public_key().0.to_string(),
+                "escrow_script": "A"
+            },
+            "utxos": [{
+                "txid": "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+                "vout": 0,
+                "script_pubkey": "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+                "value": 10000
+            }],
+            "fee_rate": 1.5,
+            "amount": 9000,
+            "change_address": "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"
+        })).unwrap();
+        let create = |signer, body: &[u8]| {
+            let header = auth::tests::auth_header(signer, "POST", "http://localhost:8080/create_escrow_tx", Some(body));
+            test::TestRequest::post()
+                .uri("/create_escrow_tx")
+                .insert_header(("Authorization", header))
+                .insert_header(("Content-Type", "application/json"))
+                .set_payload(body.to_vec())
+                .to_request()
+        };
+
+        // Mallory may not file an escrow under Alice's key, and learns nothing about its funding
+        let resp = test::call_service(&app, create(&mallory, &body)).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let unfundable = String::from_utf8(body.clone()).unwrap().replace("\"amount\":9000", "\"amount\":90000");
+        assert_ne!(unfundable.as_bytes(), body.as_slice());
+        let resp = test::call_service(&app, create(&mallory, unfundable.as_bytes())).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = test::call_service(&app, create(&alice, unfundable.as_bytes())).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let resp = test::call_service(&app, create(&alice, &body)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let created: serde_json::Value = test::read_body_json(resp).await;
+
+        let post = |signer, uri: &str, body: serde_json::Value| {
+            let body = serde_json::to_vec(&body).unwrap();
+            let header = auth::tests::auth_header(signer, "POST", &format!("http://localhost:8080{}", uri), Some(&body));
+            test::TestRequest::post()
+                .uri(uri)
+                .insert_header(("Authorization", header))
+                .insert_header(("Content-Type", "application/json"))
+                .set_payload(body)
+                .to_request()
+        };
+        let escrow_input: serde_json::Value = serde_json::from_slice::<serde_json::Value>(&body).unwrap()["escrow_input"].clone();
+
+        // Only a party may release the escrow or broadcast its transactions
+        let release = serde_json::json!({
+            "escrow_input": escrow_input,
+            "escrow_txid": created["txid"],
+            "escrow_vout": 0,
+            "amount": 9000,
+            "path": "cooperative",
+            "payouts": [{ "address": "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4" }],
+            "fee": 500
+        });
+        let resp = test::call_service(&app, post(&mallory, "/release_escrow_tx", release.clone())).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = test::call_service(&app, post(&alice, "/release_escrow_tx", release)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let released: serde_json::Value = test::read_body_json(resp).await;
+
+        // The funding transaction is matched to the escrow created for it
+        let funding = serde_json::json!({ "psbts": [created["psbt"]] });
+        let resp = test::call_service(&app, post(&mallory, "/broadcast_escrow_tx", funding.clone())).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        // Alice gets as far as the missing signatures
+        let resp = test::call_service(&app, post(&alice, "/broadcast_escrow_tx", funding)).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let spend = serde_json::json!({
+            "psbts": [released["psbt"]],
+            "release": { "escrow_input": escrow_input, "path": "cooperative" }
+        });
+        let resp = test::call_service(&app, post(&mallory, "/broadcast_escrow_tx", spend.clone())).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = test::call_service(&app, post(&alice, "/broadcast_escrow_tx", spend)).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let url = format!("http://localhost:8080/users/{}/audit", alice_id);
+        let header = auth::tests::auth_header(&alice, "GET", &url, None);
+        let req = test::TestRequest::get()
+            .uri(&format!("/users/{}/audit", alice_id))
+            .insert_header(("Authorization", header))
+            .to_request();
+        let entries: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(entries.as_array().unwrap().len(), 1);
+        assert_eq!(entries[0]["actor"], alice_id.as_str());
+    }
+
+    #[actix_web::test]
+    async fn test_configured_payload_limit_applies() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(UserStore::new()))
+                .app_data(web::Data::new(AuditLog::new()))
+                .app_data(web::Data::new(RateLimits::new(Default::default(), 1024)))
+                .app_data(web::Data::new(AuthConfig::disabled()))
+                .configure(configure)
+                .app_data(json_config().limit(16)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .set_json(serde_json::json!({ "user_id": "a-rather-long-user-id", "projection_years": 3 }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["code"], "payload_too_large");
+    }
+
+    #[actix_web::test]
+    async fn test_limits_return_structured_errors() {
+        let limits = crate::ratelimit::LimitsConfig {
+            requests_per_minute_per_ip: 1,
+            burst: 3,
+            max_projects: 1,
+            ..Default::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .wrap(from_fn(ratelimit::limit_by_ip))
+                .app_data(web::Data::new(UserStore::new()))
+                .app_data(web::Data::new(AuditLog::new()))
+                .app_data(web::Data::new(RateLimits::new(limits, 1024)))
+                .app_data(web::Data::new(AuthConfig::disabled()))
+                .configure(configure),
+        )
+        .await;
+        let peer: std::net::SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let project = serde_json::json!({ "project_name": "A", "daily_spend": 1.0, "growth_rate": 0.0, "growth_type": "flat" });
+
+        let req = test::TestRequest::post().uri("/users").peer_addr(peer)
+            .set_json(serde_json::json!({ "user_id": "alice", "projection_years": 3 }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let req = test::TestRequest::post().uri("/users/alice/projects").peer_addr(peer).set_json(&project).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let req = test::TestRequest::post().uri("/users/alice/projects").peer_addr(peer).set_json(&project).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["code"], "limit_exceeded");
+
+        let req = test::TestRequest::get().uri("/openapi.json").peer_addr(peer).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(resp.headers().contains_key(header::RETRY_AFTER));
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["code"], "rate_limited");
+
+        // Another client is unaffected
+        let req = test::TestRequest::get().uri("/openapi.json").peer_addr("10.0.0.2:5000".parse().unwrap()).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn test_inline_projection_query() {
+        let app = test_app!();
+        let model = serde_json::json!({
+            "user_id": "what-if",
+            "projection_years": 2,
+            "projects": [
+                { "project_name": "Rent", "daily_spend": 10.0, "growth_rate": 0.0, "growth_type": "Flat" }
+            ],
+            "scenarios": [{ "name": "double", "spend_multiplier": 2.0 }]
+        });
+
+        let req = test::TestRequest::post()
+            .uri("/projection?granularity=quarterly&scenario=double&horizon=3")
+            .set_json(&model)
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["periods"].as_array().unwrap().len(), 12);
+        assert_eq!(body["yearly_totals"][2]["total"], 7300.0);
+        assert_eq!(body["currency"], "USD");
+
+        let req = test::TestRequest::post()
+            .uri("/projection?format=csv&currency=eur&fx_rate=0.5")
+            .set_json(&model)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(header::CONTENT_TYPE).unwrap(), "text/csv; charset=utf-8");
+        let csv = test::read_body(resp).await;
+        assert_eq!(csv, "year,period,total,Rent\n0,0,1825,1825\n1,0,1825,1825\n");
+
+        for (query, field) in [
+            ("currency=eur", "fx_rate"),
+            ("currency=euros&fx_rate=1", "currency"),
+            ("horizon=0", "horizon"),
+            ("scenario=missing", "scenario"),
+        ] {
+            let req = test::TestRequest::post()
+                .uri(&format!("/projection?{}", query))
+                .set_json(&model)
+                .to_request();
+            let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(body["field"], field, "{}", query);
+        }
+
+        let req = test::TestRequest::post()
+            .uri("/projection?granularity=weekly")
+            .set_json(&model)
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["code"], "invalid_query");
+    }
+
+    #[actix_web::test]
+    async fn test_job_streams_progress_events() {
+        let app = test_app!();
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .set_json(serde_json::json!({ "user_id": "alice", "projection_years": 2 }))
+            .to_request();
+        test::call_service(&app, req).await;
+
+        let req = test::TestRequest::post()
+            .uri("/users/alice/jobs?horizon=3")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        let events_url = resp.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string();
+        let job: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(job["total"], 3);
+
+        let req = test::TestRequest::get().uri(&events_url).to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        let events: Vec<&str> = body.lines().filter_map(|line| line.strip_prefix("event: ")).collect();
+        assert_eq!(events, ["progress", "progress", "progress", "completed"]);
+
+        // Resuming after the third event only replays the last one
+        let req = test::TestRequest::get().uri(&events_url).insert_header(("Last-Event-ID", "2")).to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert!(std::str::from_utf8(&body).unwrap().starts_with("id: 3\nevent: completed\n"));
+
+        let req = test::TestRequest::delete().uri(&format!("/users/alice/jobs/{}", job["job_id"].as_str().unwrap())).to_request();
+        let snapshot: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(snapshot["status"], "completed");
+
+        let req = test::TestRequest::get().uri("/users/bob/jobs/job-1").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_escrow_page_and_endpoint_are_served() {
+        let app = test_app!();
+
+        let req = test::TestRequest::get().uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let page = test::read_body(resp).await;
+        assert!(std::str::from_utf8(&page).unwrap().contains("signedPost('create_escrow_tx'"));
+
+        let mut input = serde_json::json!({
+            "escrow_input": {
+                "npub_1": "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+                "npub_2": "c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5",
+                "npub_arbitrator": "nope",
+                "escrow_script": "B"
+            },
+            "utxos": [{
+                "txid": "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+                "vout": 0,
+                "script_pubkey": "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+                "value": 10000
+            }],
+            "fee_rate": 1.5,
+            "amount": 20000
+        });
+        let req = test::TestRequest::post().uri("/create_escrow_tx").set_json(&input).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["field"], "escrow_input.npub_arbitrator");
+
+        input["escrow_input"]["npub_arbitrator"] = "f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9".into();
+        let req = test::TestRequest::post().uri("/create_escrow_tx").set_json(&input).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["code"], "insufficient_funds");
+
+        input["amount"] = 9000.into();
+        let req = test::TestRequest::post().uri("/create_escrow_tx").set_json(&input).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        // 10000 sats in, 9000 to the escrow: without a change address the rest would be burned
+        assert_eq!((body["code"].as_str(), body["field"].as_str()), (Some("change_required"), Some("change_address")));
+
+        input["change_address"] = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4".into();
+        let req = test::TestRequest::post().uri("/create_escrow_tx").set_json(&input).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["fee"].as_u64().unwrap() + body["change"].as_u64().unwrap(), 1000);
+        assert_eq!(body["network"], "bitcoin");
+        let psbt = body["psbt"].as_str().unwrap();
+        assert!(psbt.starts_with("cHNidP8B"));
+
+        // Nobody has signed the funding input yet
+        let req = test::TestRequest::post().uri("/broadcast_escrow_tx")
+            .set_json(serde_json::json!({ "psbts": [psbt, psbt] }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!((body["code"].as_str(), body["details"]["input"].as_u64()), (Some("incomplete_psbt"), Some(0)));
+
+        let req = test::TestRequest::post().uri("/broadcast_escrow_tx")
+            .set_json(serde_json::json!({ "psbts": [psbt, "garbage"] }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["field"], "psbts[1]");
+
+        let req = test::TestRequest::get().uri("/missing.js").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["code"], "route_not_found");
+    }
+
+    #[actix_web::test]
+    async fn test_miniscript_compile_endpoint() {
+        let app = test_app!();
+
+        let hash = "79c0198ac27c52e56323db0f5ab0ff7fa090257263049748793babd18c07e9c7";
+        let req = test::TestRequest::post().uri("/miniscript/compile")
+            .set_json(serde_json::json!({ "miniscript": format!("and_v(v:after(4326153),sha256({}))", hash) }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["address"], "bc1q5scw556sxhqm0x8h56ejv9z9v0a9kwl5xjgu6kahtlwmafz8p9uq2pfr68");
+        assert_eq!(body["sane"], false);
+        assert_eq!(body["spending_paths"][0]["conditions"][1]["type"], "after");
+
+        let req = test::TestRequest::post().uri("/miniscript/compile")
+            .set_json(serde_json::json!({ "descriptor": format!("tr(,and_v(v:after(4326153),sha256({})))", hash) }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!((body["code"].as_str(), body["field"].as_str()), (Some("missing_internal_key"), Some("descriptor")));
+    }
+
+    #[actix_web::test]
+    async fn test_health_readiness_and_metrics() {
+        let app = test::init_service(
+            App::new()
+                .wrap(from_fn(metrics::track_requests))
+                .app_data(web::Data::new(UserStore::new()))
+                .app_data(web::Data::new(RateLimits::new(Default::default(), 1024)))
+                .app_data(web::Data::new(AuthConfig::disabled()))
+                .app_data(web::Data::new(None::<BitcoinRpc>))
+                .app_data(web::Data::new(Network::Signet))
+                .configure(configure),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/healthz").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["network"], "signet");
+
+        let req = test::TestRequest::get().uri("/readyz").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["status"], "ready");
+        assert_eq!(body["bitcoin_rpc"]["detail"], "not configured");
+
+        let req = test::TestRequest::get().uri("/metrics").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let text = std::str::from_utf8(&body).unwrap();
+        assert!(text.contains(r#"http_requests_total{method="GET",route="/healthz",status="200"}"#));
+    }
+
+    #[actix_web::test]
+    async fn test_projection_charts() {
+        let app = test_app!();
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .set_json(serde_json::json!({ "user_id": "alice", "projection_years": 4 }))
+            .to_request();
+        test::call_service(&app, req).await;
+        let req = test::TestRequest::post()
+            .uri("/users/alice/projects")
+            .set_json(serde_json::json!({ "project_name": "Rent", "daily_spend": 10.0, "growth_rate": 0.05, "growth_type": "compound" }))
+            .to_request();
+        test::call_service(&app, req).await;
+
+        let req = test::TestRequest::get().uri("/users/alice/projection.png?granularity=monthly&kind=stacked_bar").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(header::CONTENT_TYPE).unwrap(), "image/png");
+        let png = test::read_body(resp).await;
+        assert_eq!(image::load_from_memory(&png).unwrap().width(), 800);
+
+        let req = test::TestRequest::get().uri("/users/alice/projection.svg?width=300&height=200").to_request();
+        let svg = test::call_and_read_body(&app, req).await;
+        assert!(std::str::from_utf8(&svg).unwrap().contains("<polyline"));
+
+        let req = test::TestRequest::get().uri("/users/alice/projection.svg?width=5000").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["field"], "width");
+    }
+
+    #[actix_web::test]
+    async fn test_changes_are_audited_in_a_verifiable_chain() {
+        let app = test_app!();
+        let requests = [
+            test::TestRequest::post().uri("/users")
+                .set_json(serde_json::json!({ "user_id": "alice", "projection_years": 4 })),
+            test::TestRequest::put().uri("/users/alice/scenarios")
+                .set_json(serde_json::json!({ "name": "lean", "growth_rate_delta": -0.01 })),
+            test::TestRequest::put().uri("/users/alice/scenarios")
+                .set_json(serde_json::json!({ "name": "lean", "growth_rate_delta": -0.02 })),
+            test::TestRequest::delete().uri("/users/alice/scenarios/lean"),
+            test::TestRequest::post().uri("/users")
+                .set_json(serde_json::json!({ "user_id": "bob", "projection_years": 2 })),
+        ];
+        for req in requests {
+            assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+        }
+
+        let req = test::TestRequest::get().uri("/users/alice/audit?resource=scenario").to_request();
+        let entries: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+        let actions: Vec<&str> = entries.iter().map(|entry| entry["action"].as_str().unwrap()).collect();
+        assert_eq!(actions, ["create", "update", "delete"]);
+        assert_eq!(entries[1]["changes"], serde_json::json!([
+            { "path": "growth_rate_delta", "before": -0.01, "after": -0.02 },
+        ]));
+        assert_eq!(entries[1]["prev_hash"], entries[0]["hash"]);
+
+        let req = test::TestRequest::get().uri("/users/alice/audit?action=delete&until=2000-01-01T00:00:00Z").to_request();
+        let entries: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+        assert!(entries.is_empty());
+
+        let req = test::TestRequest::get().uri("/audit/verify").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["valid"], true);
+        assert_eq!(body["entries"], 5);
+    }
+
+    #[actix_web::test]
+    async fn test_projects_and_diffs_earlier_versions() {
+        let app = test_app!();
+        let requests = [
+            test::TestRequest::post().uri("/users")
+                .set_json(serde_json::json!({ "user_id": "alice", "projection_years": 2 })),
+            test::TestRequest::post().uri("/users/alice/projects")
+                .set_json(serde_json::json!({ "project_name": "Rent", "daily_spend": 10.0, "growth_rate": 0.0, "growth_type": "flat" })),
+            test::TestRequest::post().uri("/users/alice/projects")
+                .set_json(serde_json::json!({ "project_name": "Food", "daily_spend": 5.0, "growth_rate": 0.0, "growth_type": "flat" })),
+        ];
+        for req in requests {
+            assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+        }
+
+        let req = test::TestRequest::get().uri("/users/alice/history").to_request();
+        let events: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+        let types: Vec<&str> = events.iter().map(|event| event["change"]["type"].as_str().unwrap()).collect();
+        assert_eq!(types, ["created", "project_added", "project_added"]);
+
+        let total = |uri: &'static str| {
+            let app = &app;
+            async move {
+                let req = test::TestRequest::get().uri(uri).to_request();
+                let body: serde_json::Value = test::call_and_read_body_json(app, req).await;
+                body["yearly_totals"][0]["total"].as_f64().unwrap()
+            }
+        };
+        assert_eq!(total("/users/alice/projection?version=2").await, 3650.0);
+        assert_eq!(total("/users/alice/projection").await, 5475.0);
+        assert_eq!(total("/users/alice/projection?as_of=2999-01-01").await, 5475.0);
+
+        let req = test::TestRequest::get().uri("/users/alice/diff?from=2&to=3").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["changes"][0]["path"], "projects");
+        assert_eq!(body["changes"][0]["after"][1]["project_name"], "Food");
+
+        for (uri, status, field) in [
+            ("/users/alice/projection?version=9", StatusCode::NOT_FOUND, "version"),
+            ("/users/alice/projection?as_of=2000-01-01", StatusCode::NOT_FOUND, "as_of"),
+            ("/users/alice/projection?version=1&as_of=2999-01-01", StatusCode::BAD_REQUEST, "as_of"),
+            ("/users/alice/diff?from=1&to=4", StatusCode::NOT_FOUND, "to"),
+        ] {
+            let resp = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(resp.status(), status, "{}", uri);
+            let body: serde_json::Value = test::read_body_json(resp).await;
+            assert_eq!(body["field"], field, "{}", uri);
+        }
+    }
+}